@@ -0,0 +1,110 @@
+use {
+    crate::{
+        SimulateAdjudicationArgs, buffer,
+        checksum::ChecksumReader,
+        error::{Context, Result},
+    },
+    std::{fs::OpenOptions, io::Seek, path::Path},
+    stoatformat::{Outcome, stoatpack::Stoatpack},
+};
+
+#[derive(Default)]
+pub struct AdjudicationStats {
+    pub games: usize,
+    pub resigned: usize,
+    pub drawn: usize,
+    pub plies_saved: usize,
+    pub disagreements: usize,
+}
+
+pub fn simulate(
+    path: &Path,
+    args: &SimulateAdjudicationArgs,
+    stats: &mut AdjudicationStats,
+) -> Result<()> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+    let len = file.metadata()?.len();
+    let mut index = 0;
+    let mut games = 0;
+    let mut cut = 0;
+
+    while reader.stream_position()? < len && args.common.within_limit(index) {
+        let offset = reader.stream_position()?;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+        index += 1;
+
+        if args.common.skipped(index - 1) {
+            continue;
+        }
+
+        games += 1;
+
+        if let Some((ply, wdl)) = adjudicate(&game, args) {
+            cut += 1;
+            stats.plies_saved += game.moves.len() - ply;
+
+            match wdl {
+                Outcome::Draw => stats.drawn += 1,
+                _ => stats.resigned += 1,
+            }
+
+            if wdl != game.wdl {
+                stats.disagreements += 1;
+            }
+        }
+    }
+
+    stats.games += games;
+
+    println!(
+        "  OK  : {}, {} games, {} adjudicated",
+        path.display(),
+        games,
+        cut
+    );
+
+    Ok(())
+}
+
+/// Returns the ply after which the game would have been adjudicated and the resulting outcome.
+/// A resignation needs `--resign-plies` consecutive scores beyond `--resign-eval` with the same
+/// sign; a draw needs `--draw-plies` consecutive scores within `--draw-eval` from
+/// `--draw-min-ply` on.
+fn adjudicate(game: &Stoatpack, args: &SimulateAdjudicationArgs) -> Option<(usize, Outcome)> {
+    let mut resign_run = 0;
+    let mut draw_run = 0;
+
+    for (ply, &(_, score)) in game.moves.iter().enumerate() {
+        resign_run = match resign_run {
+            run if score >= args.resign_eval => run.max(0) + 1,
+            run if score <= -args.resign_eval => run.min(0) - 1,
+            _ => 0,
+        };
+
+        draw_run = if ply >= args.draw_min_ply && score.abs() <= args.draw_eval {
+            draw_run + 1
+        } else {
+            0
+        };
+
+        // Stopping on the final recorded move does not shorten the game
+        if ply + 1 == game.moves.len() {
+            break;
+        }
+
+        if resign_run >= args.resign_plies as i32 {
+            return Some((ply + 1, Outcome::SenteWin));
+        }
+
+        if resign_run <= -(args.resign_plies as i32) {
+            return Some((ply + 1, Outcome::SenteLoss));
+        }
+
+        if draw_run >= args.draw_plies {
+            return Some((ply + 1, Outcome::Draw));
+        }
+    }
+
+    None
+}
@@ -0,0 +1,294 @@
+use {
+    crate::{
+        TeeReader, buffer,
+        checksum::ChecksumReader,
+        error::{Context, Error, Result},
+    },
+    serde_json::{Value, json},
+    sha2::{Digest, Sha256},
+    std::{
+        collections::HashMap,
+        fs::{File, OpenOptions, create_dir_all},
+        hash::{DefaultHasher, Hash, Hasher},
+        io::{Read, copy},
+        path::{Path, PathBuf},
+    },
+    stoatformat::{Outcome, stoatpack::Stoatpack},
+};
+
+pub const MANIFEST: &str = "manifest.json";
+const BUNDLE_SUFFIXES: [&str; 3] = [".tar", ".tar.zst", ".zip"];
+const ZSTD_LEVEL: i32 = 19;
+
+pub struct Entry {
+    path: PathBuf,
+    name: String,
+    bytes: u64,
+    sha256: String,
+    games: usize,
+    positions: usize,
+    black_wins: usize,
+    white_wins: usize,
+    draws: usize,
+}
+
+impl Entry {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "bytes": self.bytes,
+            "sha256": self.sha256,
+            "games": self.games,
+            "positions": self.positions,
+            "black_wins": self.black_wins,
+            "white_wins": self.white_wins,
+            "draws": self.draws,
+        })
+    }
+}
+
+pub fn is_bundle(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|name| BUNDLE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)))
+}
+
+/// Streams every `.spk` member of a tar, tar.zst or zip bundle through `f`, in archive order.
+/// Members are named `<bundle>/<member>`.
+pub fn for_each_member<F>(path: &Path, mut f: F) -> Result<()>
+where
+    F: FnMut(PathBuf, &mut dyn Read, u64) -> Result<()>,
+{
+    if path.extension().and_then(|ext| ext.to_str()) == Some("zip") {
+        let mut zip = zip::ZipArchive::new(File::open(path)?).map_err(Error::other)?;
+
+        for index in 0..zip.len() {
+            let mut member = zip.by_index(index).map_err(Error::other)?;
+
+            if member.is_file() && member.name().ends_with(".spk") {
+                let size = member.size();
+                f(path.join(member.name()), &mut member, size)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    let file = File::open(path)?;
+    let input: Box<dyn Read> = if path.extension().and_then(|ext| ext.to_str()) == Some("zst") {
+        Box::new(zstd::Decoder::new(file)?)
+    } else {
+        Box::new(file)
+    };
+    let mut archive = tar::Archive::new(input);
+
+    for member in archive.entries()? {
+        let mut member = member?;
+        let name = member.path()?.into_owned();
+
+        if name.extension().and_then(|ext| ext.to_str()) == Some("spk") {
+            let size = member.size();
+            f(path.join(name), &mut member, size)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies every `.spk` member of a bundle into `cache_dir` for commands that need plain files.
+pub fn extract(path: &Path, cache_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+
+    let prefix = hasher.finish();
+    let mut members = Vec::new();
+
+    create_dir_all(cache_dir)?;
+    for_each_member(path, |member, reader, _| {
+        let output = cache_dir.join(format!(
+            "{:016x}-{}",
+            prefix,
+            member.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        copy(reader, &mut buffer::writer(File::create(&output)?))?;
+        println!("Unpack: {} -> {}", member.display(), output.display());
+        members.push(output);
+
+        Ok(())
+    })?;
+
+    Ok(members)
+}
+
+/// Counts and hashes one input file in a single pass.
+pub fn scan(path: &Path) -> Result<Entry> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let len = file.metadata()?.len();
+    let reader = buffer::reader(ChecksumReader::new(&file, path)?);
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::other(format!("Not a file: {}", path.display())))?
+        .to_string_lossy()
+        .into_owned();
+
+    scan_reader(reader, len, path.to_path_buf(), name)
+}
+
+fn scan_reader<R: Read>(reader: R, len: u64, path: PathBuf, name: String) -> Result<Entry> {
+    let mut reader = TeeReader::new(reader);
+    let mut hasher = Sha256::new();
+    let mut entry = Entry {
+        path,
+        name,
+        bytes: len,
+        sha256: String::new(),
+        games: 0,
+        positions: 0,
+        black_wins: 0,
+        white_wins: 0,
+        draws: 0,
+    };
+
+    while reader.position < len {
+        let offset = reader.position;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(&entry.path, offset)?;
+        hasher.update(reader.take_captured());
+
+        match game.wdl {
+            Outcome::SenteWin => entry.black_wins += 1,
+            Outcome::SenteLoss => entry.white_wins += 1,
+            Outcome::Draw => entry.draws += 1,
+        }
+
+        entry.games += 1;
+        entry.positions += game.moves.len() + 1;
+    }
+
+    entry.sha256 = format!("{:x}", hasher.finalize());
+
+    Ok(entry)
+}
+
+/// Writes `manifest.json`, a `SHA256SUMS` listing and every input into a zstd-compressed tar.
+pub fn write(output: &Path, entries: &[Entry]) -> Result<()> {
+    let mut names = HashMap::new();
+
+    for entry in entries {
+        if let Some(other) = names.insert(&entry.name, &entry.path) {
+            return Err(Error::other(format!(
+                "Duplicate file name in archive: {} and {}",
+                other.display(),
+                entry.path.display()
+            )));
+        }
+    }
+
+    let manifest = json!({
+        "files": entries.iter().map(Entry::to_json).collect::<Vec<_>>(),
+        "games": entries.iter().map(|entry| entry.games).sum::<usize>(),
+        "positions": entries.iter().map(|entry| entry.positions).sum::<usize>(),
+    });
+    let sums = entries
+        .iter()
+        .map(|entry| format!("{}  {}\n", entry.sha256, entry.name))
+        .collect::<String>();
+    let encoder = zstd::Encoder::new(File::create(output)?, ZSTD_LEVEL)?;
+    let mut builder = tar::Builder::new(encoder);
+
+    append_bytes(
+        &mut builder,
+        MANIFEST,
+        serde_json::to_string_pretty(&manifest)
+            .map_err(Error::other)?
+            .as_bytes(),
+    )?;
+    append_bytes(&mut builder, "SHA256SUMS", sums.as_bytes())?;
+
+    for entry in entries {
+        builder.append_path_with_name(&entry.path, &entry.name)?;
+    }
+
+    builder.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+fn append_bytes<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    Ok(builder.append_data(&mut header, name, data)?)
+}
+
+/// Re-hashes and re-counts every file in an archive against its manifest.
+/// Returns the number of files checked and the number that failed.
+pub fn verify(path: &Path) -> Result<(usize, usize)> {
+    let decoder = zstd::Decoder::new(File::open(path)?)?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut expected = HashMap::new();
+    let mut checked = 0;
+    let mut failed = 0;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+
+        if name == MANIFEST {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+
+            let manifest = serde_json::from_str::<Value>(&text).map_err(Error::other)?;
+
+            for file in manifest["files"].as_array().into_iter().flatten() {
+                if let Some(name) = file["name"].as_str() {
+                    expected.insert(name.to_string(), file.clone());
+                }
+            }
+
+            continue;
+        }
+
+        if !name.ends_with(".spk") {
+            continue;
+        }
+
+        let size = entry.size();
+        let scanned = scan_reader(&mut entry, size, PathBuf::from(&name), name.clone());
+        checked += 1;
+
+        // A corrupt member fails on its own; the rest of the archive is still checked
+        let actual = match scanned {
+            Ok(scanned) => scanned.to_json(),
+            Err(error) => {
+                failed += 1;
+                expected.remove(&name);
+                println!("Broken: {}, {}", name, error);
+                continue;
+            }
+        };
+
+        match expected.remove(&name) {
+            Some(manifest) if manifest == actual => println!("  OK  : {}", name),
+            Some(_) => {
+                failed += 1;
+                println!("Mismatch: {}, contents differ from the manifest", name);
+            }
+            None => {
+                failed += 1;
+                println!("Mismatch: {}, not listed in the manifest", name);
+            }
+        }
+    }
+
+    for name in expected.keys() {
+        failed += 1;
+        println!("Missing: {}", name);
+    }
+
+    Ok((checked, failed))
+}
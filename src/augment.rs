@@ -0,0 +1,316 @@
+use {
+    crate::{
+        AugmentArgs, buffer,
+        checksum::ChecksumReader,
+        error::{Context, Error, Result},
+        replace_file,
+    },
+    std::{fs::OpenOptions, io::Seek, path::Path},
+    stoatformat::{
+        Outcome,
+        shogi::{core::Move, position::Position},
+        stoatpack::Stoatpack,
+    },
+};
+
+pub fn augment(path: &Path, args: &AugmentArgs) -> Result<(usize, usize)> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+    let len = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    let mut records = 0;
+    let mut added = 0;
+
+    while reader.stream_position()? < len {
+        let offset = reader.stream_position()?;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+        records += 1;
+
+        if !args.replace {
+            game.serialise(&mut buffer)?;
+        }
+
+        if args.mirror {
+            mirror(&game)?.serialise(&mut buffer)?;
+            added += 1;
+        }
+
+        if args.color_flip {
+            color_flip(&game)?.serialise(&mut buffer)?;
+            added += 1;
+        }
+    }
+
+    // Only possible with --replace and no transform, which would wipe the file
+    if buffer.is_empty() && records != 0 {
+        return Err(Error::other(
+            "augment produced no games, leaving the file untouched",
+        ));
+    }
+
+    replace_file(path, &buffer)?;
+
+    println!(
+        "Augment: {}, {} records, {} games added",
+        path.display(),
+        records,
+        added
+    );
+
+    Ok((records, added))
+}
+
+/// Reflects a game across the central file: each rank is reversed and file `f` becomes
+/// `10 - f`.
+pub fn mirror(game: &Stoatpack) -> Result<Stoatpack> {
+    transform(game, mirror_sfen, mirror_usi, |score| score, |wdl| wdl)
+}
+
+/// Produces gote's view of a game: the board is rotated 180 degrees, colours and hands
+/// are swapped, and scores and the outcome are negated.
+pub fn color_flip(game: &Stoatpack) -> Result<Stoatpack> {
+    transform(
+        game,
+        flip_sfen,
+        flip_usi,
+        |score| -score,
+        |wdl| match wdl {
+            Outcome::SenteWin => Outcome::SenteLoss,
+            Outcome::SenteLoss => Outcome::SenteWin,
+            Outcome::Draw => Outcome::Draw,
+        },
+    )
+}
+
+// Positions and moves go through their SFEN/USI forms, where both symmetries are plain
+// text substitutions.
+fn transform(
+    game: &Stoatpack,
+    sfen: fn(&str) -> String,
+    usi: fn(&str) -> String,
+    score: fn(i16) -> i16,
+    wdl: fn(Outcome) -> Outcome,
+) -> Result<Stoatpack> {
+    let startpos = game.startpos.sfen();
+    let moves = game
+        .moves
+        .iter()
+        .map(|(mv, mv_score)| {
+            let mv = mv.to_string();
+            let transformed = usi(&mv)
+                .parse::<Move>()
+                .map_err(|_| Error::other(format!("failed to transform move {}", mv)))?;
+
+            Ok((transformed, score(*mv_score)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Stoatpack {
+        startpos: Position::from_sfen(&sfen(&startpos))
+            .map_err(|_| Error::other(format!("failed to transform position {}", startpos)))?,
+        wdl: wdl(game.wdl),
+        moves,
+    })
+}
+
+fn mirror_sfen(sfen: &str) -> String {
+    let (board, rest) = sfen.split_once(' ').unwrap_or((sfen, ""));
+    let ranks = board
+        .split('/')
+        .map(|rank| board_tokens(rank).into_iter().rev().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    format!("{} {}", ranks, rest)
+}
+
+fn flip_sfen(sfen: &str) -> String {
+    let mut fields = sfen.split(' ');
+    let board = fields.next().unwrap_or("");
+    let stm = fields.next().unwrap_or("b");
+    let hand = fields.next().unwrap_or("-");
+    let rest = fields.collect::<Vec<_>>().join(" ");
+
+    let board = board
+        .split('/')
+        .rev()
+        .map(|rank| {
+            board_tokens(rank)
+                .into_iter()
+                .rev()
+                .map(|token| swap_case(&token))
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    let stm = if stm == "b" { "w" } else { "b" };
+    let hand = if hand == "-" {
+        hand.to_string()
+    } else {
+        let mut tokens = Vec::new();
+        let mut count = String::new();
+
+        for c in hand.chars() {
+            if c.is_ascii_digit() {
+                count.push(c);
+            } else {
+                tokens.push(format!("{}{}", count, swap_case(&c.to_string())));
+                count.clear();
+            }
+        }
+
+        // SFEN lists sente's hand first
+        tokens.sort_by_key(|token| token.ends_with(|c: char| c.is_ascii_lowercase()));
+        tokens.concat()
+    };
+
+    format!("{} {} {} {}", board, stm, hand, rest)
+}
+
+fn board_tokens(rank: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut promoted = false;
+
+    for c in rank.chars() {
+        if c == '+' {
+            promoted = true;
+        } else {
+            tokens.push(if promoted {
+                format!("+{}", c)
+            } else {
+                c.to_string()
+            });
+            promoted = false;
+        }
+    }
+
+    tokens
+}
+
+fn swap_case(token: &str) -> String {
+    token
+        .chars()
+        .map(|c| {
+            if c.is_ascii_uppercase() {
+                c.to_ascii_lowercase()
+            } else {
+                c.to_ascii_uppercase()
+            }
+        })
+        .collect()
+}
+
+fn mirror_usi(usi: &str) -> String {
+    usi.chars()
+        .map(|c| match c.to_digit(10) {
+            Some(file @ 1..=9) => char::from_digit(10 - file, 10).unwrap(),
+            _ => c,
+        })
+        .collect()
+}
+
+fn flip_usi(usi: &str) -> String {
+    let (piece, squares) = usi.split_once('*').unwrap_or(("", usi));
+    let squares = mirror_usi(squares)
+        .chars()
+        .map(|c| match c {
+            'a'..='i' => (b'a' + b'i' - c as u8) as char,
+            _ => c,
+        })
+        .collect::<String>();
+
+    if piece.is_empty() {
+        squares
+    } else {
+        format!("{}*{}", piece, squares)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STARTPOS: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+    const MIDGAME: &str =
+        "ln1g3nl/1r1sk1gs1/p1pp1p1pp/4p1p2/1p7/2P3P2/PP1PPP+b1P/3K3R1/LNSG1GSNL w B2Pp 24";
+
+    #[test]
+    fn mirrors_positions() {
+        assert_eq!(
+            mirror_sfen(STARTPOS),
+            "lnsgkgsnl/1b5r1/ppppppppp/9/9/9/PPPPPPPPP/1R5B1/LNSGKGSNL b - 1"
+        );
+        assert_eq!(
+            mirror_sfen(MIDGAME),
+            "ln3g1nl/1sg1ks1r1/pp1p1pp1p/2p1p4/7p1/2P3P2/P1+bPPP1PP/1R3K3/LNSG1GSNL w B2Pp 24"
+        );
+    }
+
+    #[test]
+    fn flips_positions() {
+        // The starting position is symmetric under a colour flip
+        assert_eq!(
+            flip_sfen(STARTPOS),
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"
+        );
+        assert_eq!(
+            flip_sfen(MIDGAME),
+            "lnsg1gsnl/1r3k3/p1+Bppp1pp/2p3p2/7P1/2P1P4/PP1P1PP1P/1SG1KS1R1/LN3G1NL b Pb2p 24"
+        );
+    }
+
+    #[test]
+    fn transforms_moves() {
+        assert_eq!(mirror_usi("7g7f"), "3g3f");
+        assert_eq!(mirror_usi("8h2b+"), "2h8b+");
+        assert_eq!(mirror_usi("P*5e"), "P*5e");
+        assert_eq!(flip_usi("7g7f"), "3c3d");
+        assert_eq!(flip_usi("8h2b+"), "2b8h+");
+        assert_eq!(flip_usi("S*1a"), "S*9i");
+    }
+
+    #[test]
+    fn transforms_are_involutions() {
+        for sfen in [STARTPOS, MIDGAME] {
+            assert_eq!(mirror_sfen(&mirror_sfen(sfen)), sfen);
+            assert_eq!(flip_sfen(&flip_sfen(sfen)), sfen);
+        }
+
+        for usi in ["7g7f", "8h2b+", "P*5e", "S*1a"] {
+            assert_eq!(mirror_usi(&mirror_usi(usi)), usi);
+            assert_eq!(flip_usi(&flip_usi(usi)), usi);
+        }
+    }
+
+    #[test]
+    fn color_flip_negates_scores_and_outcome() {
+        let game = Stoatpack {
+            startpos: Position::from_sfen(STARTPOS).unwrap(),
+            wdl: Outcome::SenteWin,
+            moves: vec![
+                ("7g7f".parse().unwrap(), 35),
+                ("3c3d".parse().unwrap(), -20),
+            ],
+        };
+        let moves = |game: &Stoatpack| {
+            game.moves
+                .iter()
+                .map(|(mv, score)| (mv.to_string(), *score))
+                .collect::<Vec<_>>()
+        };
+
+        let flipped = color_flip(&game).unwrap();
+        assert_eq!(flipped.wdl, Outcome::SenteLoss);
+        assert_eq!(
+            moves(&flipped),
+            [("3c3d".to_string(), -35), ("7g7f".to_string(), 20)]
+        );
+
+        let mirrored = mirror(&game).unwrap();
+        assert_eq!(mirrored.wdl, Outcome::SenteWin);
+        assert_eq!(
+            moves(&mirrored),
+            [("3g3f".to_string(), 35), ("7c7d".to_string(), -20)]
+        );
+    }
+}
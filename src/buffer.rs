@@ -0,0 +1,25 @@
+use std::{
+    io::{BufReader, BufWriter, Read, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Matches the standard library's own default capacity.
+pub const DEFAULT_SIZE: usize = 8 * 1024;
+/// Used by `--sequential` for buffers that are not sized explicitly.
+pub const SEQUENTIAL_SIZE: usize = 64 * 1024 * 1024;
+
+static READ_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_SIZE);
+static WRITE_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_SIZE);
+
+pub fn configure(read_size: usize, write_size: usize) {
+    READ_SIZE.store(read_size.max(1), Ordering::Relaxed);
+    WRITE_SIZE.store(write_size.max(1), Ordering::Relaxed);
+}
+
+pub fn reader<R: Read>(inner: R) -> BufReader<R> {
+    BufReader::with_capacity(READ_SIZE.load(Ordering::Relaxed), inner)
+}
+
+pub fn writer<W: Write>(inner: W) -> BufWriter<W> {
+    BufWriter::with_capacity(WRITE_SIZE.load(Ordering::Relaxed), inner)
+}
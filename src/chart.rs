@@ -0,0 +1,44 @@
+use {
+    crate::error::{Error, Result},
+    plotters::prelude::*,
+    std::path::Path,
+};
+
+pub fn write_histogram(path: &Path, title: &str, labels: &[String], values: &[u64]) -> Result<()> {
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    let root = BitMapBackend::new(path, (960, 540)).into_drawing_area();
+
+    root.fill(&WHITE).map_err(Error::other)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 24))
+        .margin(16)
+        .x_label_area_size(40)
+        .y_label_area_size(80)
+        .build_cartesian_2d((0..values.len()).into_segmented(), 0..max + max / 10)
+        .map_err(Error::other)?;
+
+    chart
+        .configure_mesh()
+        .x_label_formatter(&|value| match value {
+            SegmentValue::Exact(idx) | SegmentValue::CenterOf(idx) => {
+                labels.get(*idx).cloned().unwrap_or_default()
+            }
+            SegmentValue::Last => String::new(),
+        })
+        .draw()
+        .map_err(Error::other)?;
+
+    chart
+        .draw_series(
+            Histogram::vertical(&chart)
+                .style(BLUE.filled())
+                .margin(1)
+                .data(values.iter().copied().enumerate()),
+        )
+        .map_err(Error::other)?;
+
+    root.present().map_err(Error::other)?;
+
+    Ok(())
+}
@@ -0,0 +1,377 @@
+use {
+    crate::{buffer, error::Result},
+    colored::Colorize,
+    sha2::{Digest, Sha256},
+    std::{
+        fs::{File, read_to_string, write},
+        io::{self, Read, Seek, SeekFrom, copy},
+        path::{Path, PathBuf},
+    },
+};
+
+/// `sha256sum` output covering every file in its directory
+const MANIFEST: &str = "SHA256SUMS";
+
+/// Hashes everything read through it and warns once the whole file has been read
+/// if the digest does not match the `.sha256` sidecar or the directory manifest.
+/// Callers may seek: bytes read again are hashed only once, and bytes skipped by a
+/// forward seek are read and hashed on the way, so the digest always covers the file.
+pub struct ChecksumReader<R> {
+    inner: R,
+    path: PathBuf,
+    expected: Option<String>,
+    hasher: Sha256,
+    position: u64,
+    /// Bytes from the start of the file hashed so far, never behind `position`
+    hashed: u64,
+    len: u64,
+}
+
+impl<'a> ChecksumReader<&'a File> {
+    pub fn new(file: &'a File, path: &Path) -> Result<Self> {
+        Ok(Self::with_len(file, path, file.metadata()?.len()))
+    }
+}
+
+impl ChecksumReader<File> {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        Ok(Self::with_len(file, path, len))
+    }
+}
+
+impl<R> ChecksumReader<R> {
+    fn with_len(inner: R, path: &Path, len: u64) -> Self {
+        Self {
+            inner,
+            path: path.to_path_buf(),
+            expected: expected(path),
+            hasher: Sha256::new(),
+            position: 0,
+            hashed: 0,
+            len,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn verify(&mut self) {
+        if self.hashed != self.len {
+            return;
+        }
+
+        let Some(expected) = self.expected.take() else {
+            return;
+        };
+        let actual = format!("{:x}", self.hasher.finalize_reset());
+
+        if actual != expected {
+            eprintln!(
+                "{}",
+                format!(
+                    "CHECKSUM MISMATCH: {}, expected {}, got {}",
+                    self.path.display(),
+                    expected,
+                    actual
+                )
+                .red()
+                .bold()
+            );
+        }
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let start = self.position;
+        self.position += read as u64;
+
+        if self.expected.is_some() && start <= self.hashed && self.position > self.hashed {
+            self.hasher
+                .update(&buf[(self.hashed - start) as usize..read]);
+            self.hashed = self.position;
+            self.verify();
+        }
+
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for ChecksumReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if pos == SeekFrom::Current(0) {
+            return Ok(self.position);
+        }
+
+        let target = self.inner.seek(pos)?;
+
+        if self.expected.is_some() && target > self.hashed {
+            // Hash the skipped bytes so the digest still covers the file in order
+            self.inner.seek(SeekFrom::Start(self.hashed))?;
+            self.hashed += copy(
+                &mut (&mut self.inner).take(target - self.hashed),
+                &mut self.hasher,
+            )?;
+            self.verify();
+
+            // Seeking past the end leaves nothing more to hash
+            if self.hashed != target {
+                self.inner.seek(SeekFrom::Start(target))?;
+            }
+        }
+
+        self.position = target;
+
+        Ok(self.position)
+    }
+}
+
+/// Rewrites the digest of a file changed in place in its `.sha256` sidecar and in its line
+/// of the directory manifest, wherever it has one.
+pub fn refresh(path: &Path) -> Result<()> {
+    let sidecar = sidecar_path(path);
+    let manifest = path.with_file_name(MANIFEST);
+    let in_manifest = manifest_entry(&manifest, path).is_some();
+
+    if !sidecar.is_file() && !in_manifest {
+        return Ok(());
+    }
+
+    let mut hasher = Sha256::new();
+    copy(&mut buffer::reader(File::open(path)?), &mut hasher)?;
+    let digest = format!("{:x}", hasher.finalize());
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    if sidecar.is_file() {
+        write(&sidecar, format!("{}  {}\n", digest, name))?;
+    }
+
+    if in_manifest {
+        let text = read_to_string(&manifest)?;
+        let lines = text
+            .lines()
+            .map(|line| match manifest_line(line) {
+                // Keep the separator and binary-mode marker as sha256sum wrote them
+                Some((hash, file)) if file == name => format!("{}{}", digest, &line[hash.len()..]),
+                _ => line.to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        write(&manifest, lines.join("\n") + "\n")?;
+    }
+
+    Ok(())
+}
+
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sha256");
+
+    PathBuf::from(name)
+}
+
+/// The sidecar or manifest holding the expected digest of `path`, if there is one.
+pub fn source(path: &Path) -> Option<PathBuf> {
+    let sidecar = sidecar_path(path);
+
+    if sidecar.is_file() {
+        return Some(sidecar);
+    }
+
+    let manifest = path.with_file_name(MANIFEST);
+    manifest_entry(&manifest, path).map(|_| manifest)
+}
+
+fn expected(path: &Path) -> Option<String> {
+    if let Ok(text) = read_to_string(sidecar_path(path)) {
+        return text.split_whitespace().next().map(str::to_lowercase);
+    }
+
+    manifest_entry(&path.with_file_name(MANIFEST), path)
+}
+
+fn manifest_entry(manifest: &Path, path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+
+    read_to_string(manifest).ok()?.lines().find_map(|line| {
+        let (hash, file) = manifest_line(line)?;
+
+        (file == name).then(|| hash.to_lowercase())
+    })
+}
+
+/// Splits a manifest line into its hash and file name
+fn manifest_line(line: &str) -> Option<(&str, &str)> {
+    let (hash, file) = line.split_once(char::is_whitespace)?;
+    // sha256sum marks binary mode with a leading '*'
+    let file = file.trim_start().trim_start_matches('*');
+
+    Some((hash, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{
+            fs::{create_dir_all, remove_dir_all},
+            io::Cursor,
+            process,
+        },
+    };
+
+    fn digest(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn reader(data: &[u8]) -> ChecksumReader<Cursor<Vec<u8>>> {
+        let mut reader = ChecksumReader::with_len(
+            Cursor::new(data.to_vec()),
+            Path::new("test.spk"),
+            data.len() as u64,
+        );
+        reader.expected = Some(digest(data));
+
+        reader
+    }
+
+    /// The digest so far, which must cover exactly the first `hashed` bytes in order
+    fn partial(reader: &ChecksumReader<Cursor<Vec<u8>>>) -> String {
+        format!("{:x}", reader.hasher.clone().finalize())
+    }
+
+    fn data() -> Vec<u8> {
+        (0..1000).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn sequential_reads_verify() {
+        let data = data();
+        let mut reader = reader(&data);
+        let mut buf = [0; 300];
+
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(partial(&reader), digest(&data[..300]));
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+
+        assert_eq!(reader.hashed, data.len() as u64);
+        assert!(reader.expected.is_none());
+    }
+
+    #[test]
+    fn forward_seek_hashes_skipped_bytes() {
+        let data = data();
+        let mut reader = reader(&data);
+        let mut buf = [0; 100];
+
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.seek(SeekFrom::Start(600)).unwrap(), 600);
+        assert_eq!(reader.hashed, 600);
+        assert_eq!(partial(&reader), digest(&data[..600]));
+
+        // Reads continue from the seek target, not from where hashing stopped
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[600..700]);
+
+        reader.seek(SeekFrom::End(0)).unwrap();
+        assert!(reader.expected.is_none());
+    }
+
+    #[test]
+    fn rereads_are_hashed_once() {
+        let data = data();
+        let mut reader = reader(&data);
+        let mut buf = [0; 400];
+
+        reader.read_exact(&mut buf).unwrap();
+        reader.seek(SeekFrom::Start(100)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[100..500]);
+        assert_eq!(reader.hashed, 500);
+        assert_eq!(partial(&reader), digest(&data[..500]));
+
+        reader.seek(SeekFrom::Current(-450)).unwrap();
+        reader.read_exact(&mut buf[..10]).unwrap();
+        assert_eq!(partial(&reader), digest(&data[..500]));
+    }
+
+    #[test]
+    fn seek_past_end_stops_at_end() {
+        let data = data();
+        let mut reader = reader(&data);
+
+        assert_eq!(reader.seek(SeekFrom::Start(5000)).unwrap(), 5000);
+        assert_eq!(reader.hashed, data.len() as u64);
+        assert!(reader.expected.is_none());
+    }
+
+    #[test]
+    fn unchecked_files_are_not_hashed() {
+        let data = data();
+        let mut reader =
+            ChecksumReader::with_len(Cursor::new(data.clone()), Path::new("test.spk"), 1000);
+        let mut buf = Vec::new();
+
+        reader.seek(SeekFrom::Start(500)).unwrap();
+        reader.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(buf, data[500..]);
+        assert_eq!(reader.hashed, 0);
+    }
+
+    #[test]
+    fn refresh_rewrites_the_manifest_entry() {
+        let dir = std::env::temp_dir().join(format!("spk-tools-checksum-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("games.spk");
+        let manifest = dir.join(MANIFEST);
+        let data = data();
+
+        write(&path, &data).unwrap();
+        write(
+            &manifest,
+            format!(
+                "{}  other.spk\n{} *games.spk\n",
+                digest(b"other"),
+                digest(&data)
+            ),
+        )
+        .unwrap();
+
+        // Rewrite the file in place, as fix or shuffle would
+        let rewritten = &data[..500];
+        write(&path, rewritten).unwrap();
+        refresh(&path).unwrap();
+
+        let mut reader = ChecksumReader::open(&path).unwrap();
+        assert_eq!(reader.expected, Some(digest(rewritten)));
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(reader.hashed, rewritten.len() as u64);
+
+        // Other entries and the binary-mode marker are left as they were
+        assert_eq!(
+            read_to_string(&manifest).unwrap(),
+            format!(
+                "{}  other.spk\n{} *games.spk\n",
+                digest(b"other"),
+                digest(rewritten)
+            )
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use {
+    crate::{CountArgs, CountStats, ending},
+    colored::Colorize,
+};
+
+/// Relative changes at least this large, in percent, are highlighted
+const HIGHLIGHT_CHANGE: f64 = 5.0;
+/// Changes in shares at least this large, in percentage points, are highlighted
+const HIGHLIGHT_POINTS: f64 = 1.0;
+
+enum Value {
+    Count(f64),
+    /// A share of a total, in percent; deltas are shown in percentage points
+    Share(f64),
+    Score(f64),
+}
+
+/// Prints the key statistics of two runs side by side, highlighting large deltas.
+pub fn print(args: &CountArgs, labels: [&str; 2], stats: [&CountStats; 2]) {
+    println!(
+        "  {: <24} | {: >14} | {: >14} | {: >10}",
+        "", labels[0], labels[1], "Delta"
+    );
+
+    for (name, values) in rows(args, stats) {
+        let (a, b, delta, highlight) = match values {
+            [Value::Count(a), Value::Count(b)] => {
+                let change = (b - a) / a * 100.0;
+
+                (
+                    format!("{:.0}", a),
+                    format!("{:.0}", b),
+                    format!("{:+.2}%", change),
+                    change.abs() >= HIGHLIGHT_CHANGE,
+                )
+            }
+            [Value::Share(a), Value::Share(b)] => (
+                format!("{:.2}%", a),
+                format!("{:.2}%", b),
+                format!("{:+.2}pp", b - a),
+                (b - a).abs() >= HIGHLIGHT_POINTS,
+            ),
+            [Value::Score(a), Value::Score(b)] => {
+                let change = (b - a) / a.abs() * 100.0;
+
+                (
+                    format!("{:+.2}", a),
+                    format!("{:+.2}", b),
+                    format!("{:+.2}", b - a),
+                    change.abs() >= HIGHLIGHT_CHANGE,
+                )
+            }
+            _ => unreachable!(),
+        };
+        let delta = format!("{: >10}", delta);
+
+        println!(
+            "  {: <24} | {: >14} | {: >14} | {}",
+            name,
+            a,
+            b,
+            if highlight {
+                delta.yellow().bold()
+            } else {
+                delta.normal()
+            }
+        );
+    }
+}
+
+fn rows(args: &CountArgs, stats: [&CountStats; 2]) -> Vec<(String, [Value; 2])> {
+    let share = |part: usize, total: usize| part as f64 / total as f64 * 100.0;
+    let mut rows = vec![
+        (
+            "Games".to_string(),
+            stats.map(|stats| Value::Count(stats.games() as f64)),
+        ),
+        (
+            "Positions".to_string(),
+            stats.map(|stats| Value::Count(stats.positions as f64)),
+        ),
+        (
+            "Positions per game".to_string(),
+            stats.map(|stats| Value::Score(stats.positions as f64 / stats.games() as f64)),
+        ),
+        (
+            "Black wins".to_string(),
+            stats.map(|stats| Value::Share(share(stats.black_wins, stats.games()))),
+        ),
+        (
+            "White wins".to_string(),
+            stats.map(|stats| Value::Share(share(stats.white_wins, stats.games()))),
+        ),
+        (
+            "Draws".to_string(),
+            stats.map(|stats| Value::Share(share(stats.draws, stats.games()))),
+        ),
+        (
+            "Reverses".to_string(),
+            stats.map(|stats| Value::Share(share(stats.reverses, stats.games()))),
+        ),
+    ];
+
+    for (idx, ply) in args.bias_plies.iter().enumerate() {
+        rows.push((
+            format!("Mean score at ply {}", ply),
+            stats.map(|stats| {
+                let (sum, count) = stats.ply_scores[idx];
+                Value::Score(sum as f64 / count as f64)
+            }),
+        ));
+    }
+
+    if !args.quick {
+        let endings = stats.map(|stats| stats.endings.counts(args.max_ply));
+
+        for (idx, name) in ending::NAMES.iter().enumerate() {
+            rows.push((
+                format!("Ending: {}", name),
+                endings.map(|counts| {
+                    Value::Share(counts[idx] as f64 / counts.iter().sum::<u64>() as f64 * 100.0)
+                }),
+            ));
+        }
+
+        rows.push((
+            "Score noise mean |diff|".to_string(),
+            stats.map(|stats| {
+                Value::Score(stats.score_noise.abs_diff / stats.score_noise.pairs as f64)
+            }),
+        ));
+    }
+
+    rows
+}
@@ -0,0 +1,255 @@
+use {
+    crate::{
+        CompressArgs, IndexRange, TeeReader, buffer,
+        checksum::ChecksumReader,
+        error::{Context, Error, Result},
+    },
+    std::{
+        fmt::Display,
+        fs::{File, OpenOptions, rename},
+        io::{Read, Seek, SeekFrom, Write},
+        path::Path,
+    },
+    stoatformat::stoatpack::Stoatpack,
+};
+
+/// Compressed files hold zstd blocks of whole records, followed by an index of the blocks,
+/// the level and block size they were packed with, the block count and [`MAGIC`], so a reader
+/// can seek straight to the block holding a record and a rewrite can pack the same way.
+pub const EXTENSION: &str = "spkz";
+
+const MAGIC: &[u8; 4] = b"SPZ2";
+const ENTRY_LEN: usize = 28;
+const TRAILER_LEN: usize = 20;
+
+/// An index entry, with the number of records before the block.
+struct Block {
+    offset: u64,
+    len: u64,
+    first_record: u64,
+    records: u32,
+}
+
+/// The block index and the settings a file was packed with
+struct Index {
+    blocks: Vec<Block>,
+    level: i32,
+    block_kib: usize,
+}
+
+pub fn is_compressed(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some(EXTENSION)
+}
+
+/// Converts between plain `.spk` and compressed `.spkz` files, next to the input.
+pub fn convert(path: &Path, args: &CompressArgs) -> Result<usize> {
+    let (output, records) = if args.decompress {
+        let output = path.with_extension("spk");
+        (output.clone(), unpack(path, &output)?)
+    } else {
+        let output = path.with_extension(EXTENSION);
+        (
+            output.clone(),
+            pack(path, &output, args.level, args.block_size)?,
+        )
+    };
+
+    println!(
+        "Pack  : {} -> {}, {} records, {} -> {} bytes",
+        path.display(),
+        output.display(),
+        records,
+        path.metadata()?.len(),
+        output.metadata()?.len()
+    );
+
+    Ok(records)
+}
+
+/// Compresses the plain file at `path` into `output`, replacing it atomically.
+pub fn pack(path: &Path, output: &Path, level: i32, block_kib: usize) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = TeeReader::new(buffer::reader(ChecksumReader::new(&file, path)?));
+    let tmp_path = output.with_extension("spkz.tmp");
+    let mut writer = buffer::writer(File::create(&tmp_path)?);
+    let mut blocks = Vec::new();
+    let mut block = Vec::new();
+    let mut block_records = 0;
+    let mut records = 0;
+    let mut offset = 0;
+
+    while reader.position < len {
+        let record_offset = reader.position;
+        Stoatpack::deserialise(&mut reader).at_offset(path, record_offset)?;
+        block.extend_from_slice(&reader.take_captured());
+        block_records += 1;
+        records += 1;
+
+        if block.len() >= block_kib * 1024 || reader.position == len {
+            let compressed = zstd::encode_all(&block[..], level)?;
+            writer.write_all(&compressed)?;
+            blocks.push(Block {
+                offset,
+                len: compressed.len() as u64,
+                first_record: (records - block_records as usize) as u64,
+                records: block_records,
+            });
+            offset += compressed.len() as u64;
+            block.clear();
+            block_records = 0;
+        }
+    }
+
+    for block in &blocks {
+        writer.write_all(&block.offset.to_le_bytes())?;
+        writer.write_all(&block.len.to_le_bytes())?;
+        writer.write_all(&block.first_record.to_le_bytes())?;
+        writer.write_all(&block.records.to_le_bytes())?;
+    }
+
+    writer.write_all(&level.to_le_bytes())?;
+    writer.write_all(&(block_kib as u32).to_le_bytes())?;
+    writer.write_all(&(blocks.len() as u64).to_le_bytes())?;
+    writer.write_all(MAGIC)?;
+    writer.flush()?;
+    drop(writer);
+    rename(&tmp_path, output)?;
+
+    Ok(records)
+}
+
+/// Decompresses the file at `path` into the plain file `output`.
+pub fn unpack(path: &Path, output: &Path) -> Result<usize> {
+    let mut file = File::open(path)?;
+    let mut writer = buffer::writer(File::create(output)?);
+    let mut records = 0;
+
+    for block in read_index(&mut file, path)?.blocks {
+        let mut compressed = vec![0; block.len as usize];
+        file.seek(SeekFrom::Start(block.offset))?;
+        file.read_exact(&mut compressed)?;
+        writer.write_all(&zstd::decode_all(&compressed[..])?)?;
+        records += block.records as usize;
+    }
+
+    writer.flush()?;
+
+    Ok(records)
+}
+
+/// Copies the records at `indices` to `writer`, decompressing only the blocks holding them.
+pub fn extract(
+    path: &Path,
+    indices: &[IndexRange],
+    writer: &mut impl Write,
+) -> Result<(usize, usize)> {
+    let mut file = File::open(path)?;
+    let index = read_index(&mut file, path)?;
+    let mut extracted = 0;
+
+    for block in &index.blocks {
+        let first = block.first_record as usize;
+        let end = first + block.records as usize;
+
+        if !indices
+            .iter()
+            .any(|range| range.start < end && range.end >= first)
+        {
+            continue;
+        }
+
+        let mut compressed = vec![0; block.len as usize];
+        file.seek(SeekFrom::Start(block.offset))?;
+        file.read_exact(&mut compressed)?;
+
+        let records = zstd::decode_all(&compressed[..])?;
+        let mut reader = TeeReader::new(&records[..]);
+
+        for record in first..end {
+            Stoatpack::deserialise(&mut reader)
+                .map_err(|error| format_error(path, format!("record {}: {}", record, error)))?;
+            let bytes = reader.take_captured();
+
+            if indices.iter().any(|range| range.contains(record)) {
+                writer.write_all(&bytes)?;
+                extracted += 1;
+            }
+        }
+    }
+
+    let records = index
+        .blocks
+        .iter()
+        .map(|block| block.records as usize)
+        .sum();
+
+    Ok((records, extracted))
+}
+
+/// Counts records from the block index alone, without decompressing anything.
+pub fn record_count(path: &Path) -> Result<usize> {
+    Ok(read_index(&mut File::open(path)?, path)?
+        .blocks
+        .iter()
+        .map(|block| block.records as usize)
+        .sum())
+}
+
+/// The zstd level and block size in KiB the file was packed with, so rewriting it keeps them.
+pub fn settings(path: &Path) -> Result<(i32, usize)> {
+    let index = read_index(&mut File::open(path)?, path)?;
+
+    Ok((index.level, index.block_kib))
+}
+
+fn read_index(file: &mut File, path: &Path) -> Result<Index> {
+    let len = file.metadata()?.len();
+
+    if len < TRAILER_LEN as u64 {
+        return Err(format_error(path, "file too short"));
+    }
+
+    let mut trailer = [0; TRAILER_LEN];
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    file.read_exact(&mut trailer)?;
+
+    if &trailer[16..] != MAGIC {
+        return Err(format_error(path, "missing SPZ2 trailer"));
+    }
+
+    let level = i32::from_le_bytes(trailer[0..4].try_into().unwrap());
+    let block_kib = u32::from_le_bytes(trailer[4..8].try_into().unwrap()) as usize;
+    let count = u64::from_le_bytes(trailer[8..16].try_into().unwrap());
+    let index_len = count
+        .checked_mul(ENTRY_LEN as u64)
+        .filter(|index_len| index_len + TRAILER_LEN as u64 <= len)
+        .ok_or_else(|| format_error(path, "block index runs past the start of the file"))?;
+
+    let mut index = vec![0; index_len as usize];
+    file.seek(SeekFrom::Start(len - TRAILER_LEN as u64 - index_len))?;
+    file.read_exact(&mut index)?;
+
+    Ok(Index {
+        blocks: index
+            .chunks_exact(ENTRY_LEN)
+            .map(|entry| Block {
+                offset: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                len: u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+                first_record: u64::from_le_bytes(entry[16..24].try_into().unwrap()),
+                records: u32::from_le_bytes(entry[24..28].try_into().unwrap()),
+            })
+            .collect(),
+        level,
+        block_kib,
+    })
+}
+
+fn format_error<E: Display>(path: &Path, error: E) -> Error {
+    Error::other(format!(
+        "Invalid {} file: {}, {}",
+        EXTENSION,
+        path.display(),
+        error
+    ))
+}
@@ -0,0 +1,348 @@
+mod dedupe;
+mod lmdb;
+mod npz;
+mod packed;
+mod parquet;
+mod pipeline;
+mod tfrecord;
+mod wdl;
+
+use {
+    crate::{
+        ConvertArgs, PositionSampler,
+        error::{Error, Result},
+        features::HAND_PIECE_TYPES,
+        relative_square,
+    },
+    clap::ValueEnum,
+    dedupe::DedupingWriter,
+    pipeline::{GameReader, PipelinedWriter},
+    std::path::Path,
+    stoatformat::{
+        Outcome,
+        shogi::{
+            core::{Color, PieceType},
+            position::Position,
+        },
+        stoatpack::Stoatpack,
+    },
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+    Parquet,
+    Npz,
+    Packed,
+    Wdl,
+    Lmdb,
+    Tfrecord,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Dtype {
+    Int16,
+    Int32,
+    Int64,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum DedupePolicy {
+    /// Keep the instance seen first
+    First,
+    /// Keep the instance seen last, which comes from the latest run when inputs are in run order
+    Latest,
+    /// Keep the instance whose score sits closest to the median of all instances. Records
+    /// carry no search depth or node count, so the best-searched instance cannot be told apart
+    /// and agreement between instances stands in for it.
+    Consensus,
+    /// Emit the mean score and mean result of all instances
+    Average,
+}
+
+/// `--lambda` weighting of a position's win probability against its result
+#[derive(Clone, Copy, Debug)]
+pub struct Blend {
+    pub scale: f64,
+    pub lambda: f64,
+}
+
+pub struct Row<'a> {
+    pub game_id: u64,
+    pub ply: usize,
+    pub pos: &'a Position,
+    pub score: i16,
+    pub result: f32,
+}
+
+impl Row<'_> {
+    pub fn stm_score(&self) -> i16 {
+        if self.pos.stm() == Color::SENTE {
+            self.score
+        } else {
+            -self.score
+        }
+    }
+
+    /// 0 = loss, 0.5 = draw, 1 = win for the side to move, or anything between once blended
+    pub fn stm_result_value(&self) -> f32 {
+        if self.pos.stm() == Color::SENTE {
+            self.result
+        } else {
+            1.0 - self.result
+        }
+    }
+
+    /// 0 = loss, 1 = draw, 2 = win for the side to move, rounding blended results
+    pub fn stm_result(&self) -> u8 {
+        (self.stm_result_value() * 2.0).round() as u8
+    }
+
+    /// The side to move's chance of winning, `1 / (1 + e^(-score / scale))`
+    pub fn win_probability(&self, scale: f64) -> f64 {
+        1.0 / (1.0 + (-self.stm_score() as f64 / scale).exp())
+    }
+
+    /// `lambda * win_probability + (1 - lambda) * result`, for the side to move
+    pub fn target(&self, blend: Blend) -> f32 {
+        (blend.lambda * self.win_probability(blend.scale)
+            + (1.0 - blend.lambda) * self.stm_result_value() as f64) as f32
+    }
+}
+
+/// Squares the side to move's king has to stand on. Presets are seen from the side to move,
+/// so `right-castle` covers 1g-3i for sente and 7a-9c for gote; square lists are absolute.
+#[derive(Clone, Debug)]
+pub struct KingRegion {
+    squares: [bool; 81],
+    relative: bool,
+}
+
+impl KingRegion {
+    pub const PRESETS: [&str; 4] = ["right-castle", "left-castle", "center", "promotion-zone"];
+
+    pub fn preset(name: &str) -> Option<Self> {
+        // Ranks count up from sente's back rank and files from the 9 file
+        let contains = |rank: usize, file: usize| match name {
+            "right-castle" => rank < 3 && file >= 6,
+            "left-castle" => rank < 3 && file < 3,
+            "center" => (3..6).contains(&file),
+            "promotion-zone" => rank >= 6,
+            _ => false,
+        };
+
+        Self::PRESETS.contains(&name).then(|| Self {
+            squares: std::array::from_fn(|square| contains(square / 9, square % 9)),
+            relative: true,
+        })
+    }
+
+    pub fn from_squares(squares: &[usize]) -> Self {
+        Self {
+            squares: std::array::from_fn(|square| squares.contains(&square)),
+            relative: false,
+        }
+    }
+
+    fn contains(&self, pos: &Position) -> bool {
+        let Some(king) = pos.piece_bb(PieceType::KING.with_color(pos.stm())).lsb() else {
+            return false;
+        };
+        let king = if self.relative {
+            relative_square(pos.stm(), king)
+        } else {
+            king
+        };
+
+        self.squares[king.idx()]
+    }
+}
+
+/// Conditions on the position itself that each emitted position has to meet
+#[derive(Default)]
+pub struct PositionFilter {
+    min_hand_pieces: u32,
+    min_hand: Vec<(PieceType, u32)>,
+    king_region: Option<KingRegion>,
+}
+
+impl PositionFilter {
+    pub fn new(args: &ConvertArgs) -> Self {
+        Self {
+            min_hand_pieces: args.min_hand_pieces,
+            min_hand: args.min_hand.clone(),
+            king_region: args.king_region.clone(),
+        }
+    }
+
+    /// Hand and king conditions apply to the side to move
+    pub fn keeps(&self, pos: &Position) -> bool {
+        let hand = pos.hand(pos.stm());
+        let pieces = HAND_PIECE_TYPES
+            .iter()
+            .map(|&(piece_type, _)| hand.count(piece_type))
+            .sum::<u32>();
+
+        pieces >= self.min_hand_pieces
+            && self
+                .min_hand
+                .iter()
+                .all(|&(piece_type, count)| hand.count(piece_type) >= count)
+            && self
+                .king_region
+                .as_ref()
+                .is_none_or(|region| region.contains(pos))
+    }
+
+    /// How many positions of `game` could be emitted, before any sampling
+    pub fn eligible_positions(&self, game: &Stoatpack, eval_limit: i16) -> u64 {
+        let mut pos = game.startpos.clone();
+        let mut positions = 0;
+
+        for &(mv, score) in &game.moves {
+            if score.abs() <= eval_limit && self.keeps(&pos) {
+                positions += 1;
+            }
+
+            pos = pos.apply_move(mv);
+        }
+
+        positions
+    }
+}
+
+pub trait PositionWriter {
+    fn write(&mut self, row: &Row) -> Result<()>;
+
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+pub fn create(args: &ConvertArgs) -> Result<Box<dyn PositionWriter + Send>> {
+    let blend = args.lambda.map(|lambda| Blend {
+        scale: args.sigmoid_scale,
+        lambda,
+    });
+    let writer: Box<dyn PositionWriter + Send> = match args.to {
+        Format::Parquet => Box::new(parquet::ParquetWriter::create(&args.output, blend)?),
+        Format::Npz => Box::new(npz::NpzWriter::create(
+            &args.output,
+            args.batch_size,
+            args.max_mem,
+            args.dtype,
+            args.seed,
+            blend,
+        )?),
+        Format::Packed | Format::Lmdb if blend.is_some() => {
+            return Err(Error::other(
+                "--lambda is not supported by the fixed record layout of packed and lmdb",
+            ));
+        }
+        Format::Packed => Box::new(packed::PackedWriter::create(&args.output)?),
+        Format::Lmdb => Box::new(lmdb::LmdbWriter::create(&args.output)?),
+        Format::Tfrecord => Box::new(tfrecord::TfRecordWriter::create(&args.output, blend)?),
+        Format::Wdl => Box::new(wdl::WdlWriter::create(
+            &args.output,
+            args.sigmoid_scale,
+            blend,
+        )?),
+    };
+
+    match args.dedupe {
+        Some(policy) => Ok(Box::new(DedupingWriter::new(writer, policy))),
+        None => Ok(writer),
+    }
+}
+
+pub const RECORD_SIZE: usize = packed::RECORD_SIZE;
+
+/// The packed record of a row, for streaming positions without a writer
+pub fn encode_record(row: &Row) -> [u8; RECORD_SIZE] {
+    packed::encode(row)
+}
+
+/// Moves serialisation and writing of `writer` onto its own thread.
+pub fn pipelined(writer: Box<dyn PositionWriter + Send>) -> Box<dyn PositionWriter> {
+    Box::new(PipelinedWriter::new(writer))
+}
+
+pub fn convert(
+    path: &Path,
+    eval_limit: i16,
+    clip_scores: Option<i16>,
+    filter: &PositionFilter,
+    writer: &mut dyn PositionWriter,
+    game_id: &mut u64,
+    mut sampler: Option<&mut PositionSampler>,
+) -> Result<(usize, usize, usize, usize)> {
+    // Decoding runs ahead on its own thread and writing behind on another, this thread
+    // only filters and samples
+    let mut reader = GameReader::spawn(path.to_path_buf(), eval_limit);
+    let mut games = 0;
+    let mut positions = 0;
+    let mut clipped = 0;
+    let mut sente = 0;
+
+    while let Some(batch) = reader.next_batch()? {
+        for game in batch {
+            for (ply, pos, score) in game.positions {
+                if !filter.keeps(&pos)
+                    || !sampler
+                        .as_deref_mut()
+                        .is_none_or(|sampler| sampler.sample(1))
+                {
+                    continue;
+                }
+
+                let score = match clip_scores {
+                    Some(clip) if score.abs() >= clip => {
+                        clipped += 1;
+                        score.clamp(-clip, clip)
+                    }
+                    _ => score,
+                };
+
+                writer.write(&Row {
+                    game_id: *game_id,
+                    ply,
+                    pos: &pos,
+                    score,
+                    result: game.result,
+                })?;
+                positions += 1;
+
+                if pos.stm() == Color::SENTE {
+                    sente += 1;
+                }
+            }
+
+            *game_id += 1;
+            games += 1;
+        }
+    }
+
+    match clip_scores {
+        Some(_) => println!(
+            "  OK  : {}, {} games, {} positions, {} clipped ({:.2}%)",
+            path.display(),
+            games,
+            positions,
+            clipped,
+            clipped as f64 / positions as f64 * 100.0f64
+        ),
+        None => println!(
+            "  OK  : {}, {} games, {} positions",
+            path.display(),
+            games,
+            positions
+        ),
+    }
+
+    Ok((games, positions, clipped, sente))
+}
+
+pub fn sente_result(wdl: Outcome) -> f32 {
+    match wdl {
+        Outcome::SenteWin => 1.0,
+        Outcome::Draw => 0.5,
+        Outcome::SenteLoss => 0.0,
+    }
+}
@@ -0,0 +1,144 @@
+use {
+    super::{DedupePolicy, PositionWriter, Row},
+    crate::error::Result,
+    std::collections::HashMap,
+    stoatformat::shogi::{core::Color, position::Position},
+};
+
+#[derive(Clone, Copy)]
+struct Instance {
+    game_id: u64,
+    ply: usize,
+    score: i16,
+    result: f32,
+}
+
+struct Duplicates {
+    pos: Position,
+    instances: Vec<Instance>,
+}
+
+/// Holds every position back until the end, then writes each distinct one once, in the order
+/// they were first seen, with the instance `policy` prefers or a blend of all of them.
+pub struct DedupingWriter {
+    inner: Box<dyn PositionWriter + Send>,
+    policy: DedupePolicy,
+    positions: Vec<Duplicates>,
+    indices: HashMap<u64, usize>,
+}
+
+impl DedupingWriter {
+    pub fn new(inner: Box<dyn PositionWriter + Send>, policy: DedupePolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            positions: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+}
+
+impl PositionWriter for DedupingWriter {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        let instance = Instance {
+            game_id: row.game_id,
+            ply: row.ply,
+            score: row.score,
+            result: row.result,
+        };
+
+        match self.indices.get(&row.pos.key()) {
+            Some(&idx) => self.positions[idx].instances.push(instance),
+            None => {
+                self.indices.insert(row.pos.key(), self.positions.len());
+                self.positions.push(Duplicates {
+                    pos: row.pos.clone(),
+                    instances: vec![instance],
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let total = self
+            .positions
+            .iter()
+            .map(|duplicates| duplicates.instances.len())
+            .sum::<usize>();
+
+        for duplicates in &self.positions {
+            let instance = merge(&duplicates.instances, self.policy);
+
+            self.inner.write(&Row {
+                game_id: instance.game_id,
+                ply: instance.ply,
+                pos: &duplicates.pos,
+                score: instance.score,
+                result: instance.result,
+            })?;
+        }
+
+        // Duplicates are not spread evenly over both sides, so the split can shift here too
+        let sente = self
+            .positions
+            .iter()
+            .filter(|duplicates| duplicates.pos.stm() == Color::SENTE)
+            .count();
+
+        if self.positions.is_empty() {
+            println!("Deduplicated positions: none to keep");
+        } else {
+            println!(
+                "Deduplicated positions: {} of {} kept, sente to move in {:.2}%",
+                self.positions.len(),
+                total,
+                sente as f64 / self.positions.len() as f64 * 100.0
+            );
+        }
+
+        self.inner.finish()
+    }
+}
+
+fn merge(instances: &[Instance], policy: DedupePolicy) -> Instance {
+    match policy {
+        DedupePolicy::First => instances[0],
+        DedupePolicy::Latest => instances[instances.len() - 1],
+        DedupePolicy::Consensus => {
+            let mut scores = instances
+                .iter()
+                .map(|instance| instance.score)
+                .collect::<Vec<_>>();
+            scores.sort_unstable();
+            let median = scores[scores.len() / 2];
+
+            // Ties go to the earliest instance
+            *instances
+                .iter()
+                .min_by_key(|instance| instance.score.abs_diff(median))
+                .unwrap()
+        }
+        DedupePolicy::Average => {
+            let count = instances.len() as f64;
+            let score = instances
+                .iter()
+                .map(|instance| instance.score as f64)
+                .sum::<f64>()
+                / count;
+            // Mixed outcomes blend into a soft result between 0 and 1
+            let result = instances
+                .iter()
+                .map(|instance| instance.result as f64)
+                .sum::<f64>()
+                / count;
+
+            Instance {
+                score: score.round() as i16,
+                result: result as f32,
+                ..instances[0]
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use {
+    super::{
+        PositionWriter, Row,
+        packed::{self, RECORD_SIZE},
+    },
+    crate::error::{Error, Result},
+    heed::{Database, Env, EnvOpenOptions, types::Bytes},
+    std::{fs::create_dir_all, path::Path},
+};
+
+/// Bumped whenever the value layout changes, so loaders can refuse data they cannot read
+const ENCODING_VERSION: u32 = 1;
+/// LMDB reserves address space rather than disk up front, so this only caps the final size
+const MAP_SIZE: usize = 1 << 40;
+const COMMIT_ROWS: usize = 1 << 16;
+
+const COUNT_KEY: &[u8] = b"__count__";
+const VERSION_KEY: &[u8] = b"__version__";
+
+/// Writes each position into an LMDB environment under its sequential index as a decimal
+/// ASCII key, with the 80-byte record of the packed format as the value. `__count__`
+/// and `__version__` hold the number of positions and [`ENCODING_VERSION`], also in ASCII.
+pub struct LmdbWriter {
+    env: Env,
+    db: Database<Bytes, Bytes>,
+    pending: Vec<[u8; RECORD_SIZE]>,
+    count: u64,
+}
+
+impl LmdbWriter {
+    pub fn create(dir: &Path) -> Result<Self> {
+        create_dir_all(dir)?;
+
+        // SAFETY: the environment is only opened once, by this writer
+        let env =
+            unsafe { EnvOpenOptions::new().map_size(MAP_SIZE).open(dir) }.map_err(Error::other)?;
+        let mut txn = env.write_txn().map_err(Error::other)?;
+        let db = env
+            .create_database::<Bytes, Bytes>(&mut txn, None)
+            .map_err(Error::other)?;
+        txn.commit().map_err(Error::other)?;
+
+        Ok(Self {
+            env,
+            db,
+            pending: Vec::with_capacity(COMMIT_ROWS),
+            count: 0,
+        })
+    }
+
+    /// Writes the pending rows in one transaction, along with the count so far
+    fn commit(&mut self) -> Result<()> {
+        let mut txn = self.env.write_txn().map_err(Error::other)?;
+
+        for record in self.pending.drain(..) {
+            self.db
+                .put(&mut txn, self.count.to_string().as_bytes(), &record)
+                .map_err(Error::other)?;
+            self.count += 1;
+        }
+
+        self.db
+            .put(&mut txn, COUNT_KEY, self.count.to_string().as_bytes())
+            .map_err(Error::other)?;
+        self.db
+            .put(
+                &mut txn,
+                VERSION_KEY,
+                ENCODING_VERSION.to_string().as_bytes(),
+            )
+            .map_err(Error::other)?;
+
+        txn.commit().map_err(Error::other)
+    }
+}
+
+impl PositionWriter for LmdbWriter {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        self.pending.push(packed::encode(row));
+
+        if self.pending.len() >= COMMIT_ROWS {
+            self.commit()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.commit()?;
+
+        println!("Wrote {} positions to LMDB", self.count);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use {
+    super::{Blend, Dtype, PositionWriter, Row},
+    crate::error::{Error, Result},
+    crate::features::{FeatureSet, MAX_ACTIVE},
+    rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom},
+    std::{
+        fs::{File, create_dir_all},
+        io::Write,
+        path::{Path, PathBuf},
+    },
+    zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions},
+};
+
+/// Writes shuffled batches of positions. Positions are held in memory until the end, or with
+/// `--max-mem` until that many MiB are buffered, when they are shuffled and written out as full
+/// batches. Capping memory shuffles within each window rather than across the whole output.
+pub struct NpzWriter {
+    dir: PathBuf,
+    batch_size: usize,
+    max_positions: usize,
+    dtype: Dtype,
+    rng: SmallRng,
+    batches: usize,
+    features: Vec<i32>,
+    scores: Vec<i16>,
+    results: Vec<f32>,
+    blend: Option<Blend>,
+    targets: Vec<f32>,
+}
+
+impl NpzWriter {
+    pub fn create(
+        dir: &Path,
+        batch_size: usize,
+        max_mem: Option<u64>,
+        dtype: Dtype,
+        seed: u64,
+        blend: Option<Blend>,
+    ) -> Result<Self> {
+        create_dir_all(dir)?;
+
+        // Features, score, result, target and the shuffled index of one buffered position
+        let position_mem = MAX_ACTIVE * size_of::<i32>() + 2 + 4 + 4 + size_of::<usize>();
+        let max_positions = max_mem.map_or(usize::MAX, |max_mem| {
+            max_mem.saturating_mul(1 << 20) as usize / position_mem
+        });
+
+        if max_positions < batch_size {
+            return Err(Error::other(format!(
+                "--max-mem is too small to hold one batch of {} positions",
+                batch_size
+            )));
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            batch_size,
+            max_positions,
+            dtype,
+            rng: SmallRng::seed_from_u64(seed),
+            batches: 0,
+            features: Vec::new(),
+            scores: Vec::new(),
+            results: Vec::new(),
+            blend,
+            targets: Vec::new(),
+        })
+    }
+
+    /// Shuffles the buffered positions and writes them out in batches. Unless `all` is set,
+    /// positions that would only make a partial batch stay buffered for the next window.
+    fn flush(&mut self, all: bool) -> Result<()> {
+        let mut indices = (0..self.scores.len()).collect::<Vec<_>>();
+        indices.shuffle(&mut self.rng);
+
+        let written = if all {
+            indices.len()
+        } else {
+            indices.len() / self.batch_size * self.batch_size
+        };
+
+        for chunk in indices[..written].chunks(self.batch_size) {
+            self.write_batch(self.batches, chunk)?;
+            self.batches += 1;
+        }
+
+        let kept = &indices[written..];
+        self.features = kept
+            .iter()
+            .flat_map(|&idx| &self.features[idx * MAX_ACTIVE..(idx + 1) * MAX_ACTIVE])
+            .copied()
+            .collect();
+        self.scores = kept.iter().map(|&idx| self.scores[idx]).collect();
+        self.results = kept.iter().map(|&idx| self.results[idx]).collect();
+
+        if self.blend.is_some() {
+            self.targets = kept.iter().map(|&idx| self.targets[idx]).collect();
+        }
+
+        Ok(())
+    }
+
+    fn write_batch(&self, batch: usize, indices: &[usize]) -> Result<()> {
+        let path = self.dir.join(format!("batch_{:06}.npz", batch));
+        let mut zip = ZipWriter::new(File::create(&path)?);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        let mut features = Vec::new();
+
+        for &idx in indices {
+            for &feature in &self.features[idx * MAX_ACTIVE..(idx + 1) * MAX_ACTIVE] {
+                match self.dtype {
+                    Dtype::Int16 => features.extend((feature as i16).to_le_bytes()),
+                    Dtype::Int32 => features.extend(feature.to_le_bytes()),
+                    Dtype::Int64 => features.extend((feature as i64).to_le_bytes()),
+                }
+            }
+        }
+
+        let scores = indices
+            .iter()
+            .flat_map(|&idx| self.scores[idx].to_le_bytes())
+            .collect::<Vec<_>>();
+        let results = indices
+            .iter()
+            .flat_map(|&idx| self.results[idx].to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let mut arrays = vec![
+            (
+                "features.npy",
+                self.dtype.descr(),
+                format!("({}, {})", indices.len(), MAX_ACTIVE),
+                features,
+            ),
+            ("scores.npy", "<i2", format!("({},)", indices.len()), scores),
+            (
+                "results.npy",
+                "<f4",
+                format!("({},)", indices.len()),
+                results,
+            ),
+        ];
+
+        if self.blend.is_some() {
+            let targets = indices
+                .iter()
+                .flat_map(|&idx| self.targets[idx].to_le_bytes())
+                .collect::<Vec<_>>();
+            arrays.push((
+                "targets.npy",
+                "<f4",
+                format!("({},)", indices.len()),
+                targets,
+            ));
+        }
+
+        for (name, descr, shape, data) in arrays {
+            zip.start_file(name, options).map_err(Error::other)?;
+            zip.write_all(&npy_header(descr, &shape))?;
+            zip.write_all(&data)?;
+        }
+
+        zip.finish().map_err(Error::other)?;
+
+        Ok(())
+    }
+}
+
+impl PositionWriter for NpzWriter {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        let features = FeatureSet::Psq.active(row.pos, row.pos.stm());
+
+        self.features
+            .extend(features.iter().map(|&feature| feature as i32));
+        self.features
+            .extend(std::iter::repeat_n(-1, MAX_ACTIVE - features.len()));
+        self.scores.push(row.score);
+        self.results.push(row.result);
+
+        if let Some(blend) = self.blend {
+            self.targets.push(row.target(blend));
+        }
+
+        if self.scores.len() >= self.max_positions {
+            self.flush(false)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush(true)?;
+
+        println!("Wrote {} batches to {}", self.batches, self.dir.display());
+
+        Ok(())
+    }
+}
+
+impl Dtype {
+    fn descr(self) -> &'static str {
+        match self {
+            Dtype::Int16 => "<i2",
+            Dtype::Int32 => "<i4",
+            Dtype::Int64 => "<i8",
+        }
+    }
+}
+
+fn npy_header(descr: &str, shape: &str) -> Vec<u8> {
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    let padding = 64 - (10 + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
+
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend((header.len() as u16).to_le_bytes());
+    bytes.extend(header.as_bytes());
+
+    bytes
+}
@@ -0,0 +1,113 @@
+use {
+    super::{PositionWriter, Row},
+    crate::{
+        buffer,
+        error::Result,
+        features::{HAND_PIECE_TYPES, PIECE_TYPES, opponent},
+        relative_square,
+    },
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::Path,
+    },
+    stoatformat::shogi::core::PieceType,
+};
+
+pub const RECORD_SIZE: usize = 80;
+
+/// Writes the packed position format: fixed-size 80-byte records with no header, one per
+/// position, always from the side to move's perspective. This is spk-tools' own layout, not
+/// a format any trainer reads as is. Integers are little-endian. Squares are indexed
+/// `(8 - rank) * 9 + (9 - file)`, where rank counts from 0 for USI rank `a` and file is the
+/// USI file, so 0 is `9i` and 80 is `1a`. When gote is to move, square `i` becomes `80 - i`.
+///
+/// | Bytes | Type | Field |
+/// |-------|------|-------|
+/// | 0..16 | `u128` | occupancy, bit `i` set when square `i` is occupied |
+/// | 16..56 | `[u8; 40]` | one byte per occupied square in ascending square order, then zeros |
+/// | 56..63 | `[u8; 7]` | side to move's hand: pawn, lance, knight, silver, gold, bishop, rook |
+/// | 63..70 | `[u8; 7]` | opponent's hand, in the same order |
+/// | 70..72 | `i16` | score for the side to move |
+/// | 72 | `u8` | result for the side to move: 0 loss, 1 draw, 2 win |
+/// | 73 | `u8` | side to move's king square |
+/// | 74 | `u8` | opponent's king square |
+/// | 75..80 | `[u8; 5]` | zero padding |
+///
+/// A piece byte is `side << 4 | piece`, with `side` 0 for the side to move and 1 for the
+/// opponent, and `piece` 0 to 13 for pawn, lance, knight, silver, gold, bishop, rook,
+/// promoted pawn, promoted lance, promoted knight, promoted silver, horse, dragon and king.
+pub struct PackedWriter {
+    writer: BufWriter<File>,
+}
+
+impl PackedWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            writer: buffer::writer(File::create(path)?),
+        })
+    }
+}
+
+impl PositionWriter for PackedWriter {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        self.writer.write_all(&encode(row))?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Packs a row into the record layout described on [`PackedWriter`]
+pub fn encode(row: &Row) -> [u8; RECORD_SIZE] {
+    let stm = row.pos.stm();
+    let mut board = [None; 81];
+    let mut record = [0u8; RECORD_SIZE];
+
+    for (side, color) in [stm, opponent(stm)].into_iter().enumerate() {
+        for (idx, piece_type) in PIECE_TYPES.iter().enumerate() {
+            for square in row.pos.piece_bb(piece_type.with_color(color)) {
+                board[relative_square(stm, square).idx()] = Some((side << 4 | idx) as u8);
+            }
+        }
+
+        for (idx, (piece_type, _)) in HAND_PIECE_TYPES.iter().enumerate() {
+            record[56 + side * 7 + idx] = row.pos.hand(color).count(*piece_type) as u8;
+        }
+    }
+
+    let mut occupancy = 0u128;
+
+    for (idx, (square, piece)) in board
+        .iter()
+        .enumerate()
+        .filter_map(|(square, piece)| piece.map(|piece| (square, piece)))
+        .take(40)
+        .enumerate()
+    {
+        occupancy |= 1 << square;
+        record[16 + idx] = piece;
+    }
+
+    let king = |color| {
+        relative_square(
+            stm,
+            row.pos
+                .piece_bb(PieceType::KING.with_color(color))
+                .lsb()
+                .unwrap(),
+        )
+        .idx() as u8
+    };
+
+    record[..16].copy_from_slice(&occupancy.to_le_bytes());
+    record[70..72].copy_from_slice(&row.stm_score().to_le_bytes());
+    record[72] = row.stm_result();
+    record[73] = king(stm);
+    record[74] = king(opponent(stm));
+
+    record
+}
@@ -0,0 +1,116 @@
+use {
+    super::{Blend, PositionWriter, Row},
+    crate::error::{Error, Result},
+    ::arrow::{
+        array::{
+            ArrayRef, Float32Builder, Int16Builder, StringBuilder, UInt32Builder, UInt64Builder,
+        },
+        datatypes::{DataType, Field, Schema, SchemaRef},
+        record_batch::RecordBatch,
+    },
+    ::parquet::arrow::ArrowWriter,
+    std::{fs::File, path::Path, sync::Arc},
+};
+
+const BATCH_ROWS: usize = 1 << 16;
+
+pub struct ParquetWriter {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    rows: usize,
+    sfen: StringBuilder,
+    score: Int16Builder,
+    result: Float32Builder,
+    ply: UInt32Builder,
+    game_id: UInt64Builder,
+    blend: Option<Blend>,
+    target: Float32Builder,
+}
+
+impl ParquetWriter {
+    /// With a blend, adds a `target` column holding it from the side to move's perspective
+    pub fn create(path: &Path, blend: Option<Blend>) -> Result<Self> {
+        let mut fields = vec![
+            Field::new("sfen", DataType::Utf8, false),
+            Field::new("score", DataType::Int16, false),
+            Field::new("result", DataType::Float32, false),
+            Field::new("ply", DataType::UInt32, false),
+            Field::new("game_id", DataType::UInt64, false),
+        ];
+
+        if blend.is_some() {
+            fields.push(Field::new("target", DataType::Float32, false));
+        }
+
+        let schema = Arc::new(Schema::new(fields));
+        let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), None)
+            .map_err(Error::other)?;
+
+        Ok(Self {
+            writer,
+            schema,
+            rows: 0,
+            sfen: StringBuilder::new(),
+            score: Int16Builder::new(),
+            result: Float32Builder::new(),
+            ply: UInt32Builder::new(),
+            game_id: UInt64Builder::new(),
+            blend,
+            target: Float32Builder::new(),
+        })
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(self.sfen.finish()),
+            Arc::new(self.score.finish()),
+            Arc::new(self.result.finish()),
+            Arc::new(self.ply.finish()),
+            Arc::new(self.game_id.finish()),
+        ];
+
+        if self.blend.is_some() {
+            columns.push(Arc::new(self.target.finish()));
+        }
+
+        let batch = RecordBatch::try_new(self.schema.clone(), columns).map_err(Error::other)?;
+
+        self.writer.write(&batch).map_err(Error::other)?;
+        self.rows = 0;
+
+        Ok(())
+    }
+}
+
+impl PositionWriter for ParquetWriter {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        self.sfen.append_value(row.pos.sfen());
+        self.score.append_value(row.score);
+        self.result.append_value(row.result);
+        self.ply.append_value(row.ply as u32);
+        self.game_id.append_value(row.game_id);
+
+        if let Some(blend) = self.blend {
+            self.target.append_value(row.target(blend));
+        }
+
+        self.rows += 1;
+
+        if self.rows == BATCH_ROWS {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush()?;
+        self.writer.close().map_err(Error::other)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use {
+    super::{PositionWriter, Row, sente_result},
+    crate::{
+        buffer,
+        checksum::ChecksumReader,
+        error::{Context, Error, Result},
+        progress,
+    },
+    std::{
+        fs::OpenOptions,
+        io::Seek,
+        mem,
+        path::PathBuf,
+        sync::mpsc::{Receiver, SyncSender, sync_channel},
+        thread::{self, JoinHandle},
+    },
+    stoatformat::{shogi::position::Position, stoatpack::Stoatpack},
+};
+
+const BATCH_SIZE: usize = 4096;
+const QUEUE_DEPTH: usize = 16;
+const GAME_BATCH_SIZE: usize = 256;
+
+/// A decoded game, with the positions inside the eval limit already played out
+pub struct Game {
+    pub result: f32,
+    /// Ply, position and score of each position within the eval limit
+    pub positions: Vec<(usize, Position, i16)>,
+}
+
+/// Decodes the games of one file on a dedicated thread and plays out their moves, handing
+/// them over in batches through a bounded channel so reading overlaps with filtering and
+/// writing. Order is kept, so sampling stays reproducible.
+pub struct GameReader {
+    receiver: Receiver<Vec<Game>>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl GameReader {
+    pub fn spawn(path: PathBuf, eval_limit: i16) -> Self {
+        let (sender, receiver) = sync_channel::<Vec<Game>>(QUEUE_DEPTH);
+        let handle = thread::spawn(move || {
+            let file = OpenOptions::new().read(true).open(&path)?;
+            let mut reader = buffer::reader(ChecksumReader::new(&file, &path)?);
+            let len = file.metadata()?.len();
+            let mut batch = Vec::with_capacity(GAME_BATCH_SIZE);
+
+            while reader.stream_position()? < len {
+                let offset = reader.stream_position()?;
+                let game = Stoatpack::deserialise(&mut reader).at_offset(&path, offset)?;
+                progress::record(&path);
+                batch.push(expand(game, eval_limit));
+
+                // A closed channel means the consumer gave up, which it reports itself
+                if batch.len() == GAME_BATCH_SIZE && sender.send(mem::take(&mut batch)).is_err() {
+                    return Ok(());
+                }
+            }
+
+            if !batch.is_empty() {
+                let _ = sender.send(batch);
+            }
+
+            Ok(())
+        });
+
+        Self {
+            receiver,
+            handle: Some(handle),
+        }
+    }
+
+    /// The next batch of games, or `None` once the file is done
+    pub fn next_batch(&mut self) -> Result<Option<Vec<Game>>> {
+        if let Ok(batch) = self.receiver.recv() {
+            return Ok(Some(batch));
+        }
+
+        // The thread hangs up when the file is done or it failed, which joining surfaces
+        if let Some(handle) = self.handle.take() {
+            handle
+                .join()
+                .map_err(|_| Error::other("Reader thread panicked"))??;
+        }
+
+        Ok(None)
+    }
+}
+
+fn expand(game: Stoatpack, eval_limit: i16) -> Game {
+    let mut positions = Vec::new();
+    let mut pos = game.startpos;
+
+    for (ply, (mv, score)) in game.moves.into_iter().enumerate() {
+        let next = pos.apply_move(mv);
+
+        if score.abs() <= eval_limit {
+            positions.push((ply, pos, score));
+        }
+
+        pos = next;
+    }
+
+    Game {
+        result: sente_result(game.wdl),
+        positions,
+    }
+}
+
+struct OwnedRow {
+    game_id: u64,
+    ply: usize,
+    pos: Position,
+    score: i16,
+    result: f32,
+}
+
+type WriterThread = JoinHandle<Result<Box<dyn PositionWriter + Send>>>;
+
+/// Hands rows to a dedicated thread that serialises and writes them, so decoding the next
+/// records overlaps with output. Rows travel in batches through a bounded channel, which
+/// only blocks the reader once the writer falls `QUEUE_DEPTH` batches behind.
+pub struct PipelinedWriter {
+    batch: Vec<OwnedRow>,
+    sender: Option<SyncSender<Vec<OwnedRow>>>,
+    handle: Option<WriterThread>,
+}
+
+impl PipelinedWriter {
+    pub fn new(mut inner: Box<dyn PositionWriter + Send>) -> Self {
+        let (sender, receiver) = sync_channel::<Vec<OwnedRow>>(QUEUE_DEPTH);
+        let handle = thread::spawn(move || {
+            for batch in receiver {
+                for row in &batch {
+                    inner.write(&Row {
+                        game_id: row.game_id,
+                        ply: row.ply,
+                        pos: &row.pos,
+                        score: row.score,
+                        result: row.result,
+                    })?;
+                }
+            }
+
+            Ok(inner)
+        });
+
+        Self {
+            batch: Vec::with_capacity(BATCH_SIZE),
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+
+    fn send_batch(&mut self) -> Result<()> {
+        let batch = mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+        let sender = self.sender.as_ref().unwrap();
+
+        if sender.send(batch).is_err() {
+            // The writer thread only hangs up after an error, which joining surfaces
+            self.join()?;
+            return Err(Error::other("Writer thread stopped unexpectedly"));
+        }
+
+        Ok(())
+    }
+
+    fn join(&mut self) -> Result<Box<dyn PositionWriter + Send>> {
+        drop(self.sender.take());
+
+        self.handle
+            .take()
+            .unwrap()
+            .join()
+            .map_err(|_| Error::other("Writer thread panicked"))?
+    }
+}
+
+impl PositionWriter for PipelinedWriter {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        self.batch.push(OwnedRow {
+            game_id: row.game_id,
+            ply: row.ply,
+            pos: row.pos.clone(),
+            score: row.score,
+            result: row.result,
+        });
+
+        if self.batch.len() >= BATCH_SIZE {
+            self.send_batch()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        if !self.batch.is_empty() {
+            self.send_batch()?;
+        }
+
+        self.join()?.finish()
+    }
+}
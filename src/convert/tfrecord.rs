@@ -0,0 +1,151 @@
+use {
+    super::{Blend, PositionWriter, Row},
+    crate::{buffer, error::Result, features::FeatureSet},
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::Path,
+    },
+};
+
+/// CRC-32C (Castagnoli), which TFRecord framing uses rather than the zlib polynomial
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut idx = 0;
+
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[idx] = crc;
+        idx += 1;
+    }
+
+    table
+};
+
+/// Writes one `tf.train.Example` per position, framed as a TFRecord file. Each example holds
+/// `features` (the active PSQ feature indices, as in npz), `score` and `result` from the side
+/// to move's perspective, and `target` when `--lambda` is given.
+pub struct TfRecordWriter {
+    writer: BufWriter<File>,
+    blend: Option<Blend>,
+}
+
+impl TfRecordWriter {
+    pub fn create(path: &Path, blend: Option<Blend>) -> Result<Self> {
+        Ok(Self {
+            writer: buffer::writer(File::create(path)?),
+            blend,
+        })
+    }
+}
+
+impl PositionWriter for TfRecordWriter {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        let features = FeatureSet::Psq
+            .active(row.pos, row.pos.stm())
+            .into_iter()
+            .map(i64::from)
+            .collect::<Vec<_>>();
+        let mut map = Vec::new();
+
+        map_entry(&mut map, "features", &int64_list(&features));
+        map_entry(&mut map, "score", &int64_list(&[row.stm_score() as i64]));
+        map_entry(&mut map, "result", &float_list(&[row.stm_result_value()]));
+
+        if let Some(blend) = self.blend {
+            map_entry(&mut map, "target", &float_list(&[row.target(blend)]));
+        }
+
+        // Example.features is field 1, holding the map above
+        let mut example = Vec::new();
+        length_delimited(&mut example, 1, &map);
+
+        let length = (example.len() as u64).to_le_bytes();
+        self.writer.write_all(&length)?;
+        self.writer.write_all(&masked_crc(&length).to_le_bytes())?;
+        self.writer.write_all(&example)?;
+        self.writer.write_all(&masked_crc(&example).to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// One entry of the `Features.feature` map: key is field 1, value field 2
+fn map_entry(buffer: &mut Vec<u8>, key: &str, feature: &[u8]) {
+    let mut entry = Vec::new();
+    length_delimited(&mut entry, 1, key.as_bytes());
+    length_delimited(&mut entry, 2, feature);
+
+    length_delimited(buffer, 1, &entry);
+}
+
+/// A `Feature` holding an `Int64List` (field 3) of packed varints
+fn int64_list(values: &[i64]) -> Vec<u8> {
+    let mut packed = Vec::new();
+
+    for &value in values {
+        varint(&mut packed, value as u64);
+    }
+
+    let mut list = Vec::new();
+    length_delimited(&mut list, 1, &packed);
+
+    let mut feature = Vec::new();
+    length_delimited(&mut feature, 3, &list);
+
+    feature
+}
+
+/// A `Feature` holding a `FloatList` (field 2) of packed little-endian floats
+fn float_list(values: &[f32]) -> Vec<u8> {
+    let packed = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect::<Vec<_>>();
+
+    let mut list = Vec::new();
+    length_delimited(&mut list, 1, &packed);
+
+    let mut feature = Vec::new();
+    length_delimited(&mut feature, 2, &list);
+
+    feature
+}
+
+fn length_delimited(buffer: &mut Vec<u8>, field: u64, data: &[u8]) {
+    varint(buffer, field << 3 | 2);
+    varint(buffer, data.len() as u64);
+    buffer.extend_from_slice(data);
+}
+
+fn varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    buffer.push(value as u8);
+}
+
+fn masked_crc(data: &[u8]) -> u32 {
+    let crc = !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    });
+
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
@@ -0,0 +1,65 @@
+use {
+    super::{Blend, PositionWriter, Row},
+    crate::{buffer, error::Result},
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::Path,
+    },
+};
+
+const HEADER: &str = "sfen,win_probability,result";
+const BLENDED_HEADER: &str = "sfen,target";
+
+/// Writes one CSV line per position with the score turned into a win probability, both that
+/// and the result (0, 0.5 or 1, or a blend after `--dedupe average`) from the side to move's
+/// perspective. With `--lambda` the two are blended into a single target column instead.
+pub struct WdlWriter {
+    writer: BufWriter<File>,
+    scale: f64,
+    blend: Option<Blend>,
+}
+
+impl WdlWriter {
+    pub fn create(path: &Path, scale: f64, blend: Option<Blend>) -> Result<Self> {
+        let mut writer = buffer::writer(File::create(path)?);
+        writeln!(
+            writer,
+            "{}",
+            if blend.is_some() {
+                BLENDED_HEADER
+            } else {
+                HEADER
+            }
+        )?;
+
+        Ok(Self {
+            writer,
+            scale,
+            blend,
+        })
+    }
+}
+
+impl PositionWriter for WdlWriter {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        if let Some(blend) = self.blend {
+            writeln!(self.writer, "{},{:.6}", row.pos.sfen(), row.target(blend))?;
+            return Ok(());
+        }
+
+        writeln!(
+            self.writer,
+            "{},{:.6},{}",
+            row.pos.sfen(),
+            row.win_probability(self.scale),
+            row.stm_result_value()
+        )?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
@@ -0,0 +1,204 @@
+use {
+    crate::{
+        BuildDatasetArgs, buffer,
+        checksum::ChecksumReader,
+        error::{Context, Error, Result},
+        expand_path, is_reverse,
+    },
+    rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom},
+    serde::Deserialize,
+    std::{
+        fs::{File, OpenOptions, create_dir_all, read_to_string},
+        io::{Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+    },
+    stoatformat::stoatpack::Stoatpack,
+};
+
+/// A dataset recipe: each `[[source]]` contributes roughly `weight / total weight` of
+/// `target_positions`, after its own filters are applied.
+#[derive(Deserialize, Debug)]
+struct Recipe {
+    target_positions: usize,
+    #[serde(default = "default_seed")]
+    seed: u64,
+    #[serde(rename = "source")]
+    sources: Vec<Source>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Source {
+    path: PathBuf,
+    #[serde(default = "default_weight")]
+    weight: f64,
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    drop_reverses: bool,
+    #[serde(default = "default_eval_limit")]
+    eval_limit: i16,
+}
+
+/// Inputs kept open at once while copying games out, well below common descriptor limits
+const MAX_OPEN_FILES: usize = 64;
+
+struct GameRef {
+    file: usize,
+    offset: u64,
+    size: usize,
+    positions: usize,
+}
+
+fn default_seed() -> u64 {
+    42
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_eval_limit() -> i16 {
+    25001
+}
+
+pub fn build(args: &BuildDatasetArgs) -> Result<()> {
+    let recipe: Recipe = toml::from_str(&read_to_string(&args.recipe)?).map_err(Error::other)?;
+    let total_weight = recipe
+        .sources
+        .iter()
+        .map(|source| source.weight)
+        .sum::<f64>();
+
+    // Weights are shares of the target, which only make sense when they add up to something
+    if recipe
+        .sources
+        .iter()
+        .any(|source| !source.weight.is_finite() || source.weight < 0.0)
+        || total_weight <= 0.0
+    {
+        return Err(Error::other(format!(
+            "Invalid recipe: {}, weights must be non-negative with a positive total",
+            args.recipe.display()
+        )));
+    }
+
+    let mut rng = SmallRng::seed_from_u64(recipe.seed);
+    let mut files = Vec::new();
+    let mut selected = Vec::new();
+    let mut total_positions = 0;
+
+    for source in &recipe.sources {
+        let target =
+            (recipe.target_positions as f64 * source.weight / total_weight).round() as usize;
+        let mut paths = expand_path(&source.path, source.recursive)?
+            .into_iter()
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("spk"))
+            .collect::<Vec<_>>();
+        // Directory order is not stable, so sort to keep builds reproducible
+        paths.sort();
+
+        let mut games = Vec::new();
+
+        for path in paths {
+            scan(&path, files.len(), source, &mut games)?;
+            files.push(path);
+        }
+
+        games.shuffle(&mut rng);
+
+        let mut positions = 0;
+        let mut taken = 0;
+
+        for game in games {
+            if positions >= target {
+                break;
+            }
+
+            positions += game.positions;
+            taken += 1;
+            selected.push(game);
+        }
+
+        println!(
+            "Source: {}, {} games, {} of {} positions",
+            source.path.display(),
+            taken,
+            positions,
+            target
+        );
+
+        total_positions += positions;
+    }
+
+    selected.shuffle(&mut rng);
+    create_dir_all(&args.output)?;
+
+    let output = args.output.join("dataset.spk");
+    let mut writer = buffer::writer(File::create(&output)?);
+    // Games come from all over the inputs, so open files are reused up to a limit, oldest
+    // closed first
+    let mut readers: Vec<(usize, File)> = Vec::with_capacity(MAX_OPEN_FILES);
+    let mut game_buffer = Vec::new();
+
+    for game in &selected {
+        let reader = match readers.iter().position(|&(file, _)| file == game.file) {
+            Some(idx) => &mut readers[idx].1,
+            None => {
+                if readers.len() == MAX_OPEN_FILES {
+                    readers.remove(0);
+                }
+
+                readers.push((game.file, File::open(&files[game.file])?));
+                &mut readers.last_mut().unwrap().1
+            }
+        };
+
+        game_buffer.resize(game.size, 0);
+        reader.seek(SeekFrom::Start(game.offset))?;
+        reader.read_exact(&mut game_buffer)?;
+        writer.write_all(&game_buffer)?;
+    }
+
+    writer.flush()?;
+
+    println!("-------------------------------------");
+    println!("               Summary               ");
+    println!("-------------------------------------");
+    println!("Total games    : {}", selected.len());
+    println!("Total positions: {}", total_positions);
+    println!("Output: {}", output.display());
+
+    Ok(())
+}
+
+fn scan(path: &Path, file: usize, source: &Source, games: &mut Vec<GameRef>) -> Result<()> {
+    let handle = OpenOptions::new().read(true).open(path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&handle, path)?);
+    let len = handle.metadata()?.len();
+    let mut prev_pos = 0;
+
+    while reader.stream_position()? < len {
+        let game = Stoatpack::deserialise(&mut reader).at_offset(path, prev_pos)?;
+        let curr_pos = reader.stream_position()?;
+
+        if !(source.drop_reverses && is_reverse(&game, source.eval_limit)) {
+            games.push(GameRef {
+                file,
+                offset: prev_pos,
+                size: (curr_pos - prev_pos) as usize,
+                positions: game
+                    .moves
+                    .iter()
+                    .filter(|(_, score)| score.abs() <= source.eval_limit)
+                    .count()
+                    + 1,
+            });
+        }
+
+        prev_pos = curr_pos;
+    }
+
+    println!("  OK  : {}, {} games", path.display(), games.len());
+
+    Ok(())
+}
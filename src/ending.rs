@@ -0,0 +1,132 @@
+use {
+    crate::{
+        buffer,
+        checksum::ChecksumReader,
+        error::{Context, Result},
+    },
+    std::{collections::HashMap, fs::OpenOptions, io::Seek, path::PathBuf},
+    stoatformat::{Outcome, shogi::position::Position, stoatpack::Stoatpack},
+};
+
+pub const NAMES: [&str; 6] = [
+    "Checkmate",
+    "Eval resign",
+    "Repetition",
+    "Max-ply cap",
+    "Draw adjudication",
+    "Other",
+];
+
+/// A decisive game whose last score favours the winner by at least this much counts as resigned
+const RESIGN_SCORE: i16 = 1000;
+/// A draw whose last score is at most this far from zero counts as adjudicated
+const DRAW_SCORE: i16 = 100;
+
+/// How games ended, judged from the final position and the score trajectory.
+#[derive(Default)]
+pub struct Endings {
+    checkmates: u64,
+    resigns: u64,
+    repetitions: u64,
+    other_decisive: u64,
+    /// Draws not explained by repetition per game length, split into those ending near a zero
+    /// score and the rest, so the max-ply cap can be inferred once every file has been read
+    draw_lengths: HashMap<usize, [u64; 2]>,
+}
+
+impl Endings {
+    /// Classifies one game from its final position and the keys of every position it passed.
+    pub fn record(&mut self, game: &Stoatpack, last: &Position, keys: &[u64]) {
+        let last_score = game.moves.last().map_or(0, |&(_, score)| score);
+
+        if last.legal_moves().is_empty() {
+            self.checkmates += 1;
+            return;
+        }
+
+        // The final position having appeared before means the game stopped on a repetition
+        if keys[..keys.len() - 1].contains(&last.key()) {
+            self.repetitions += 1;
+            return;
+        }
+
+        match game.wdl {
+            Outcome::SenteWin if last_score >= RESIGN_SCORE => self.resigns += 1,
+            Outcome::SenteLoss if last_score <= -RESIGN_SCORE => self.resigns += 1,
+            Outcome::SenteWin | Outcome::SenteLoss => self.other_decisive += 1,
+            Outcome::Draw => {
+                let lengths = self.draw_lengths.entry(game.moves.len()).or_default();
+                lengths[usize::from(last_score.abs() > DRAW_SCORE)] += 1;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.checkmates += other.checkmates;
+        self.resigns += other.resigns;
+        self.repetitions += other.repetitions;
+        self.other_decisive += other.other_decisive;
+
+        for (&length, counts) in &other.draw_lengths {
+            let entry = self.draw_lengths.entry(length).or_default();
+            entry[0] += counts[0];
+            entry[1] += counts[1];
+        }
+    }
+
+    /// The max-ply cap: `max_ply` if given, otherwise the longest unexplained draw.
+    pub fn cap(&self, max_ply: Option<usize>) -> Option<usize> {
+        max_ply.or_else(|| self.draw_lengths.keys().max().copied())
+    }
+
+    /// Counts per ending in [`NAMES`] order.
+    pub fn counts(&self, max_ply: Option<usize>) -> [u64; NAMES.len()] {
+        let cap = self.cap(max_ply);
+        let mut counts = [
+            self.checkmates,
+            self.resigns,
+            self.repetitions,
+            0,
+            0,
+            self.other_decisive,
+        ];
+
+        for (&length, &[near_zero, rest]) in &self.draw_lengths {
+            if cap.is_some_and(|cap| length >= cap) {
+                counts[3] += near_zero + rest;
+            } else {
+                counts[4] += near_zero;
+                counts[5] += rest;
+            }
+        }
+
+        counts
+    }
+}
+
+/// Whether a game looks cut off by the datagen move cap rather than drawn on the board.
+pub fn is_capped(game: &Stoatpack, cap: usize) -> bool {
+    game.wdl == Outcome::Draw && game.moves.len() >= cap
+}
+
+/// Infers the move cap as the length of the longest drawn game across `paths`.
+pub fn longest_draw(paths: &[PathBuf]) -> Result<Option<usize>> {
+    let mut longest = None;
+
+    for path in paths {
+        let file = OpenOptions::new().read(true).open(path)?;
+        let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+        let len = file.metadata()?.len();
+
+        while reader.stream_position()? < len {
+            let offset = reader.stream_position()?;
+            let game = Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+
+            if game.wdl == Outcome::Draw {
+                longest = longest.max(Some(game.moves.len()));
+            }
+        }
+    }
+
+    Ok(longest)
+}
@@ -0,0 +1,134 @@
+use std::{
+    fmt::{self, Debug, Display},
+    io,
+    path::{Path, PathBuf},
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub enum Error {
+    /// An I/O failure, naming the file it happened on once that is known
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// A record that could not be decoded, at its byte offset in the file
+    Parse {
+        path: PathBuf,
+        offset: u64,
+        source: io::Error,
+    },
+    /// A move that is not legal in its position
+    InvalidMove {
+        path: PathBuf,
+        game: usize,
+        ply: usize,
+        mv: String,
+    },
+    Other(String),
+}
+
+impl Error {
+    pub fn other<E: Display>(message: E) -> Self {
+        Self::Other(message.to_string())
+    }
+
+    /// Names the file an I/O failure happened on, unless it already names one.
+    pub fn in_file(self, file: &Path) -> Self {
+        match self {
+            Self::Io { path: None, source } => Self::Io {
+                path: Some(file.to_path_buf()),
+                source,
+            },
+            other => other,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path: None, source } => write!(f, "{}", source),
+            Self::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {}", path.display(), source),
+            Self::Parse {
+                path,
+                offset,
+                source,
+            } => write!(
+                f,
+                "{}: broken record at offset {}: {}",
+                path.display(),
+                offset,
+                source
+            ),
+            Self::InvalidMove {
+                path,
+                game,
+                ply,
+                mv,
+            } => write!(
+                f,
+                "{}: illegal move {} in game {} at ply {}",
+                path.display(),
+                mv,
+                game,
+                ply
+            ),
+            Self::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// `main` prints the error it returns with Debug, so keep that readable too
+impl Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } | Self::Parse { source, .. } => Some(source),
+            Self::InvalidMove { .. } | Self::Other(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        // Errors that already went through an io::Error on their way up keep their structure
+        if source.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return *source.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
+
+        Self::Io { path: None, source }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io { path: None, source } => source,
+            error => io::Error::other(error),
+        }
+    }
+}
+
+/// Attaches where a failed read happened.
+pub trait Context<T> {
+    fn at_offset(self, path: &Path, offset: u64) -> Result<T>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn at_offset(self, path: &Path, offset: u64) -> Result<T> {
+        self.map_err(|source| Error::Parse {
+            path: path.to_path_buf(),
+            offset,
+            source,
+        })
+    }
+}
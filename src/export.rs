@@ -0,0 +1,119 @@
+mod csv;
+mod jsonl;
+mod ki2;
+mod sqlite;
+
+use {
+    crate::{
+        buffer,
+        checksum::ChecksumReader,
+        error::{Context, Result},
+        progress,
+    },
+    clap::ValueEnum,
+    std::{fs::OpenOptions, io::Seek, path::Path},
+    stoatformat::{Outcome, stoatpack::Stoatpack},
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+    Csv,
+    Sqlite,
+    Ki2,
+    Jsonl,
+}
+
+pub struct GameRecord<'a> {
+    pub game_id: u64,
+    pub path: &'a Path,
+    pub index: usize,
+    pub offset: u64,
+    pub game: &'a Stoatpack,
+}
+
+impl GameRecord<'_> {
+    pub fn min_eval(&self) -> Option<i16> {
+        self.game.moves.iter().map(|(_, score)| *score).min()
+    }
+
+    pub fn max_eval(&self) -> Option<i16> {
+        self.game.moves.iter().map(|(_, score)| *score).max()
+    }
+
+    pub fn final_eval(&self) -> Option<i16> {
+        self.game.moves.last().map(|(_, score)| *score)
+    }
+
+    pub fn drops(&self) -> usize {
+        self.count_moves(|usi| usi.contains('*'))
+    }
+
+    pub fn promotions(&self) -> usize {
+        self.count_moves(|usi| usi.ends_with('+'))
+    }
+
+    fn count_moves(&self, predicate: impl Fn(&str) -> bool) -> usize {
+        self.game
+            .moves
+            .iter()
+            .filter(|(mv, _)| predicate(&mv.to_string()))
+            .count()
+    }
+}
+
+pub trait GameWriter {
+    fn write(&mut self, record: &GameRecord) -> Result<()>;
+
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+pub fn create(format: Format, path: &Path) -> Result<Box<dyn GameWriter>> {
+    match format {
+        Format::Csv => Ok(Box::new(csv::CsvWriter::create(path)?)),
+        Format::Sqlite => Ok(Box::new(sqlite::SqliteWriter::create(path)?)),
+        Format::Ki2 => Ok(Box::new(ki2::Ki2Writer::create(path)?)),
+        Format::Jsonl => Ok(Box::new(jsonl::JsonlWriter::create(path)?)),
+    }
+}
+
+pub fn export(path: &Path, writer: &mut dyn GameWriter, game_id: &mut u64) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+    let len = file.metadata()?.len();
+    let mut games = 0;
+
+    while reader.stream_position()? < len {
+        let offset = reader.stream_position()?;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+        progress::record(path);
+
+        writer.write(&GameRecord {
+            game_id: *game_id,
+            path,
+            index: games,
+            offset,
+            game: &game,
+        })?;
+
+        *game_id += 1;
+        games += 1;
+    }
+
+    println!("  OK  : {}, {} games", path.display(), games);
+
+    Ok(games)
+}
+
+pub fn outcome_name(wdl: Outcome) -> &'static str {
+    match wdl {
+        Outcome::SenteWin => "sente_win",
+        Outcome::SenteLoss => "sente_loss",
+        Outcome::Draw => "draw",
+    }
+}
+
+pub fn parse_outcome(name: &str) -> Option<Outcome> {
+    [Outcome::SenteWin, Outcome::SenteLoss, Outcome::Draw]
+        .into_iter()
+        .find(|&wdl| outcome_name(wdl) == name)
+}
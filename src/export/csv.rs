@@ -0,0 +1,63 @@
+use {
+    super::{GameRecord, GameWriter, outcome_name},
+    crate::buffer,
+    crate::error::Result,
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::Path,
+    },
+};
+
+const HEADER: &str =
+    "index,plies,outcome,min_eval,max_eval,final_eval,drops,promotions,file,offset";
+
+pub struct CsvWriter {
+    writer: BufWriter<File>,
+}
+
+impl CsvWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut writer = buffer::writer(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+
+        Ok(Self { writer })
+    }
+}
+
+impl GameWriter for CsvWriter {
+    fn write(&mut self, record: &GameRecord) -> Result<()> {
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{},{},{}",
+            record.index,
+            record.game.moves.len(),
+            outcome_name(record.game.wdl),
+            optional(record.min_eval()),
+            optional(record.max_eval()),
+            optional(record.final_eval()),
+            record.drops(),
+            record.promotions(),
+            quote(&record.path.display().to_string()),
+            record.offset
+        )?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+fn optional(value: Option<i16>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
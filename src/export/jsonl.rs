@@ -0,0 +1,56 @@
+use {
+    super::{GameRecord, GameWriter, outcome_name},
+    crate::buffer,
+    crate::error::{Error, Result},
+    serde_json::json,
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::Path,
+    },
+};
+
+/// One JSON object per line holding everything needed to rebuild the game, plus where it
+/// came from under `metadata`.
+pub struct JsonlWriter {
+    writer: BufWriter<File>,
+}
+
+impl JsonlWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            writer: buffer::writer(File::create(path)?),
+        })
+    }
+}
+
+impl GameWriter for JsonlWriter {
+    fn write(&mut self, record: &GameRecord) -> Result<()> {
+        let game = record.game;
+        let line = json!({
+            "start_sfen": game.startpos.sfen(),
+            "moves": game.moves.iter().map(|(mv, _)| mv.to_string()).collect::<Vec<_>>(),
+            "scores": game.moves.iter().map(|&(_, score)| score).collect::<Vec<_>>(),
+            "outcome": outcome_name(game.wdl),
+            "metadata": {
+                "game_id": record.game_id,
+                "file": record.path.display().to_string(),
+                "index": record.index,
+                "offset": record.offset,
+                "plies": game.moves.len(),
+            },
+        });
+
+        writeln!(
+            self.writer,
+            "{}",
+            serde_json::to_string(&line).map_err(Error::other)?
+        )?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
@@ -0,0 +1,225 @@
+use {
+    super::{GameRecord, GameWriter},
+    crate::{
+        buffer,
+        error::Result,
+        show::{self, FILES, NUMERALS},
+    },
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::Path,
+    },
+    stoatformat::{
+        Outcome,
+        shogi::{
+            core::{Color, Move},
+            position::Position,
+        },
+    },
+};
+
+const STANDARD_SFEN: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b -";
+const MOVES_PER_LINE: usize = 6;
+
+/// Piece names as written in moves, in [`show::PIECES`] order
+const NAMES: [&str; 14] = [
+    "歩", "香", "桂", "銀", "金", "角", "飛", "と", "成香", "成桂", "成銀", "馬", "龍", "玉",
+];
+
+/// Writes every game as KI2, separated by a blank line and a `#` comment naming its source.
+/// Games from other start positions get a BOD board diagram instead of a handicap line.
+pub struct Ki2Writer {
+    writer: BufWriter<File>,
+}
+
+impl Ki2Writer {
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            writer: buffer::writer(File::create(path)?),
+        })
+    }
+}
+
+impl GameWriter for Ki2Writer {
+    fn write(&mut self, record: &GameRecord) -> Result<()> {
+        let game = record.game;
+        let mut pos = game.startpos.clone();
+        let mut previous = None;
+        let mut line = Vec::new();
+
+        writeln!(
+            self.writer,
+            "# {} game {}",
+            record.path.display(),
+            record.index
+        )?;
+
+        if pos.sfen().starts_with(STANDARD_SFEN) {
+            writeln!(self.writer, "手合割：平手")?;
+        } else {
+            writeln!(self.writer, "{}", show::render(&pos, None, false))?;
+
+            if pos.stm() == Color::GOTE {
+                writeln!(self.writer, "後手番")?;
+            }
+        }
+
+        for &(mv, _) in &game.moves {
+            line.push(notation(&pos, mv, previous));
+            previous = show::destination(&mv.to_string());
+            pos = pos.apply_move(mv);
+
+            if line.len() == MOVES_PER_LINE {
+                writeln!(self.writer, "{}", line.join("    "))?;
+                line.clear();
+            }
+        }
+
+        if !line.is_empty() {
+            writeln!(self.writer, "{}", line.join("    "))?;
+        }
+
+        let result = match game.wdl {
+            Outcome::SenteWin => "先手の勝ち",
+            Outcome::SenteLoss => "後手の勝ち",
+            Outcome::Draw => "引き分け",
+        };
+
+        writeln!(self.writer, "まで{}手で{}\n", game.moves.len(), result)?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Writes one move as KI2, adding 右/左/直 and 上/引/寄 when another piece of the same kind
+/// could reach the same square, and 打 when a drop could be mistaken for a board move.
+fn notation(pos: &Position, mv: Move, previous: Option<usize>) -> String {
+    let usi = mv.to_string();
+    let mark = if pos.stm() == Color::SENTE {
+        "▲"
+    } else {
+        "△"
+    };
+    let Some(to) = show::destination(&usi) else {
+        return format!("{}{}", mark, usi);
+    };
+    let board = show::board(pos);
+    let legal = pos.legal_moves();
+    let from = show::square(&usi);
+    let piece = match from {
+        Some(from) => board[from].map(|(_, piece)| piece),
+        None => show::PIECES[..7]
+            .iter()
+            .position(|(_, _, letter)| usi.starts_with(letter)),
+    };
+    let Some(piece) = piece else {
+        return format!("{}{}", mark, usi);
+    };
+
+    // Squares of the other pieces of this kind that could also move to `to`
+    let others = legal
+        .iter()
+        .filter_map(|other| {
+            let other = other.to_string();
+            let other_from = show::square(&other)?;
+
+            (show::destination(&other) == Some(to)
+                && Some(other_from) != from
+                && board[other_from] == Some((pos.stm(), piece)))
+            .then_some(other_from)
+        })
+        .collect::<Vec<_>>();
+    let mut text = String::from(mark);
+
+    if previous == Some(to) {
+        text.push('同');
+
+        if NAMES[piece].chars().count() == 1 {
+            text.push('\u{3000}');
+        }
+    } else {
+        text.push_str(FILES[to % 9]);
+        text.push_str(NUMERALS[8 - to / 9]);
+    }
+
+    text.push_str(NAMES[piece]);
+
+    match from {
+        None if !others.is_empty() => text.push('打'),
+        None => {}
+        Some(from) => {
+            text.push_str(&disambiguation(pos.stm(), piece, from, to, &others));
+
+            if usi.ends_with('+') {
+                text.push('成');
+            } else if legal
+                .iter()
+                .any(|other| other.to_string() == format!("{}+", usi))
+            {
+                text.push_str("不成");
+            }
+        }
+    }
+
+    text
+}
+
+fn disambiguation(stm: Color, piece: usize, from: usize, to: usize, others: &[usize]) -> String {
+    if others.is_empty() {
+        return String::new();
+    }
+
+    // Files and ranks from the mover's side, larger meaning further right and further forward
+    let relative = |square: usize| {
+        let (file, rank) = ((square % 9) as i32, (square / 9) as i32);
+
+        if stm == Color::SENTE {
+            (file, rank)
+        } else {
+            (8 - file, 8 - rank)
+        }
+    };
+    let vertical = |square: usize| match relative(to).1.cmp(&relative(square).1) {
+        std::cmp::Ordering::Greater => '上',
+        std::cmp::Ordering::Less => '引',
+        std::cmp::Ordering::Equal => '寄',
+    };
+
+    if others
+        .iter()
+        .all(|&other| vertical(other) != vertical(from))
+    {
+        return vertical(from).to_string();
+    }
+
+    let group = others
+        .iter()
+        .copied()
+        .filter(|&other| vertical(other) == vertical(from))
+        .collect::<Vec<_>>();
+    let file = relative(from).0;
+    // Rooks, bishops and their promotions never take 直
+    let straight = relative(from).0 == relative(to).0
+        && vertical(from) == '上'
+        && ![5, 6, 11, 12].contains(&piece);
+    let horizontal = if straight {
+        "直"
+    } else if group.iter().all(|&other| relative(other).0 < file) {
+        "右"
+    } else if group.iter().all(|&other| relative(other).0 > file) {
+        "左"
+    } else {
+        ""
+    };
+
+    if group.len() == others.len() || straight {
+        horizontal.to_string()
+    } else {
+        format!("{}{}", horizontal, vertical(from))
+    }
+}
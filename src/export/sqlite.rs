@@ -0,0 +1,70 @@
+use {
+    super::{GameRecord, GameWriter, outcome_name},
+    crate::error::{Error, Result},
+    rusqlite::{Connection, params},
+    std::{fs::remove_file, path::Path},
+};
+
+const SCHEMA: &str = "CREATE TABLE games (
+    id INTEGER PRIMARY KEY,
+    file TEXT NOT NULL,
+    game_index INTEGER NOT NULL,
+    offset INTEGER NOT NULL,
+    length INTEGER NOT NULL,
+    outcome TEXT NOT NULL,
+    start_sfen TEXT NOT NULL,
+    min_eval INTEGER,
+    max_eval INTEGER
+);";
+
+pub struct SqliteWriter {
+    connection: Connection,
+}
+
+impl SqliteWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            remove_file(path)?;
+        }
+
+        let connection = Connection::open(path).map_err(Error::other)?;
+        connection.execute_batch(SCHEMA).map_err(Error::other)?;
+        connection.execute_batch("BEGIN;").map_err(Error::other)?;
+
+        Ok(Self { connection })
+    }
+}
+
+impl GameWriter for SqliteWriter {
+    fn write(&mut self, record: &GameRecord) -> Result<()> {
+        self.connection
+            .prepare_cached(
+                "INSERT INTO games (id, file, game_index, offset, length, outcome, start_sfen, min_eval, max_eval)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )
+            .and_then(|mut statement| {
+                statement.execute(params![
+                    record.game_id as i64,
+                    record.path.display().to_string(),
+                    record.index as i64,
+                    record.offset as i64,
+                    record.game.moves.len() as i64,
+                    outcome_name(record.game.wdl),
+                    record.game.startpos.sfen(),
+                    record.min_eval(),
+                    record.max_eval(),
+                ])
+            })
+            .map_err(Error::other)?;
+
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.connection
+            .execute_batch("COMMIT;")
+            .map_err(Error::other)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use {
+    crate::{
+        buffer,
+        convert::{PositionWriter, Row},
+        error::Result,
+        relative_square,
+    },
+    clap::ValueEnum,
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::Path,
+    },
+    stoatformat::shogi::{
+        core::{Color, PieceType},
+        position::Position,
+    },
+};
+
+pub const MAX_ACTIVE: usize = 40;
+
+const MAGIC: &[u8; 4] = b"SPKF";
+const VERSION: u32 = 1;
+
+pub const PIECE_TYPES: [PieceType; 14] = [
+    PieceType::PAWN,
+    PieceType::LANCE,
+    PieceType::KNIGHT,
+    PieceType::SILVER,
+    PieceType::GOLD,
+    PieceType::BISHOP,
+    PieceType::ROOK,
+    PieceType::PROMOTED_PAWN,
+    PieceType::PROMOTED_LANCE,
+    PieceType::PROMOTED_KNIGHT,
+    PieceType::PROMOTED_SILVER,
+    PieceType::PROMOTED_BISHOP,
+    PieceType::PROMOTED_ROOK,
+    PieceType::KING,
+];
+
+pub const HAND_PIECE_TYPES: [(PieceType, usize); 7] = [
+    (PieceType::PAWN, 18),
+    (PieceType::LANCE, 4),
+    (PieceType::KNIGHT, 4),
+    (PieceType::SILVER, 4),
+    (PieceType::GOLD, 4),
+    (PieceType::BISHOP, 2),
+    (PieceType::ROOK, 2),
+];
+
+const BOARD_FEATURES: usize = PIECE_TYPES.len() * 81;
+const HAND_FEATURES: usize = 38;
+const PIECE_FEATURES: usize = 2 * (BOARD_FEATURES + HAND_FEATURES);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FeatureSet {
+    Psq,
+    #[value(name = "halfkp")]
+    HalfKp,
+    #[value(name = "halfka")]
+    HalfKa,
+}
+
+impl FeatureSet {
+    pub fn count(self) -> usize {
+        match self {
+            FeatureSet::Psq => PIECE_FEATURES,
+            FeatureSet::HalfKp | FeatureSet::HalfKa => 81 * PIECE_FEATURES,
+        }
+    }
+
+    /// Active feature indices from `perspective`'s point of view: its own pieces first,
+    /// then the opponent's, with squares rotated for gote and one feature per piece held
+    /// in hand. The king-relative sets bucket these by `perspective`'s king square, and
+    /// HalfKP leaves the kings themselves out.
+    pub fn active(self, pos: &Position, perspective: Color) -> Vec<u32> {
+        let mut features = Vec::with_capacity(MAX_ACTIVE);
+
+        for (side, color) in [perspective, opponent(perspective)].into_iter().enumerate() {
+            for (idx, piece_type) in PIECE_TYPES.iter().enumerate() {
+                if self == FeatureSet::HalfKp && *piece_type == PieceType::KING {
+                    continue;
+                }
+
+                for square in pos.piece_bb(piece_type.with_color(color)) {
+                    let square = relative_square(perspective, square);
+                    features.push((side * BOARD_FEATURES + idx * 81 + square.idx()) as u32);
+                }
+            }
+
+            let mut offset = 2 * BOARD_FEATURES + side * HAND_FEATURES;
+
+            for (piece_type, max) in HAND_PIECE_TYPES {
+                let count = (pos.hand(color).count(piece_type) as usize).min(max);
+                features.extend((offset..offset + count).map(|feature| feature as u32));
+                offset += max;
+            }
+        }
+
+        if self != FeatureSet::Psq {
+            let king = pos
+                .piece_bb(PieceType::KING.with_color(perspective))
+                .lsb()
+                .unwrap();
+            let bucket = (relative_square(perspective, king).idx() * PIECE_FEATURES) as u32;
+
+            for feature in &mut features {
+                *feature += bucket;
+            }
+        }
+
+        features
+    }
+}
+
+/// Writes positions as sparse feature indices. The file starts with the magic `SPKF`
+/// followed by the format version, feature set and feature count as little-endian `u32`s.
+/// Each position is then stored as:
+///
+/// - `i16` score from the side to move's perspective
+/// - `u8` result from the side to move's perspective (0 = loss, 1 = draw, 2 = win)
+/// - `u8` number of active features for the side to move, `n`
+/// - `u8` number of active features for the opponent, `m`
+/// - `n + m` little-endian `u32` feature indices, side to move first
+pub struct FeatureWriter {
+    writer: BufWriter<File>,
+    feature_set: FeatureSet,
+}
+
+impl FeatureWriter {
+    pub fn create(path: &Path, feature_set: FeatureSet) -> Result<Self> {
+        let mut writer = buffer::writer(File::create(path)?);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(feature_set as u32).to_le_bytes())?;
+        writer.write_all(&(feature_set.count() as u32).to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            feature_set,
+        })
+    }
+}
+
+impl PositionWriter for FeatureWriter {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        let stm = row.pos.stm();
+        let ours = self.feature_set.active(row.pos, stm);
+        let theirs = self.feature_set.active(row.pos, opponent(stm));
+
+        self.writer.write_all(&row.stm_score().to_le_bytes())?;
+        self.writer
+            .write_all(&[row.stm_result(), ours.len() as u8, theirs.len() as u8])?;
+
+        for feature in ours.into_iter().chain(theirs) {
+            self.writer.write_all(&feature.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+pub fn opponent(color: Color) -> Color {
+    if color == Color::SENTE {
+        Color::GOTE
+    } else {
+        Color::SENTE
+    }
+}
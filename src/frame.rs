@@ -0,0 +1,472 @@
+use {
+    crate::{
+        FrameArgs, TeeReader, buffer,
+        checksum::ChecksumReader,
+        error::{Context, Error, Result},
+    },
+    std::{
+        fs::{File, OpenOptions, rename},
+        io::{self, BufWriter, Read, Write, copy},
+        path::Path,
+    },
+    stoatformat::stoatpack::Stoatpack,
+};
+
+/// Framed files hold each record behind [`MAGIC`] and followed by a little-endian CRC-32 of
+/// its bytes. After damage, reading resumes at the next marker whose frame checks out.
+pub const EXTENSION: &str = "spkc";
+
+const MAGIC: &[u8; 4] = b"SPKC";
+/// Far beyond any real game, so a frame still cut short past this is damage rather than a
+/// record waiting on more bytes
+const MAX_RECORD_LEN: usize = 1 << 20;
+const CRC_LEN: usize = 4;
+const CHUNK: usize = 64 * 1024;
+
+enum Record {
+    /// A whole frame whose record is this many bytes long
+    Valid(usize),
+    /// The record or its CRC runs past the end of the data
+    Truncated,
+    Corrupted,
+}
+
+pub fn is_framed(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some(EXTENSION)
+}
+
+/// Converts between plain `.spk` and framed `.spkc` files, next to the input.
+pub fn convert(path: &Path, args: &FrameArgs) -> Result<usize> {
+    if args.unframe {
+        unframe(path)
+    } else {
+        frame(path)
+    }
+}
+
+fn frame(path: &Path) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = TeeReader::new(buffer::reader(ChecksumReader::new(&file, path)?));
+    let output = path.with_extension(EXTENSION);
+    let mut writer = buffer::writer(File::create(&output)?);
+    let mut records = 0;
+
+    while reader.position < len {
+        let offset = reader.position;
+        Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+
+        let record = reader.take_captured();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&record)?;
+        writer.write_all(&crc32fast::hash(&record).to_le_bytes())?;
+        records += 1;
+    }
+
+    writer.flush()?;
+    println!(
+        "Frame : {} -> {}, {} records",
+        path.display(),
+        output.display(),
+        records
+    );
+
+    Ok(records)
+}
+
+fn unframe(path: &Path) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut frames = Frames::new(buffer::reader(ChecksumReader::new(&file, path)?));
+    let output = path.with_extension("spk");
+    let mut writer = buffer::writer(File::create(&output)?);
+    let mut records = 0;
+
+    while !frames.at_end()? {
+        let Record::Valid(len) = frames.classify()? else {
+            return Err(Error::other(format!(
+                "Broken record at offset {} in {}, run fix first",
+                frames.offset,
+                path.display()
+            )));
+        };
+
+        writer.write_all(&frames.available()[MAGIC.len()..MAGIC.len() + len])?;
+        frames.advance(frame_len(len));
+        records += 1;
+    }
+
+    writer.flush()?;
+    println!(
+        "Frame : {} -> {}, {} records",
+        path.display(),
+        output.display(),
+        records
+    );
+
+    Ok(records)
+}
+
+/// Drops damaged records from a framed file. A broken record at the tail counts as truncated;
+/// anywhere else it is bit-flipped, and reading resumes at the next marker whose frame checks
+/// out. Like `fix` on plain files, the result streams to a temporary file renamed over the input.
+pub fn fix(path: &Path) -> Result<(usize, usize, u64)> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut frames = Frames::new(buffer::reader(ChecksumReader::new(&file, path)?));
+    let tmp_path = path.with_extension("spkc.tmp");
+    let mut writer: Option<BufWriter<File>> = None;
+    let mut records = 0;
+    let mut flipped_records = 0;
+    let mut truncated_bytes = 0;
+    let mut valid_bytes = 0;
+
+    while !frames.at_end()? {
+        match frames.classify()? {
+            Record::Valid(record_len) => {
+                let frame = &frames.available()[..frame_len(record_len)];
+
+                if let Some(writer) = writer.as_mut() {
+                    writer.write_all(frame)?;
+                }
+
+                valid_bytes += frame.len() as u64;
+                records += 1;
+                frames.advance(frame_len(record_len));
+            }
+            damaged => {
+                // Valid frames are only copied out once the first damaged one shows up
+                if writer.is_none() {
+                    let mut tmp = buffer::writer(File::create(&tmp_path)?);
+                    copy(&mut File::open(path)?.take(valid_bytes), &mut tmp)?;
+                    writer = Some(tmp);
+                }
+
+                let damaged_offset = frames.offset;
+
+                if frames.resync()? {
+                    flipped_records += 1;
+                } else if matches!(damaged, Record::Truncated) {
+                    truncated_bytes = len - damaged_offset;
+                } else {
+                    flipped_records += 1;
+                }
+            }
+        }
+    }
+
+    let Some(mut writer) = writer else {
+        println!("  OK  : {}, {} records", path.display(), records);
+        return Ok((records, 0, 0));
+    };
+
+    writer.flush()?;
+    drop(writer);
+    rename(&tmp_path, path)?;
+
+    println!(
+        "Fixed : {}, {} records, {} bit-flipped records, {} truncated bytes",
+        path.display(),
+        records,
+        flipped_records,
+        truncated_bytes
+    );
+
+    Ok((
+        records,
+        flipped_records + usize::from(truncated_bytes != 0),
+        len - valid_bytes,
+    ))
+}
+
+fn frame_len(record_len: usize) -> usize {
+    MAGIC.len() + record_len + CRC_LEN
+}
+
+/// A window onto a framed stream, holding at least one whole frame when there is one, so
+/// files are checked without reading them into memory.
+struct Frames<R> {
+    reader: R,
+    window: Vec<u8>,
+    start: usize,
+    /// File offset of the first available byte
+    offset: u64,
+}
+
+impl<R: Read> Frames<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            window: Vec::new(),
+            start: 0,
+            offset: 0,
+        }
+    }
+
+    fn available(&self) -> &[u8] {
+        &self.window[self.start..]
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        self.start += bytes;
+        self.offset += bytes as u64;
+    }
+
+    /// Reads another chunk, returning false at the end of the stream
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.start > self.window.len() / 2 {
+            self.window.drain(..self.start);
+            self.start = 0;
+        }
+
+        let filled = self.window.len();
+        self.window.resize(filled + CHUNK, 0);
+        let read = self.reader.read(&mut self.window[filled..])?;
+        self.window.truncate(filled + read);
+
+        Ok(read != 0)
+    }
+
+    fn at_end(&mut self) -> io::Result<bool> {
+        Ok(self.available().is_empty() && !self.fill()?)
+    }
+
+    /// Checks the frame at the current offset, reading more while it looks cut short, up to
+    /// one frame of [`MAX_RECORD_LEN`]
+    fn classify(&mut self) -> io::Result<Record> {
+        loop {
+            let record = record_at(self.available());
+
+            if !matches!(record, Record::Truncated) {
+                return Ok(record);
+            }
+
+            if self.available().len() >= frame_len(MAX_RECORD_LEN) {
+                return Ok(Record::Corrupted);
+            }
+
+            if !self.fill()? {
+                return Ok(record);
+            }
+        }
+    }
+
+    /// Skips past a damaged frame to the next marker starting a valid one. Only marker
+    /// positions are tried, and each reads at most one frame of [`MAX_RECORD_LEN`] ahead.
+    /// Returns false once the stream ends without one, with everything consumed.
+    fn resync(&mut self) -> io::Result<bool> {
+        self.advance(1);
+
+        loop {
+            match find_magic(self.available()) {
+                Some(idx) => {
+                    self.advance(idx);
+
+                    if matches!(self.classify()?, Record::Valid(_)) {
+                        return Ok(true);
+                    }
+
+                    self.advance(1);
+                }
+                None => {
+                    // A marker may straddle the chunk boundary
+                    let keep = (MAGIC.len() - 1).min(self.available().len());
+                    self.advance(self.available().len() - keep);
+
+                    if !self.fill()? {
+                        self.advance(keep);
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn find_magic(data: &[u8]) -> Option<usize> {
+    data.windows(MAGIC.len()).position(|window| window == MAGIC)
+}
+
+fn record_at(data: &[u8]) -> Record {
+    if data.len() < MAGIC.len() {
+        return if MAGIC.starts_with(data) {
+            Record::Truncated
+        } else {
+            Record::Corrupted
+        };
+    }
+
+    if &data[..MAGIC.len()] != MAGIC {
+        return Record::Corrupted;
+    }
+
+    let data = &data[MAGIC.len()..];
+    let mut reader = data;
+
+    if Stoatpack::deserialise(&mut reader).is_err() {
+        // Parsing only fails on a clean prefix when it runs out of bytes
+        return if reader.is_empty() {
+            Record::Truncated
+        } else {
+            Record::Corrupted
+        };
+    }
+
+    let len = data.len() - reader.len();
+    let mut crc = [0; CRC_LEN];
+
+    if reader.read_exact(&mut crc).is_err() {
+        return Record::Truncated;
+    }
+
+    if u32::from_le_bytes(crc) == crc32fast::hash(&data[..len]) {
+        Record::Valid(len)
+    } else {
+        Record::Corrupted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        stoatformat::{
+            Outcome,
+            shogi::{core::Move, position::Position},
+        },
+    };
+
+    const STARTPOS: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+    fn record(moves: &[&str]) -> Vec<u8> {
+        let game = Stoatpack {
+            startpos: Position::from_sfen(STARTPOS).unwrap(),
+            wdl: Outcome::Draw,
+            moves: moves
+                .iter()
+                .enumerate()
+                .map(|(idx, mv)| (mv.parse::<Move>().unwrap(), idx as i16))
+                .collect(),
+        };
+        let mut buffer = Vec::new();
+        game.serialise(&mut buffer).unwrap();
+
+        buffer
+    }
+
+    fn framed(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for record in records {
+            data.extend_from_slice(MAGIC);
+            data.extend_from_slice(record);
+            data.extend_from_slice(&crc32fast::hash(record).to_le_bytes());
+        }
+
+        data
+    }
+
+    /// Hands out a byte per read, so every frame and marker straddles a fill
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = buf.len().min(self.0.len()).min(1);
+            buf[..read].copy_from_slice(&self.0[..read]);
+            self.0 = &self.0[read..];
+
+            Ok(read)
+        }
+    }
+
+    /// Walks the frames the way `fix` does, returning the valid records and the damaged
+    /// frames skipped
+    fn recover(reader: impl Read) -> (Vec<Vec<u8>>, usize) {
+        let mut frames = Frames::new(reader);
+        let mut records = Vec::new();
+        let mut damaged = 0;
+
+        while !frames.at_end().unwrap() {
+            match frames.classify().unwrap() {
+                Record::Valid(len) => {
+                    records.push(frames.available()[MAGIC.len()..MAGIC.len() + len].to_vec());
+                    frames.advance(frame_len(len));
+                }
+                _ => {
+                    frames.resync().unwrap();
+                    damaged += 1;
+                }
+            }
+        }
+
+        (records, damaged)
+    }
+
+    fn records() -> Vec<Vec<u8>> {
+        vec![
+            record(&["7g7f", "3c3d"]),
+            record(&["2g2f"]),
+            record(&["7g7f", "8c8d", "2g2f"]),
+        ]
+    }
+
+    #[test]
+    fn classifies_frames() {
+        let records = records();
+        let data = framed(&records[..1]);
+
+        assert!(matches!(record_at(&data), Record::Valid(len) if len == records[0].len()));
+        assert!(matches!(record_at(&data[..2]), Record::Truncated));
+        assert!(matches!(
+            record_at(&data[..data.len() - 1]),
+            Record::Truncated
+        ));
+        assert!(matches!(record_at(b"SPX"), Record::Corrupted));
+
+        let mut flipped = data.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(matches!(record_at(&flipped), Record::Corrupted));
+    }
+
+    #[test]
+    fn clean_frames_read_back() {
+        let records = records();
+        let data = framed(&records);
+
+        assert_eq!(recover(&data[..]), (records.clone(), 0));
+        assert_eq!(recover(Trickle(&data)), (records, 0));
+    }
+
+    #[test]
+    fn resyncs_after_a_damaged_frame() {
+        let records = records();
+        let mut data = framed(&records);
+        // Break the CRC of the middle frame
+        let crc = frame_len(records[0].len()) + frame_len(records[1].len()) - 1;
+        data[crc] ^= 0xff;
+
+        let expected = vec![records[0].clone(), records[2].clone()];
+        assert_eq!(recover(&data[..]), (expected.clone(), 1));
+        assert_eq!(recover(Trickle(&data)), (expected, 1));
+    }
+
+    #[test]
+    fn resyncs_past_garbage_and_false_markers() {
+        let records = records();
+        let mut data = framed(&records[..1]);
+        data.extend_from_slice(b"\x00SPKC\x01\x02SPK");
+        data.extend_from_slice(&framed(&records[1..]));
+
+        assert_eq!(recover(&data[..]), (records.clone(), 1));
+        assert_eq!(recover(Trickle(&data)), (records, 1));
+    }
+
+    #[test]
+    fn truncated_tail_is_dropped() {
+        let records = records();
+        let mut data = framed(&records);
+        data.truncate(data.len() - 3);
+
+        assert_eq!(recover(&data[..]), (records[..2].to_vec(), 1));
+        assert_eq!(recover(Trickle(&data)), (records[..2].to_vec(), 1));
+    }
+}
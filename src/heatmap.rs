@@ -0,0 +1,140 @@
+use {
+    crate::error::Result,
+    std::{fmt::Write as _, fs::File, io::Write, path::Path},
+};
+
+const CELL: usize = 56;
+const MARGIN: usize = 24;
+const TITLE: usize = 32;
+const LEGEND: usize = 56;
+
+pub fn write_svg(path: &Path, title: &str, counts: &[u64; 81]) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(render_svg(title, counts).as_bytes())?;
+
+    Ok(())
+}
+
+/// Writes each heatmap as nine rows of counts, laid out as in the SVGs with sente's side at the
+/// bottom, prefixed by the heatmap's title and the row number.
+pub fn write_csv(path: &Path, heatmaps: &[(String, &[u64; 81])]) -> Result<()> {
+    let mut file = File::create(path)?;
+    let mut csv = String::from("heatmap,row");
+
+    for col in 0..9 {
+        write!(csv, ",{}", col).unwrap();
+    }
+
+    csv.push('\n');
+
+    for (title, counts) in heatmaps {
+        for row in 0..9 {
+            write!(csv, "\"{}\",{}", title.replace('"', "\"\""), row).unwrap();
+
+            for col in 0..9 {
+                write!(csv, ",{}", counts[(8 - row) * 9 + col]).unwrap();
+            }
+
+            csv.push('\n');
+        }
+    }
+
+    Ok(file.write_all(csv.as_bytes())?)
+}
+
+pub fn render_svg(title: &str, counts: &[u64; 81]) -> String {
+    let total = counts.iter().sum::<u64>().max(1) as f64;
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let board = 9 * CELL;
+    let width = board + 2 * MARGIN;
+    let height = TITLE + board + LEGEND + 2 * MARGIN;
+    let mut svg = String::new();
+
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif">"#
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<rect width="{width}" height="{height}" fill="white"/>"#
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<text x="{}" y="{}" font-size="18" text-anchor="middle">{}</text>"#,
+        width / 2,
+        MARGIN + 16,
+        escape(title)
+    )
+    .unwrap();
+
+    for row in 0..9 {
+        for col in 0..9 {
+            let square = (8 - row) * 9 + col;
+            let ratio = counts[square] as f64 / total;
+            let x = MARGIN + col * CELL;
+            let y = MARGIN + TITLE + row * CELL;
+
+            writeln!(
+                svg,
+                r#"<rect x="{x}" y="{y}" width="{CELL}" height="{CELL}" fill="{}" stroke="black"/>"#,
+                colour(counts[square] as f64 / max)
+            )
+            .unwrap();
+            writeln!(
+                svg,
+                r#"<text x="{}" y="{}" font-size="12" text-anchor="middle">{:.2}%</text>"#,
+                x + CELL / 2,
+                y + CELL / 2 + 4,
+                ratio * 100.0
+            )
+            .unwrap();
+        }
+    }
+
+    let legend_y = MARGIN + TITLE + board + 16;
+
+    writeln!(
+        svg,
+        r#"<defs><linearGradient id="legend"><stop offset="0" stop-color="{}"/><stop offset="1" stop-color="{}"/></linearGradient></defs>"#,
+        colour(0.0),
+        colour(1.0)
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<rect x="{MARGIN}" y="{legend_y}" width="{board}" height="12" fill="url(#legend)" stroke="black"/>"#
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<text x="{MARGIN}" y="{}" font-size="12">0.00%</text>"#,
+        legend_y + 28
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<text x="{}" y="{}" font-size="12" text-anchor="end">{:.2}%</text>"#,
+        MARGIN + board,
+        legend_y + 28,
+        max / total * 100.0
+    )
+    .unwrap();
+    svg.push_str("</svg>\n");
+
+    svg
+}
+
+fn colour(intensity: f64) -> String {
+    let intensity = intensity.clamp(0.0, 1.0);
+    let fade = (255.0 * (1.0 - intensity)).round() as u8;
+
+    format!("rgb(255,{},{})", fade, fade)
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,103 @@
+use {
+    crate::{
+        buffer,
+        error::{Error, Result},
+        export::parse_outcome,
+    },
+    clap::ValueEnum,
+    serde::Deserialize,
+    std::{
+        fs::File,
+        io::{BufRead, Write},
+        path::Path,
+    },
+    stoatformat::{
+        shogi::{core::Move, position::Position},
+        stoatpack::Stoatpack,
+    },
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+    Jsonl,
+}
+
+/// The schema written by `export --format jsonl`. `metadata` is ignored and `scores` may be
+/// left out of hand-written games, in which case every move scores 0.
+#[derive(Deserialize, Debug)]
+struct JsonlGame {
+    start_sfen: String,
+    moves: Vec<String>,
+    #[serde(default)]
+    scores: Vec<i16>,
+    outcome: String,
+}
+
+/// Packs every game of a JSONL file into `writer`, in file order.
+pub fn import(path: &Path, format: Format, writer: &mut impl Write) -> Result<usize> {
+    let Format::Jsonl = format;
+    let reader = buffer::reader(File::open(path)?);
+    let mut games = 0;
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let game = parse_game(&line, path, line_number + 1, games)?;
+
+        game.serialise(writer)?;
+        games += 1;
+    }
+
+    println!("  OK  : {}, {} games", path.display(), games);
+
+    Ok(games)
+}
+
+/// Parses the game on line `line_number`, the `index`th game of the file.
+fn parse_game(line: &str, path: &Path, line_number: usize, index: usize) -> Result<Stoatpack> {
+    let invalid =
+        |message: String| Error::other(format!("{}:{}: {}", path.display(), line_number, message));
+    let game = serde_json::from_str::<JsonlGame>(line).map_err(|e| invalid(e.to_string()))?;
+
+    if !game.scores.is_empty() && game.scores.len() != game.moves.len() {
+        return Err(invalid(format!(
+            "{} moves but {} scores",
+            game.moves.len(),
+            game.scores.len()
+        )));
+    }
+
+    let startpos = Position::from_sfen(&game.start_sfen)
+        .map_err(|_| invalid(format!("invalid SFEN: {}", game.start_sfen)))?;
+    let wdl = parse_outcome(&game.outcome)
+        .ok_or_else(|| invalid(format!("invalid outcome: {}", game.outcome)))?;
+    let mut pos = startpos.clone();
+    let mut moves = Vec::with_capacity(game.moves.len());
+
+    for (ply, usi) in game.moves.iter().enumerate() {
+        let illegal = || Error::InvalidMove {
+            path: path.to_path_buf(),
+            game: index,
+            ply,
+            mv: usi.clone(),
+        };
+        let mv = usi.parse::<Move>().map_err(|_| illegal())?;
+
+        if !pos.is_legal(mv) {
+            return Err(illegal());
+        }
+
+        pos = pos.apply_move(mv);
+        moves.push((mv, game.scores.get(ply).copied().unwrap_or(0)));
+    }
+
+    Ok(Stoatpack {
+        startpos,
+        wdl,
+        moves,
+    })
+}
@@ -0,0 +1,91 @@
+use {
+    crate::{
+        buffer, checksum, compress,
+        error::{Context, Result},
+        export::outcome_name,
+        sidecar,
+    },
+    std::{fs::OpenOptions, io::Seek, path::Path},
+    stoatformat::stoatpack::Stoatpack,
+};
+
+/// Prints a quick overview of one file and returns its record count.
+pub fn info(path: &Path) -> Result<usize> {
+    let len = path.metadata()?.len();
+
+    println!("File     : {}", path.display());
+    println!("Size     : {} bytes", len);
+
+    let records = if compress::is_compressed(path) {
+        let records = compress::record_count(path)?;
+
+        println!(
+            "Format   : zstd-compressed stoatpack (.{})",
+            compress::EXTENSION
+        );
+        println!("Records  : {}", records);
+        records
+    } else {
+        println!("Format   : stoatpack (.spk)");
+        scan(path, len)?
+    };
+
+    let mut extras = Vec::new();
+
+    if compress::is_compressed(path) {
+        extras.push("block index".to_string());
+    }
+
+    if let Some(source) = checksum::source(path) {
+        extras.push(format!("checksum ({})", source.display()));
+    }
+
+    if sidecar::sidecar_path(path).is_file() {
+        extras.push(format!("stats ({})", sidecar::sidecar_path(path).display()));
+    }
+
+    if extras.is_empty() {
+        println!("Extras   : none");
+    } else {
+        println!("Extras   : {}", extras.join(", "));
+    }
+
+    println!();
+
+    Ok(records)
+}
+
+fn scan(path: &Path, len: u64) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut reader = buffer::reader(&file);
+    let mut records = 0;
+    let mut positions = 0;
+    let mut first = None;
+    let mut last = None;
+
+    while reader.stream_position()? < len {
+        let offset = reader.stream_position()?;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+        let summary = format!(
+            "{} plies, {}, start {}",
+            game.moves.len(),
+            outcome_name(game.wdl),
+            game.startpos.sfen()
+        );
+
+        positions += game.moves.len() + 1;
+        records += 1;
+        first.get_or_insert_with(|| summary.clone());
+        last = Some(summary);
+    }
+
+    println!("Records  : {}", records);
+    println!("Positions: {}", positions);
+
+    if let (Some(first), Some(last)) = (first, last) {
+        println!("First    : {}", first);
+        println!("Last     : {}", last);
+    }
+
+    Ok(records)
+}
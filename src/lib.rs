@@ -0,0 +1,13 @@
+//! The parts of spk-tools a Rust trainer can use directly: reading `.spk` files with checksum
+//! verification, shuffling, and a [`DataLoader`] that runs the whole input pipeline on
+//! background threads.
+
+pub mod buffer;
+pub mod checksum;
+pub mod error;
+pub mod loader;
+pub mod permutation;
+pub mod reader;
+pub mod shuffle;
+
+pub use loader::{DataLoader, DataLoaderBuilder, Sample};
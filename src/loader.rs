@@ -0,0 +1,256 @@
+use {
+    crate::{error::Result, reader::RecordReader, shuffle::ShuffleBuffer},
+    std::{
+        mem,
+        path::{Path, PathBuf},
+        sync::{
+            Arc,
+            mpsc::{Receiver, SyncSender, sync_channel},
+        },
+        thread,
+    },
+    stoatformat::{Outcome, shogi::position::Position, stoatpack::Stoatpack},
+};
+
+/// Positions a reader thread hands to the shuffler at a time
+const CHUNK_SIZE: usize = 4096;
+
+type Filter = Arc<dyn Fn(&Sample) -> bool + Send + Sync>;
+
+/// One training position, as played out from a game record
+#[derive(Clone)]
+pub struct Sample {
+    pub pos: Position,
+    pub ply: usize,
+    pub score: i16,
+    pub wdl: Outcome,
+}
+
+/// Configures a [`DataLoader`]. The defaults match `serve`.
+pub struct DataLoaderBuilder {
+    paths: Vec<PathBuf>,
+    batch_size: usize,
+    threads: usize,
+    prefetch: usize,
+    shuffle_buffer: usize,
+    seed: u64,
+    eval_limit: i16,
+    filter: Option<Filter>,
+}
+
+impl DataLoaderBuilder {
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Reader threads decoding files in parallel, each taking every `threads`-th file
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Ready batches kept queued ahead of the trainer
+    pub fn prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch.max(1);
+        self
+    }
+
+    /// Positions held back for shuffling before the first batch is handed out
+    pub fn shuffle_buffer(mut self, shuffle_buffer: usize) -> Self {
+        self.shuffle_buffer = shuffle_buffer;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Positions scored above this in absolute value are skipped
+    pub fn eval_limit(mut self, eval_limit: i16) -> Self {
+        self.eval_limit = eval_limit;
+        self
+    }
+
+    /// Keeps only the samples `filter` accepts. It runs on the reader threads.
+    pub fn filter<F: Fn(&Sample) -> bool + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    pub fn build(self) -> DataLoader {
+        let (chunk_sender, chunk_receiver) = sync_channel(self.threads * 2);
+        let (batch_sender, batch_receiver) = sync_channel(self.prefetch);
+        let paths = Arc::new(self.paths);
+        let threads = self.threads;
+
+        for thread in 0..threads {
+            let paths = Arc::clone(&paths);
+            let sender = chunk_sender.clone();
+            let filter = self.filter.clone();
+            let eval_limit = self.eval_limit;
+
+            thread::spawn(move || {
+                let files = paths.iter().skip(thread).step_by(threads);
+
+                for path in files {
+                    // A failed send means the loader was dropped, so there is no one to tell
+                    if let Err(error) = read_file(path, eval_limit, filter.as_deref(), &sender) {
+                        let _ = sender.send(Err(error));
+                        return;
+                    }
+                }
+            });
+        }
+
+        drop(chunk_sender);
+
+        let shuffler = ShuffleBuffer::new(self.shuffle_buffer, self.seed);
+        let batch_size = self.batch_size;
+        thread::spawn(move || collect(chunk_receiver, shuffler, batch_size, batch_sender));
+
+        DataLoader {
+            receiver: batch_receiver,
+        }
+    }
+}
+
+/// Streams shuffled batches of positions from `.spk` files, with decoding, filtering and
+/// shuffling running on background threads ahead of the trainer. Iteration ends after one
+/// pass over the files, or after the first error. Dropping the loader stops the threads.
+///
+/// ```no_run
+/// use spk_tools::DataLoader;
+///
+/// let loader = DataLoader::builder(vec!["games.spk".into()])
+///     .batch_size(16384)
+///     .filter(|sample| sample.ply >= 16)
+///     .build();
+///
+/// for batch in loader {
+///     let batch = batch?;
+///     // Train on `batch`
+/// }
+/// # Ok::<(), spk_tools::error::Error>(())
+/// ```
+pub struct DataLoader {
+    receiver: Receiver<Result<Vec<Sample>>>,
+}
+
+impl DataLoader {
+    pub fn builder(paths: Vec<PathBuf>) -> DataLoaderBuilder {
+        DataLoaderBuilder {
+            paths,
+            batch_size: 16384,
+            threads: 4,
+            prefetch: 8,
+            shuffle_buffer: 1 << 20,
+            seed: 42,
+            eval_limit: 25001,
+            filter: None,
+        }
+    }
+}
+
+impl Iterator for DataLoader {
+    type Item = Result<Vec<Sample>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+fn read_file(
+    path: &Path,
+    eval_limit: i16,
+    filter: Option<&(dyn Fn(&Sample) -> bool + Send + Sync)>,
+    sender: &SyncSender<Result<Vec<Sample>>>,
+) -> Result<()> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+
+    for game in RecordReader::open(path)? {
+        for sample in expand(game?, eval_limit) {
+            if filter.is_none_or(|filter| filter(&sample)) {
+                chunk.push(sample);
+            }
+        }
+
+        if chunk.len() >= CHUNK_SIZE && sender.send(Ok(mem::take(&mut chunk))).is_err() {
+            return Ok(());
+        }
+    }
+
+    if !chunk.is_empty() {
+        let _ = sender.send(Ok(chunk));
+    }
+
+    Ok(())
+}
+
+fn expand(game: Stoatpack, eval_limit: i16) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    let mut pos = game.startpos;
+    let wdl = game.wdl;
+
+    for (ply, (mv, score)) in game.moves.into_iter().enumerate() {
+        let next = pos.apply_move(mv);
+
+        if score.abs() <= eval_limit {
+            samples.push(Sample {
+                pos,
+                ply,
+                score,
+                wdl,
+            });
+        }
+
+        pos = next;
+    }
+
+    samples
+}
+
+/// Runs the shuffle buffer on the samples from all reader threads and cuts them into batches
+fn collect(
+    receiver: Receiver<Result<Vec<Sample>>>,
+    mut shuffler: ShuffleBuffer<Sample>,
+    batch_size: usize,
+    sender: SyncSender<Result<Vec<Sample>>>,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+
+    for chunk in receiver {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                let _ = sender.send(Err(error));
+                return;
+            }
+        };
+
+        for sample in chunk {
+            let Some(sample) = shuffler.push(sample) else {
+                continue;
+            };
+
+            batch.push(sample);
+
+            if batch.len() == batch_size && sender.send(Ok(mem::take(&mut batch))).is_err() {
+                return;
+            }
+        }
+    }
+
+    while let Some(sample) = shuffler.pop() {
+        batch.push(sample);
+
+        if batch.len() == batch_size && sender.send(Ok(mem::take(&mut batch))).is_err() {
+            return;
+        }
+    }
+
+    if !batch.is_empty() {
+        let _ = sender.send(Ok(batch));
+    }
+}
@@ -1,21 +1,112 @@
+mod adjudication;
+mod archive;
+mod augment;
+mod chart;
+mod compare;
+mod compress;
+mod convert;
+mod dataset;
+mod ending;
+mod export;
+mod features;
+mod frame;
+mod heatmap;
+mod import;
+mod info;
+mod progress;
+mod prune;
+mod remap;
+mod remote;
+mod report;
+mod serve;
+mod show;
+mod sidecar;
+mod split;
+mod stats_db;
+mod upload;
+mod verify;
+
 use {
-    clap::{Parser, Subcommand},
+    clap::{Parser, Subcommand, ValueEnum},
     colored::{ColoredString, Colorize},
     console::pad_str,
-    rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom},
+    rand::{Rng, SeedableRng, rngs::SmallRng},
+    spk_tools::{
+        buffer,
+        checksum::{self, ChecksumReader},
+        error::{self, Context, Error, Result},
+        permutation,
+    },
     std::{
+        collections::{BTreeMap, HashMap, HashSet},
         env,
-        fs::{File, OpenOptions, read_dir},
-        io::{BufReader, Read, Result, Seek, SeekFrom, Write},
+        fs::{File, OpenOptions, create_dir_all, metadata, read_dir, remove_file, rename},
+        hash::{DefaultHasher, Hash, Hasher},
+        io::{self, BufRead, BufWriter, Read, Seek, SeekFrom, Write, copy},
         path::{Path, PathBuf},
+        str::FromStr,
+        time::Instant,
     },
     stoatformat::{
         Outcome,
-        shogi::core::{Color, PieceType, Square},
+        shogi::{
+            core::{Color, Move, PieceType, Square},
+            position::Position,
+        },
         stoatpack::Stoatpack,
     },
+    upload::UploadTarget,
 };
 
+/// Side-to-move shares further than this from 50%, in percentage points, are highlighted
+const PARITY_WARNING: f64 = 1.0;
+
+const PHASES: [&str; 3] = ["Opening", "Middlegame", "Endgame"];
+
+const PIECE_VALUES: [(PieceType, i32); 13] = [
+    (PieceType::PAWN, 1),
+    (PieceType::LANCE, 3),
+    (PieceType::KNIGHT, 4),
+    (PieceType::SILVER, 5),
+    (PieceType::GOLD, 6),
+    (PieceType::BISHOP, 8),
+    (PieceType::ROOK, 10),
+    (PieceType::PROMOTED_PAWN, 6),
+    (PieceType::PROMOTED_LANCE, 6),
+    (PieceType::PROMOTED_KNIGHT, 6),
+    (PieceType::PROMOTED_SILVER, 6),
+    (PieceType::PROMOTED_BISHOP, 10),
+    (PieceType::PROMOTED_ROOK, 12),
+];
+
+const HAND_PIECE_TYPES: usize = 7;
+
+const MATERIAL_RANGE: i32 = 12;
+
+const DROP_PIECES: [(char, &str); 7] = [
+    ('P', "Pawn"),
+    ('L', "Lance"),
+    ('N', "Knight"),
+    ('S', "Silver"),
+    ('G', "Gold"),
+    ('B', "Bishop"),
+    ('R', "Rook"),
+];
+
+const MAJOR_PIECE_TYPES: [PieceType; 4] = [
+    PieceType::BISHOP,
+    PieceType::ROOK,
+    PieceType::PROMOTED_BISHOP,
+    PieceType::PROMOTED_ROOK,
+];
+
+const EVAL_RANGE: i16 = 3000;
+const EVAL_BUCKET: i16 = 200;
+const EVAL_BUCKETS: usize = (2 * EVAL_RANGE / EVAL_BUCKET) as usize;
+
+const LENGTH_BUCKET: usize = 10;
+const LENGTH_BUCKETS: usize = 31;
+
 #[derive(Parser)]
 #[command(name = "spk-tools")]
 #[clap(
@@ -32,344 +123,3798 @@ struct Cli {
 #[derive(Debug, Subcommand)]
 enum Command {
     Count(CountArgs),
-    Fix(CommonArgs),
+    Fix(FixArgs),
     Shuffle(ShuffleArgs),
+    Trim(TrimArgs),
+    Filter(FilterArgs),
+    Sort(SortArgs),
+    Report(ReportArgs),
+    Convert(ConvertArgs),
+    Export(ExportArgs),
+    Features(FeaturesArgs),
+    Augment(AugmentArgs),
+    Startpos(StartposArgs),
+    Extract(ExtractArgs),
+    Verify(VerifyArgs),
+    Duplicates(CommonArgs),
+    Selftest(CommonArgs),
+    Split(SplitArgs),
+    Relabel(CommonArgs),
+    BuildDataset(BuildDatasetArgs),
+    Prune(PruneArgs),
+    SimulateAdjudication(SimulateAdjudicationArgs),
+    Remap(RemapArgs),
+    NearDuplicates(NearDuplicatesArgs),
+    StatsDb(StatsDbArgs),
+    Frame(FrameArgs),
+    Compress(CompressArgs),
+    Archive(ArchiveArgs),
+    Info(CommonArgs),
+    Compare(CompareArgs),
+    Show(ShowArgs),
+    Import(ImportArgs),
+    Serve(ServeArgs),
+}
+
+impl Command {
+    fn common(&self) -> &CommonArgs {
+        match self {
+            Command::Count(args) => &args.common,
+            Command::Fix(args) => &args.common,
+            Command::Shuffle(args) => &args.common,
+            Command::Trim(args) => &args.common,
+            Command::Filter(args) => &args.common,
+            Command::Sort(args) => &args.common,
+            Command::Report(args) => &args.count.common,
+            Command::Convert(args) => &args.common,
+            Command::Export(args) => &args.common,
+            Command::Features(args) => &args.common,
+            Command::Augment(args) => &args.common,
+            Command::Startpos(args) => &args.common,
+            Command::Extract(args) => &args.common,
+            Command::Verify(args) => &args.common,
+            Command::Duplicates(args) => args,
+            Command::Selftest(args) => args,
+            Command::Split(args) => &args.common,
+            Command::Relabel(args) => args,
+            Command::BuildDataset(args) => &args.common,
+            Command::Prune(args) => &args.common,
+            Command::SimulateAdjudication(args) => &args.common,
+            Command::Remap(args) => &args.common,
+            Command::NearDuplicates(args) => &args.common,
+            Command::StatsDb(args) => &args.common,
+            Command::Frame(args) => &args.common,
+            Command::Compress(args) => &args.common,
+            Command::Archive(args) => &args.common,
+            Command::Info(args) => args,
+            Command::Compare(args) => &args.count.common,
+            Command::Show(args) => &args.common,
+            Command::Import(args) => &args.common,
+            Command::Serve(args) => &args.common,
+        }
+    }
+}
+
+#[derive(Parser, Debug, Default)]
+struct CommonArgs {
+    #[arg(short, long)]
+    recursive: bool,
+
+    #[arg(long, value_name = "LABEL=PATH")]
+    input: Vec<LabeledInput>,
+
+    #[arg(required_unless_present = "input")]
+    paths: Vec<PathBuf>,
+
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    read_buffer: Option<usize>,
+
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    write_buffer: Option<usize>,
+
+    #[arg(long)]
+    sequential: bool,
+
+    #[arg(long, value_name = "GAMES")]
+    limit: Option<usize>,
+
+    #[arg(long, value_name = "GAMES", default_value_t = 0)]
+    skip: usize,
+
+    #[arg(long, value_name = "FORMAT")]
+    progress: Option<progress::Format>,
+}
+
+impl CommonArgs {
+    /// Whether a scan should read the game at `index` of a file. `--skip` and `--limit` are
+    /// only honoured by read-only scans (count, report, verify and simulate-adjudication).
+    fn within_limit(&self, index: usize) -> bool {
+        self.limit
+            .is_none_or(|limit| index < self.skip.saturating_add(limit))
+    }
+
+    fn skipped(&self, index: usize) -> bool {
+        index < self.skip
+    }
+
+    fn configure_buffers(&self) {
+        let default = if self.sequential {
+            buffer::SEQUENTIAL_SIZE
+        } else {
+            buffer::DEFAULT_SIZE
+        };
+
+        buffer::configure(
+            self.read_buffer.unwrap_or(default),
+            self.write_buffer.unwrap_or(default),
+        );
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LabeledInput {
+    label: String,
+    path: PathBuf,
+}
+
+impl FromStr for LabeledInput {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some((label, path)) if !label.is_empty() => Ok(Self {
+                label: label.to_string(),
+                path: PathBuf::from(path),
+            }),
+            _ => Err(Error::other(format!("Invalid labeled input: {}", s))),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+struct CountArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, short)]
+    quick: bool,
+
+    #[arg(long, short, default_value_t = 25001)]
+    eval_limit: i16,
+
+    #[arg(long, value_delimiter = ',', default_values_t = [1, 10, 20, 40, 80])]
+    bias_plies: Vec<usize>,
+
+    #[arg(long, default_value_t = 10)]
+    top_moves: usize,
+
+    #[arg(long, default_value_t = 0)]
+    top_positions: usize,
+
+    #[arg(long)]
+    transpositions: bool,
+
+    #[arg(long, value_delimiter = ',', default_values_t = [1.0, 5.0, 50.0, 95.0, 99.0])]
+    percentiles: Vec<f64>,
+
+    #[arg(long)]
+    heatmap_out: Option<PathBuf>,
+
+    #[arg(long)]
+    heatmap_csv: Option<PathBuf>,
+
+    #[arg(long, value_name = "LOW,HIGH", value_parser = parse_thresholds, default_value = "5,10")]
+    heatmap_thresholds: (f64, f64),
+
+    #[arg(long, conflicts_with = "heatmap_thresholds")]
+    heatmap_quantiles: bool,
+
+    #[arg(long, value_enum, default_value_t = Normalize::Total)]
+    heatmap_normalize: Normalize,
+
+    #[arg(long)]
+    chart_out: Option<PathBuf>,
+
+    #[arg(long)]
+    json_out: Option<PathBuf>,
+
+    #[arg(long)]
+    list_reverses: Option<PathBuf>,
+
+    #[arg(long)]
+    sidecar: bool,
+
+    #[arg(long)]
+    max_ply: Option<usize>,
+}
+
+#[derive(Parser, Debug)]
+struct FixArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long)]
+    sidecar: bool,
+
+    #[arg(long)]
+    upload: Option<UploadTarget>,
+}
+
+#[derive(Parser, Debug)]
+struct ArchiveArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, short, required_unless_present = "verify")]
+    output: Option<PathBuf>,
+
+    #[arg(long)]
+    verify: bool,
+}
+
+#[derive(Parser, Debug)]
+struct CompressArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long)]
+    decompress: bool,
+
+    #[arg(long, default_value_t = compress::DEFAULT_LEVEL)]
+    level: i32,
+
+    #[arg(long, value_name = "KIB", default_value_t = compress::DEFAULT_BLOCK_KIB)]
+    block_size: usize,
+}
+
+#[derive(Parser, Debug)]
+struct FrameArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long)]
+    unframe: bool,
+}
+
+struct CountStats {
+    positions: usize,
+    black_wins: usize,
+    white_wins: usize,
+    draws: usize,
+    reverses: usize,
+    king_squares: [u64; 81],
+    phase_king_squares: [[u64; 81]; PHASES.len()],
+    /// King squares of the side that went on to win, then of the side that lost
+    outcome_king_squares: [[u64; 81]; 2],
+    ply_scores: Vec<(i64, usize)>,
+    opening_moves: [HashMap<String, usize>; 2],
+    material: [[u64; 2 * MATERIAL_RANGE as usize + 1]; PHASES.len()],
+    eval_histogram: [u64; EVAL_BUCKETS],
+    length_histogram: [u64; LENGTH_BUCKETS],
+    score_counts: [Vec<u64>; 3],
+    reverse_games: Vec<(PathBuf, usize, u64, usize, i16)>,
+    king_distances: [u64; 9],
+    major_distances: [u64; 10],
+    board_pieces: [u64; 41],
+    promotion_zone_squares: [[u64; 81]; PHASES.len()],
+    promotion_zone_positions: [[u64; 2]; PHASES.len()],
+    drop_squares: [[u64; 81]; DROP_PIECES.len()],
+    position_counts: HashMap<u64, PositionCount>,
+    transpositions: HashMap<u64, (u64, bool, usize)>,
+    score_noise: ScoreNoise,
+    endings: ending::Endings,
+}
+
+#[derive(Default)]
+struct ScoreNoise {
+    pairs: u64,
+    abs_diff: f64,
+    sum: [f64; 2],
+    sum_sq: [f64; 2],
+    sum_product: f64,
+}
+
+impl ScoreNoise {
+    fn record(&mut self, a: i16, b: i16) {
+        let (a, b) = (a as f64, b as f64);
+
+        self.pairs += 1;
+        self.abs_diff += (a - b).abs();
+        self.sum[0] += a;
+        self.sum[1] += b;
+        self.sum_sq[0] += a * a;
+        self.sum_sq[1] += b * b;
+        self.sum_product += a * b;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.pairs += other.pairs;
+        self.abs_diff += other.abs_diff;
+        self.sum_product += other.sum_product;
+
+        for i in 0..2 {
+            self.sum[i] += other.sum[i];
+            self.sum_sq[i] += other.sum_sq[i];
+        }
+    }
+
+    fn correlation(&self) -> f64 {
+        let n = self.pairs as f64;
+        let covariance = self.sum_product / n - self.sum[0] / n * self.sum[1] / n;
+        let variance = |i: usize| self.sum_sq[i] / n - (self.sum[i] / n).powi(2);
+
+        covariance / (variance(0) * variance(1)).sqrt()
+    }
+}
+
+struct PositionCount {
+    sfen: String,
+    count: usize,
+    score_sum: i64,
+}
+
+impl CountStats {
+    fn new(args: &CountArgs) -> Self {
+        Self {
+            positions: 0,
+            black_wins: 0,
+            white_wins: 0,
+            draws: 0,
+            reverses: 0,
+            king_squares: [0; 81],
+            phase_king_squares: [[0; 81]; PHASES.len()],
+            outcome_king_squares: [[0; 81]; 2],
+            ply_scores: vec![(0, 0); args.bias_plies.len()],
+            opening_moves: Default::default(),
+            material: [[0; 2 * MATERIAL_RANGE as usize + 1]; PHASES.len()],
+            eval_histogram: [0; EVAL_BUCKETS],
+            length_histogram: [0; LENGTH_BUCKETS],
+            score_counts: std::array::from_fn(|_| vec![0; 1 << 16]),
+            reverse_games: Vec::new(),
+            king_distances: [0; 9],
+            major_distances: [0; 10],
+            board_pieces: [0; 41],
+            promotion_zone_squares: [[0; 81]; PHASES.len()],
+            promotion_zone_positions: [[0; 2]; PHASES.len()],
+            drop_squares: [[0; 81]; DROP_PIECES.len()],
+            position_counts: HashMap::new(),
+            transpositions: HashMap::new(),
+            score_noise: ScoreNoise::default(),
+            endings: ending::Endings::default(),
+        }
+    }
+
+    fn record_outcome_kings(&mut self, pos: &Position, wdl: Outcome) {
+        let winner = match wdl {
+            Outcome::SenteWin => Color::SENTE,
+            Outcome::SenteLoss => Color::GOTE,
+            Outcome::Draw => return,
+        };
+
+        for (idx, color) in [winner, features::opponent(winner)].into_iter().enumerate() {
+            let king_square = relative_square(
+                color,
+                pos.piece_bb(PieceType::KING.with_color(color))
+                    .lsb()
+                    .unwrap(),
+            );
+            self.outcome_king_squares[idx][king_square.idx()] += 1;
+        }
+    }
+
+    fn record_position(&mut self, pos: &Position, ply: usize) {
+        let king_square = relative_square(
+            pos.stm(),
+            pos.piece_bb(PieceType::KING.with_color(pos.stm()))
+                .lsb()
+                .unwrap(),
+        );
+        self.king_squares[king_square.idx()] += 1;
+        self.phase_king_squares[phase(ply)][king_square.idx()] += 1;
+
+        let balance = material(pos, Color::SENTE) - material(pos, Color::GOTE);
+        let bucket = (balance.clamp(-MATERIAL_RANGE, MATERIAL_RANGE) + MATERIAL_RANGE) as usize;
+        self.material[phase(ply)][bucket] += 1;
+
+        let kings = [Color::SENTE, Color::GOTE].map(|color| {
+            pos.piece_bb(PieceType::KING.with_color(color))
+                .lsb()
+                .unwrap()
+        });
+        self.king_distances[distance(kings[0], kings[1])] += 1;
+
+        for (color, king) in [(Color::SENTE, kings[0]), (Color::GOTE, kings[1])] {
+            // The last bucket counts kings with no enemy major piece on the board
+            let nearest = MAJOR_PIECE_TYPES
+                .iter()
+                .flat_map(|&piece_type| {
+                    pos.piece_bb(piece_type.with_color(features::opponent(color)))
+                })
+                .map(|square| distance(king, square))
+                .min()
+                .unwrap_or(self.major_distances.len() - 1);
+            self.major_distances[nearest] += 1;
+        }
+
+        let pieces = features::PIECE_TYPES
+            .iter()
+            .flat_map(|&piece_type| {
+                [Color::SENTE, Color::GOTE].map(|color| pos.piece_bb(piece_type.with_color(color)))
+            })
+            .map(|bb| bb.popcount() as usize)
+            .sum::<usize>();
+        self.board_pieces[pieces.min(self.board_pieces.len() - 1)] += 1;
+
+        for (side, color) in [Color::SENTE, Color::GOTE].into_iter().enumerate() {
+            let mut occupied = false;
+
+            for &piece_type in &features::PIECE_TYPES[..features::PIECE_TYPES.len() - 1] {
+                for square in pos.piece_bb(piece_type.with_color(color)) {
+                    let square = relative_square(color, square);
+
+                    if square.idx() / 9 >= 6 {
+                        self.promotion_zone_squares[phase(ply)][square.idx()] += 1;
+                        occupied = true;
+                    }
+                }
+            }
+
+            if occupied {
+                self.promotion_zone_positions[phase(ply)][side] += 1;
+            }
+        }
+    }
+
+    fn record_drop(&mut self, stm: Color, usi: &str) {
+        let Some((piece, square)) = usi.split_once('*') else {
+            return;
+        };
+        let piece = DROP_PIECES
+            .iter()
+            .position(|(letter, _)| piece.starts_with(*letter));
+        let mut chars = square.chars();
+        let file = chars.next().and_then(|c| c.to_digit(10));
+        let rank = chars.next().filter(|c| ('a'..='i').contains(c));
+
+        if let (Some(piece), Some(file @ 1..=9), Some(rank)) = (piece, file, rank) {
+            // USI ranks run from gote's side, files from right to left
+            let idx = (8 - (rank as usize - 'a' as usize)) * 9 + (9 - file as usize);
+            let idx = if stm == Color::SENTE { idx } else { 80 - idx };
+            self.drop_squares[piece][idx] += 1;
+        }
+    }
+
+    fn record_position_count(&mut self, pos: &Position, score: i16) {
+        let entry = self
+            .position_counts
+            .entry(pos.key())
+            .or_insert_with(|| PositionCount {
+                sfen: pos.sfen(),
+                count: 0,
+                score_sum: 0,
+            });
+        entry.count += 1;
+        entry.score_sum += score as i64;
+    }
+
+    fn record_transposition(&mut self, pos: &Position, path: u64) {
+        let (first_path, transposed, count) = self
+            .transpositions
+            .entry(pos.key())
+            .or_insert((path, false, 0));
+        *transposed |= *first_path != path;
+        *count += 1;
+    }
+
+    fn record_score(&mut self, wdl: Outcome, score: i16) {
+        let outcome = match wdl {
+            Outcome::SenteWin => 0,
+            Outcome::SenteLoss => 1,
+            Outcome::Draw => 2,
+        };
+        self.score_counts[outcome][(score as i32 - i16::MIN as i32) as usize] += 1;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.positions += other.positions;
+        self.black_wins += other.black_wins;
+        self.white_wins += other.white_wins;
+        self.draws += other.draws;
+        self.reverses += other.reverses;
+        add_counts(&mut self.king_squares, &other.king_squares);
+
+        for (a, b) in self
+            .phase_king_squares
+            .iter_mut()
+            .zip(&other.phase_king_squares)
+        {
+            add_counts(a, b);
+        }
+
+        for (a, b) in self
+            .outcome_king_squares
+            .iter_mut()
+            .zip(&other.outcome_king_squares)
+        {
+            add_counts(a, b);
+        }
+
+        for ((sum, count), (other_sum, other_count)) in
+            self.ply_scores.iter_mut().zip(&other.ply_scores)
+        {
+            *sum += other_sum;
+            *count += other_count;
+        }
+
+        for (a, b) in self.opening_moves.iter_mut().zip(&other.opening_moves) {
+            for (line, count) in b {
+                *a.entry(line.clone()).or_default() += count;
+            }
+        }
+
+        for (a, b) in self.material.iter_mut().zip(&other.material) {
+            add_counts(a, b);
+        }
+
+        add_counts(&mut self.eval_histogram, &other.eval_histogram);
+        add_counts(&mut self.length_histogram, &other.length_histogram);
+
+        for (a, b) in self.score_counts.iter_mut().zip(&other.score_counts) {
+            add_counts(a, b);
+        }
+
+        self.reverse_games
+            .extend(other.reverse_games.iter().cloned());
+        add_counts(&mut self.king_distances, &other.king_distances);
+        add_counts(&mut self.major_distances, &other.major_distances);
+        add_counts(&mut self.board_pieces, &other.board_pieces);
+
+        for (a, b) in self
+            .promotion_zone_squares
+            .iter_mut()
+            .zip(&other.promotion_zone_squares)
+        {
+            add_counts(a, b);
+        }
+
+        for (a, b) in self
+            .promotion_zone_positions
+            .iter_mut()
+            .zip(&other.promotion_zone_positions)
+        {
+            add_counts(a, b);
+        }
+
+        for (a, b) in self.drop_squares.iter_mut().zip(&other.drop_squares) {
+            add_counts(a, b);
+        }
+
+        for (key, position) in &other.position_counts {
+            let entry = self
+                .position_counts
+                .entry(*key)
+                .or_insert_with(|| PositionCount {
+                    sfen: position.sfen.clone(),
+                    count: 0,
+                    score_sum: 0,
+                });
+            entry.count += position.count;
+            entry.score_sum += position.score_sum;
+        }
+
+        for (key, &(path, transposed, count)) in &other.transpositions {
+            let entry = self.transpositions.entry(*key).or_insert((path, false, 0));
+            entry.1 |= transposed || entry.0 != path;
+            entry.2 += count;
+        }
+
+        self.score_noise.merge(&other.score_noise);
+        self.endings.merge(&other.endings);
+    }
+
+    fn games(&self) -> usize {
+        self.black_wins + self.white_wins + self.draws
+    }
+
+    fn eval_labels(&self) -> Vec<String> {
+        (0..EVAL_BUCKETS)
+            .map(|bucket| format!("{}", bucket as i16 * EVAL_BUCKET - EVAL_RANGE))
+            .collect()
+    }
+
+    fn length_labels(&self) -> Vec<String> {
+        (0..LENGTH_BUCKETS)
+            .map(|bucket| {
+                if bucket == LENGTH_BUCKETS - 1 {
+                    format!("{}+", bucket * LENGTH_BUCKET)
+                } else {
+                    format!("{}", bucket * LENGTH_BUCKET)
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Parser, Debug)]
+struct ReportArgs {
+    #[clap(flatten)]
+    count: CountArgs,
+
+    #[arg(long, short, required = true)]
+    output: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct ShowArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, default_value_t = 0)]
+    game: usize,
+
+    #[arg(long)]
+    ply: Option<usize>,
+
+    #[arg(long)]
+    ascii: bool,
+}
+
+/// Takes two runs, either as two directories or as two labeled inputs
+#[derive(Parser, Debug)]
+struct CompareArgs {
+    #[clap(flatten)]
+    count: CountArgs,
+}
+
+impl CompareArgs {
+    fn labels(&self) -> Vec<String> {
+        self.count
+            .common
+            .input
+            .iter()
+            .map(|input| input.label.clone())
+            .chain(
+                self.count
+                    .common
+                    .paths
+                    .iter()
+                    .map(|path| path.display().to_string()),
+            )
+            .collect()
+    }
+}
+
+#[derive(Parser, Debug)]
+struct ConvertArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, value_enum)]
+    to: convert::Format,
+
+    #[arg(long, short, required = true)]
+    output: PathBuf,
+
+    #[arg(long, short, default_value_t = 25001)]
+    eval_limit: i16,
+
+    #[arg(long, value_parser = parse_positive, default_value_t = 16384)]
+    batch_size: usize,
+
+    #[arg(long, value_name = "MIB")]
+    max_mem: Option<u64>,
+
+    #[arg(long, value_enum, default_value_t = convert::Dtype::Int32)]
+    dtype: convert::Dtype,
+
+    #[arg(long, short, default_value_t = 42)]
+    seed: u64,
+
+    #[arg(long, value_parser = parse_count)]
+    target_positions: Option<u64>,
+
+    #[arg(long, value_parser = parse_clip)]
+    clip_scores: Option<i16>,
+
+    #[arg(long, value_enum, value_name = "POLICY")]
+    dedupe: Option<convert::DedupePolicy>,
+
+    #[arg(long, default_value_t = 0)]
+    min_hand_pieces: u32,
+
+    #[arg(long, value_name = "PIECE=N", value_parser = parse_hand_condition)]
+    min_hand: Vec<(PieceType, u32)>,
+
+    #[arg(long, value_name = "REGION", value_parser = parse_king_region)]
+    king_region: Option<convert::KingRegion>,
+
+    #[arg(long, default_value_t = 600.0)]
+    sigmoid_scale: f64,
+
+    #[arg(long, value_parser = parse_lambda)]
+    lambda: Option<f64>,
+}
+
+#[derive(Parser, Debug)]
+struct FeaturesArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, value_enum, default_value_t = features::FeatureSet::HalfKa)]
+    feature_set: features::FeatureSet,
+
+    #[arg(long, short, required = true)]
+    output: PathBuf,
+
+    #[arg(long, short, default_value_t = 25001)]
+    eval_limit: i16,
+
+    #[arg(long, value_parser = parse_clip)]
+    clip_scores: Option<i16>,
+}
+
+#[derive(Parser, Debug)]
+struct ExportArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, value_enum)]
+    format: export::Format,
+
+    #[arg(long, short, required = true)]
+    output: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct ImportArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, value_enum, default_value_t = import::Format::Jsonl)]
+    format: import::Format,
+
+    #[arg(long, short, required = true)]
+    output: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct ServeArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:5555")]
+    listen: String,
+
+    #[arg(long, value_parser = parse_positive, default_value_t = 16384)]
+    batch_size: usize,
+
+    #[arg(long, value_name = "POSITIONS", value_parser = parse_count, default_value = "1M")]
+    shuffle_buffer: u64,
+
+    #[arg(long, short, default_value_t = 25001)]
+    eval_limit: i16,
+
+    #[arg(long)]
+    epochs: Option<usize>,
+
+    #[arg(long, short, default_value_t = 42)]
+    seed: u64,
+
+    #[arg(long, value_name = "PATH", conflicts_with = "listen")]
+    shm: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["listen", "shm"])]
+    socket: Option<PathBuf>,
+
+    #[arg(long, value_parser = parse_positive, default_value_t = 8)]
+    slots: usize,
+
+    #[arg(long, value_name = "ADDR")]
+    metrics: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct AugmentArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, required_unless_present = "color_flip")]
+    mirror: bool,
+
+    #[arg(long)]
+    color_flip: bool,
+
+    #[arg(long)]
+    replace: bool,
+}
+
+#[derive(Parser, Debug)]
+struct NearDuplicatesArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, default_value_t = 40)]
+    prefix_length: usize,
+
+    #[arg(long)]
+    keep: Option<usize>,
+
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+}
+
+#[derive(Parser, Debug)]
+struct RemapArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, short, required = true)]
+    table: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct SimulateAdjudicationArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, required = true)]
+    resign_eval: i16,
+
+    #[arg(long, default_value_t = 4)]
+    resign_plies: usize,
+
+    #[arg(long, required = true)]
+    draw_eval: i16,
+
+    #[arg(long, default_value_t = 8)]
+    draw_plies: usize,
+
+    #[arg(long, default_value_t = 80)]
+    draw_min_ply: usize,
+}
+
+#[derive(Parser, Debug)]
+struct PruneArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, required = true, value_parser = parse_count)]
+    keep_positions: u64,
+
+    #[arg(long, value_enum, default_value_t = prune::Order::Mtime)]
+    order: prune::Order,
+
+    #[arg(long)]
+    archive: Option<PathBuf>,
+
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Parses a byte size with an optional binary K/M/G suffix, e.g. `64M`.
+fn parse_size(s: &str) -> Result<usize> {
+    let (digits, shift) = match s.chars().last() {
+        Some('K' | 'k') => (&s[..s.len() - 1], 10),
+        Some('M' | 'm') => (&s[..s.len() - 1], 20),
+        Some('G' | 'g') => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+
+    // A shift would silently drop the high bits
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|size| size.checked_mul(1 << shift))
+        .ok_or_else(|| Error::other(format!("Invalid size: {}", s)))
+}
+
+fn parse_thresholds(s: &str) -> Result<(f64, f64)> {
+    s.split_once(',')
+        .and_then(|(low, high)| Some((low.parse::<f64>().ok()?, high.parse::<f64>().ok()?)))
+        .filter(|(low, high)| low <= high)
+        .ok_or_else(|| Error::other(format!("Invalid thresholds: {}, expected LOW,HIGH", s)))
+}
+
+/// Parses a piece held in hand, by USI letter or English name, and a count such as `P=3`.
+fn parse_hand_condition(s: &str) -> Result<(PieceType, u32)> {
+    let invalid = || Error::other(format!("Invalid hand condition: {}, expected PIECE=N", s));
+    let (piece, count) = s.split_once('=').ok_or_else(invalid)?;
+    let idx = DROP_PIECES
+        .iter()
+        .position(|(letter, name)| {
+            piece.eq_ignore_ascii_case(&letter.to_string()) || piece.eq_ignore_ascii_case(name)
+        })
+        .ok_or_else(invalid)?;
+
+    Ok((
+        features::HAND_PIECE_TYPES[idx].0,
+        count.trim().parse::<u32>().map_err(|_| invalid())?,
+    ))
+}
+
+/// Parses a preset region name or a comma-separated list of USI squares such as `5e,5d`.
+fn parse_king_region(s: &str) -> Result<convert::KingRegion> {
+    if let Some(region) = convert::KingRegion::preset(s) {
+        return Ok(region);
+    }
+
+    s.split(',')
+        .map(|square| {
+            let square = square.trim();
+            show::square(square).filter(|_| square.len() == 2)
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|squares| convert::KingRegion::from_squares(&squares))
+        .ok_or_else(|| {
+            Error::other(format!(
+                "Invalid king region: {}, expected one of {} or a list of squares",
+                s,
+                convert::KingRegion::PRESETS.join(", ")
+            ))
+        })
+}
+
+fn parse_lambda(s: &str) -> Result<f64> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|lambda| (0.0..=1.0).contains(lambda))
+        .ok_or_else(|| Error::other(format!("Invalid lambda: {}, expected 0 to 1", s)))
+}
+
+fn parse_clip(s: &str) -> Result<i16> {
+    s.parse::<i16>()
+        .ok()
+        .filter(|&clip| clip > 0)
+        .ok_or_else(|| Error::other(format!("Invalid clip: {}, expected a positive score", s)))
+}
+
+fn parse_positive(s: &str) -> Result<usize> {
+    s.parse::<usize>()
+        .ok()
+        .filter(|&value| value > 0)
+        .ok_or_else(|| Error::other(format!("Invalid value: {}, expected a positive integer", s)))
+}
+
+fn parse_count(s: &str) -> Result<u64> {
+    let (digits, scale) = match s.chars().last() {
+        Some('K' | 'k') => (&s[..s.len() - 1], 1_000),
+        Some('M' | 'm') => (&s[..s.len() - 1], 1_000_000),
+        Some('B' | 'b' | 'G' | 'g') => (&s[..s.len() - 1], 1_000_000_000),
+        _ => (s, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(scale))
+        .ok_or_else(|| Error::other(format!("Invalid count: {}", s)))
+}
+
+#[derive(Parser, Debug)]
+struct BuildDatasetArgs {
+    #[clap(skip)]
+    common: CommonArgs,
+
+    recipe: PathBuf,
+
+    #[arg(long, short, required = true)]
+    output: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct StatsDbArgs {
+    #[clap(skip)]
+    common: CommonArgs,
+
+    #[arg(long, default_value = "spk-stats.sqlite")]
+    db: PathBuf,
+
+    #[clap(subcommand)]
+    action: StatsDbAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum StatsDbAction {
+    Append {
+        #[arg(required = true)]
+        runs: Vec<PathBuf>,
+
+        #[arg(long)]
+        label: Option<String>,
+    },
+    Query {
+        #[arg(long)]
+        label: Option<String>,
+
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+}
+
+#[derive(Parser, Debug)]
+struct SplitArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(
+        long,
+        short,
+        required_unless_present = "by_hash",
+        conflicts_with = "by_hash"
+    )]
+    by: Option<split::SplitBy>,
+
+    #[arg(long, value_parser = split::parse_hash_split)]
+    by_hash: Option<split::SplitBy>,
+
+    #[arg(long, short, required = true)]
+    output_dir: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct ShuffleArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, short, default_value_t = 42)]
+    seed: u64,
+
+    #[arg(long)]
+    verify: bool,
+
+    #[arg(long, value_name = "MIB")]
+    max_mem: Option<u64>,
+
+    #[arg(long, default_value_t = permutation::LATEST_VERSION)]
+    rng_version: u32,
+
+    #[arg(long)]
+    upload: Option<UploadTarget>,
+}
+
+#[derive(Parser, Debug)]
+struct TrimArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, short, default_value_t = 3000)]
+    trim_after_eval: i16,
+
+    #[arg(long, short, default_value_t = 6)]
+    plies: usize,
+}
+
+#[derive(Parser, Debug)]
+struct FilterArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long)]
+    drop_reverses: bool,
+
+    #[arg(long, short, default_value_t = 25001)]
+    eval_limit: i16,
+
+    #[arg(long)]
+    max_per_startpos: Option<usize>,
+
+    #[arg(long)]
+    startpos_list: Option<PathBuf>,
+
+    #[arg(long, requires = "startpos_list")]
+    invert: bool,
+
+    #[arg(long, value_parser = parse_count)]
+    target_positions: Option<u64>,
+
+    #[arg(long, short, default_value_t = 42)]
+    seed: u64,
+
+    #[arg(long, default_value_t = 0)]
+    min_positions: u64,
+
+    #[arg(long, default_value_t = 0)]
+    min_ply: usize,
+
+    #[arg(long)]
+    drop_illegal_drops: bool,
+
+    #[arg(long)]
+    drop_ply_capped: bool,
+
+    #[arg(long, requires = "drop_ply_capped")]
+    max_ply: Option<usize>,
+}
+
+#[derive(Default)]
+struct FilterState {
+    startpos_counts: HashMap<u64, (usize, usize)>,
+    startpos_list: Option<HashSet<u64>>,
+    sampler: Option<PositionSampler>,
+    positions_histogram: [u64; LENGTH_BUCKETS],
+    short_games: usize,
+    illegal_drop_games: usize,
+    ply_cap: Option<usize>,
+    ply_capped_games: usize,
+}
+
+impl FilterState {
+    fn new(args: &FilterArgs) -> Result<Self> {
+        let mut state = Self::default();
+
+        if let Some(path) = &args.startpos_list {
+            state.startpos_list = Some(read_startpos_list(path)?);
+        }
+
+        Ok(state)
+    }
+}
+
+/// Selects games (or single positions) so that exactly `needed` of `remaining` positions are
+/// kept when each is sampled one at a time, and close to that when whole games are sampled.
+struct PositionSampler {
+    needed: u64,
+    remaining: u64,
+    rng: SmallRng,
+}
+
+impl PositionSampler {
+    fn new(target: u64, total: u64, seed: u64) -> Self {
+        if total < target {
+            println!(
+                "Only {} positions are available for a target of {}",
+                total, target
+            );
+        }
+
+        Self {
+            needed: target,
+            remaining: total,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    fn sample(&mut self, positions: u64) -> bool {
+        let keep = self.needed > 0 && self.rng.random_range(0..self.remaining.max(1)) < self.needed;
+        self.remaining = self.remaining.saturating_sub(positions);
+
+        if keep {
+            self.needed = self.needed.saturating_sub(positions);
+        }
+
+        keep
+    }
+}
+
+#[derive(Parser, Debug)]
+struct StartposArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, short, required = true)]
+    output: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct ExtractArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, required_unless_present = "indices")]
+    sfen: Vec<String>,
+
+    #[arg(long, value_delimiter = ',')]
+    indices: Vec<IndexRange>,
+
+    #[arg(long, short, required = true)]
+    output: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct IndexRange {
+    start: usize,
+    end: usize,
+}
+
+impl IndexRange {
+    fn contains(&self, index: usize) -> bool {
+        (self.start..=self.end).contains(&index)
+    }
+}
+
+impl FromStr for IndexRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::other(format!("Invalid index range: {}", s));
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let start = start.parse().map_err(|_| invalid())?;
+        let end = end.parse().map_err(|_| invalid())?;
+
+        if start > end {
+            return Err(invalid());
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+#[derive(Parser, Debug)]
+struct VerifyArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long)]
+    repair: bool,
+
+    #[arg(long, value_enum, value_delimiter = ',')]
+    rules: Vec<verify::Rule>,
+}
+
+#[derive(Parser, Debug)]
+struct SortArgs {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[arg(long, short, value_enum, default_value_t = SortBy::Hash)]
+    key: SortBy,
+
+    #[arg(long, short, required = true)]
+    output: PathBuf,
+}
+
+/// What each heatmap percentage is relative to
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Normalize {
+    Total,
+    Row,
+    Column,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SortBy {
+    Length,
+    Hash,
+    FirstMove,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Length(usize),
+    Hash(u64),
+    FirstMove(String),
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let command = &cli.command;
+
+    command.common().configure_buffers();
+    progress::configure(command.common().progress);
+
+    if let Command::BuildDataset(args) = command {
+        return dataset::build(args);
+    }
+
+    if let Command::StatsDb(args) = command {
+        return stats_db::run(args);
+    }
+
+    let args = command.common();
+
+    let mut paths = Vec::new();
+    let mut labels = HashMap::new();
+    let mut extracted = HashSet::new();
+    // Only count reads sequentially, so it can stream remote inputs and tar/zip members instead
+    // of caching them; archive takes its bundles as they are
+    let stream = matches!(
+        command,
+        Command::Count(_) | Command::Report(_) | Command::Compare(_) | Command::Archive(_)
+    );
+    let rewrites_input = match command {
+        Command::Fix(_)
+        | Command::Shuffle(_)
+        | Command::Trim(_)
+        | Command::Filter(_)
+        | Command::Augment(_)
+        | Command::Relabel(_)
+        | Command::Remap(_) => true,
+        Command::Verify(args) => args.repair,
+        Command::NearDuplicates(args) => args.keep.is_some(),
+        _ => false,
+    };
+
+    if let Command::Compare(args) = command {
+        if args.labels().len() != 2 {
+            return Err(Error::other("compare takes exactly two runs"));
+        }
+    }
+
+    for path in args.paths.clone() {
+        let resolved = resolve_path(&path, args, stream, &mut extracted)?;
+
+        // Each directory given to compare is one run
+        if let Command::Compare(_) = command {
+            for resolved in &resolved {
+                labels.insert(resolved.clone(), path.display().to_string());
+            }
+        }
+
+        paths.extend(resolved);
+    }
+
+    for input in &args.input {
+        for path in resolve_path(&input.path, args, stream, &mut extracted)? {
+            labels.insert(path.clone(), input.label.clone());
+            paths.push(path);
+        }
+    }
+
+    let extensions: &[&str] = match command {
+        Command::Frame(args) if args.unframe => &[frame::EXTENSION],
+        Command::Compress(args) if args.decompress => &[compress::EXTENSION],
+        Command::Fix(_) => &["spk", frame::EXTENSION],
+        Command::Import(_) => &["jsonl"],
+        Command::Archive(args) if args.verify => &["zst"],
+        Command::Frame(_) | Command::Compress(_) | Command::Prune(_) | Command::Archive(_) => {
+            &["spk"]
+        }
+        _ => &["spk", compress::EXTENSION],
+    };
+    let bundles = matches!(
+        command,
+        Command::Count(_) | Command::Report(_) | Command::Compare(_)
+    );
+    paths = paths
+        .into_iter()
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| extensions.contains(&ext))
+                || (bundles && archive::is_bundle(path))
+        })
+        .collect();
+
+    if rewrites_input && (args.limit.is_some() || args.skip > 0) {
+        return Err(Error::other(
+            "--limit and --skip only apply to commands that leave their inputs unchanged",
+        ));
+    }
+
+    if rewrites_input && !extracted.is_empty() {
+        return Err(Error::other(
+            "Files inside tar/zip archives cannot be rewritten in place, unpack them first",
+        ));
+    }
+
+    // Compressed inputs are unpacked into the cache and handled as plain files from there on,
+    // except where the block index lets a command read just the records it needs
+    let mut compressed = HashMap::new();
+    let random_access = matches!(command, Command::Extract(args) if args.sfen.is_empty());
+
+    if !random_access && !matches!(command, Command::Compress(_) | Command::Info(_)) {
+        for path in &mut paths {
+            if compress::is_compressed(path) {
+                let mut hasher = DefaultHasher::new();
+                path.hash(&mut hasher);
+
+                let plain = cache_dir(args).join(format!(
+                    "{:016x}-{}.spk",
+                    hasher.finish(),
+                    path.file_stem().unwrap_or_default().to_string_lossy()
+                ));
+                create_dir_all(cache_dir(args))?;
+                compress::unpack(path, &plain)?;
+
+                if let Some(label) = labels.remove(path) {
+                    labels.insert(plain.clone(), label);
+                }
+
+                compressed.insert(plain.clone(), std::mem::replace(path, plain));
+            }
+        }
+    }
+
+    println!("Checking {} files...", paths.len());
+
+    if let Command::Serve(args) = command {
+        serve::serve(&paths, args)?;
+
+        // Unpacked copies are only needed while serving
+        for path in compressed.keys().chain(&extracted) {
+            remove_file(path)?;
+        }
+
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let files = paths.len();
+
+    let mut count_stats = match command {
+        Command::Count(args) => Some(CountStats::new(args)),
+        Command::Report(args) => Some(CountStats::new(&args.count)),
+        _ => None,
+    };
+    let mut label_stats = BTreeMap::new();
+    let mut total_records = 0;
+    let mut total_broken_records = 0;
+    let mut total_trimmed_bytes = 0;
+    let mut fixed_files = 0;
+    let mut trimmed_games = 0;
+    let mut trimmed_moves = 0;
+    let mut dropped_games = 0;
+    let mut added_games = 0;
+    let mut filter_state = match command {
+        Command::Filter(args) => FilterState::new(args)?,
+        _ => FilterState::default(),
+    };
+    let mut convert_sampler = None;
+    let position_filter = match command {
+        Command::Convert(args) => convert::PositionFilter::new(args),
+        _ => convert::PositionFilter::default(),
+    };
+    let eval_table = match command {
+        Command::Remap(args) => Some(remap::EvalTable::load(&args.table)?),
+        _ => None,
+    };
+
+    // Sampling to a target needs the number of eligible positions up front
+    match command {
+        Command::Filter(args) => {
+            if args.drop_ply_capped {
+                filter_state.ply_cap = match args.max_ply {
+                    Some(cap) => Some(cap),
+                    None => ending::longest_draw(&paths)?,
+                };
+            }
+
+            if let Some(target) = args.target_positions {
+                let mut state = FilterState::new(args)?;
+                state.ply_cap = filter_state.ply_cap;
+                let total = count_eligible_positions(&paths, |game| {
+                    keep_game(game, args, &mut state)
+                        .then(|| filter_positions(game, args.eval_limit))
+                })?;
+                filter_state.sampler = Some(PositionSampler::new(target, total, args.seed));
+            }
+        }
+        Command::Convert(args) => {
+            if let Some(target) = args.target_positions {
+                let total = count_eligible_positions(&paths, |game| {
+                    Some(position_filter.eligible_positions(game, args.eval_limit))
+                })?;
+                convert_sampler = Some(PositionSampler::new(target, total, args.seed));
+            }
+        }
+        _ => {}
+    }
+    let mut startpos_counts = HashMap::new();
+    let mut extracted_games = 0;
+    let mut illegal_games = 0;
+    let mut rule_games = Vec::new();
+    let mut mismatched_records = 0;
+    let mut relabeled_games = 0;
+    let mut extract_targets = HashSet::new();
+    let mut extract_writer = None;
+
+    if let Command::Extract(args) = command {
+        for sfen in &args.sfen {
+            let pos = Position::from_sfen(sfen)
+                .map_err(|_| Error::other(format!("Invalid SFEN: {}", sfen)))?;
+            extract_targets.insert(pos.key());
+        }
+
+        extract_writer = Some(buffer::writer(File::create(&args.output)?));
+    }
+
+    let mut import_writer = match command {
+        Command::Import(args) => Some(buffer::writer(File::create(&args.output)?)),
+        _ => None,
+    };
+    let mut game_hashes = HashMap::new();
+    let mut prune_files = Vec::new();
+    let mut archive_entries = Vec::new();
+    let mut failed_files = 0;
+    let mut prefix_clusters = HashMap::new();
+    let mut adjudication_stats = adjudication::AdjudicationStats::default();
+    let mut splitter = match command {
+        Command::Split(args) => Some(split::Splitter::new(args)?),
+        _ => None,
+    };
+    let mut sorted_records = Vec::new();
+    let mut file_summaries = Vec::new();
+    let mut converted_positions = 0;
+    let mut clipped_positions = 0;
+    let mut sente_positions = 0;
+    let mut game_id = 0;
+    let mut converter = match command {
+        Command::Convert(args) => Some(convert::pipelined(convert::create(args)?)),
+        Command::Features(args) => Some(convert::pipelined(Box::new(
+            features::FeatureWriter::create(&args.output, args.feature_set)?,
+        ))),
+        _ => None,
+    };
+    let mut exporter = match command {
+        Command::Export(args) => Some(export::create(args.format, &args.output)?),
+        _ => None,
+    };
+
+    for (index, path) in paths.into_iter().enumerate() {
+        let input = path.clone();
+        let file_started = Instant::now();
+        let records_before = total_records;
+        // Sidecars and uploads belong to the file that was given, not its unpacked copy
+        let original = compressed.get(&input).unwrap_or(&input).clone();
+        let mut upload = None;
+        progress::file_started(&input, index, files);
+
+        // Runs the command on one file, so any I/O failure inside can be pinned to that file
+        let processed = (|| -> Result<()> {
+            match command {
+                Command::Count(args) => {
+                    let stats = match labels.get(&path) {
+                        Some(label) => label_stats
+                            .entry(label.clone())
+                            .or_insert_with(|| CountStats::new(args)),
+                        None => count_stats.as_mut().unwrap(),
+                    };
+
+                    if args.sidecar {
+                        let mut file_stats = CountStats::new(args);
+                        total_records += count(path.clone(), args, &mut file_stats)?;
+                        sidecar::update(
+                            &original,
+                            "count",
+                            sidecar::count_summary(&args.bias_plies, &file_stats),
+                        )?;
+                        stats.merge(&file_stats);
+                    } else {
+                        total_records += count(path, args, stats)?;
+                    }
+                }
+                Command::Fix(args) => {
+                    let (records, broken_records, trimmed_bytes) = if frame::is_framed(&path) {
+                        frame::fix(&path)?
+                    } else {
+                        fix(path.clone())?
+                    };
+
+                    if args.sidecar {
+                        sidecar::update(
+                            &original,
+                            "fix",
+                            sidecar::fix_summary(records, broken_records, trimmed_bytes),
+                        )?;
+                    }
+
+                    upload = args.upload.as_ref();
+
+                    total_records += records;
+                    total_broken_records += broken_records;
+                    total_trimmed_bytes += trimmed_bytes;
+
+                    if broken_records != 0 {
+                        fixed_files += 1;
+                    }
+                }
+                Command::Shuffle(args) => {
+                    let (records, broken_records) = shuffle(path.clone(), args)?;
+                    upload = args.upload.as_ref();
+
+                    total_records += records;
+                    total_broken_records += broken_records;
+                }
+                Command::Trim(args) => {
+                    let (records, games, moves) = trim(path, args.trim_after_eval, args.plies)?;
+                    total_records += records;
+                    trimmed_games += games;
+                    trimmed_moves += moves;
+                }
+                Command::Filter(args) => {
+                    let (records, dropped) = filter(path, args, &mut filter_state)?;
+                    total_records += records;
+                    dropped_games += dropped;
+                }
+                Command::Sort(args) => {
+                    total_records += get_sort_keys(path, args.key, &mut sorted_records)?;
+                }
+                Command::Report(args) => {
+                    let stats = match labels.get(&path) {
+                        Some(label) => label_stats
+                            .entry(label.clone())
+                            .or_insert_with(|| CountStats::new(&args.count)),
+                        None => count_stats.as_mut().unwrap(),
+                    };
+                    let before = report::FileSummary::new(&path, stats);
+
+                    total_records += count(path.clone(), &args.count, stats)?;
+                    file_summaries.push(report::FileSummary::new(&path, stats).since(&before));
+                }
+                Command::Compare(args) => {
+                    let stats = label_stats
+                        .entry(labels[&path].clone())
+                        .or_insert_with(|| CountStats::new(&args.count));
+
+                    total_records += count(path, &args.count, stats)?;
+                }
+                Command::Convert(ConvertArgs {
+                    eval_limit,
+                    clip_scores,
+                    ..
+                })
+                | Command::Features(FeaturesArgs {
+                    eval_limit,
+                    clip_scores,
+                    ..
+                }) => {
+                    let (games, positions, clipped, sente) = convert::convert(
+                        &path,
+                        *eval_limit,
+                        *clip_scores,
+                        &position_filter,
+                        converter.as_deref_mut().unwrap(),
+                        &mut game_id,
+                        convert_sampler.as_mut(),
+                    )?;
+                    total_records += games;
+                    converted_positions += positions;
+                    clipped_positions += clipped;
+                    sente_positions += sente;
+                }
+                Command::Augment(args) => {
+                    let (records, added) = augment::augment(&path, args)?;
+                    total_records += records;
+                    added_games += added;
+                }
+                Command::Verify(args) => {
+                    let (records, illegal, rules) = verify::verify(&path, args)?;
+                    total_records += records;
+                    illegal_games += illegal;
+                    rule_games.resize(rules.len(), 0);
+
+                    for (total, games) in rule_games.iter_mut().zip(rules) {
+                        *total += games;
+                    }
+                }
+                Command::Extract(args) => {
+                    let (records, extracted) = extract(
+                        path,
+                        &extract_targets,
+                        &args.indices,
+                        extract_writer.as_mut().unwrap(),
+                    )?;
+                    total_records += records;
+                    extracted_games += extracted;
+                }
+                Command::BuildDataset(_) => unreachable!("build-dataset does not scan input paths"),
+                Command::Serve(_) => unreachable!("serve streams its input paths itself"),
+                Command::StatsDb(_) => unreachable!("stats-db does not scan input paths"),
+                Command::NearDuplicates(args) => {
+                    let (records, dropped) = near_duplicates(path, args, &mut prefix_clusters)?;
+                    total_records += records;
+                    dropped_games += dropped;
+                }
+                Command::Remap(_) => {
+                    total_records += remap::remap(&path, eval_table.as_ref().unwrap())?;
+                }
+                Command::SimulateAdjudication(args) => {
+                    adjudication::simulate(&path, args, &mut adjudication_stats)?;
+                }
+                Command::Prune(_) => {
+                    prune_files.push(prune::scan(path)?);
+                }
+                Command::Relabel(_) => {
+                    let (records, relabeled) = relabel(path)?;
+                    total_records += records;
+                    relabeled_games += relabeled;
+                }
+                Command::Frame(args) => {
+                    total_records += frame::convert(&path, args)?;
+                }
+                Command::Compress(args) => {
+                    total_records += compress::convert(&path, args)?;
+                }
+                Command::Archive(args) => {
+                    if args.verify {
+                        let (checked, failed) = archive::verify(&path)?;
+                        total_records += checked;
+                        failed_files += failed;
+                    } else {
+                        archive_entries.push(archive::scan(&path)?);
+                    }
+                }
+                Command::Split(_) => {
+                    total_records += splitter.as_mut().unwrap().split(&path)?;
+                }
+                Command::Selftest(_) => {
+                    let (records, mismatched) = selftest(path)?;
+                    total_records += records;
+                    mismatched_records += mismatched;
+                }
+                Command::Duplicates(_) => {
+                    total_records += get_game_hashes(path, &mut game_hashes)?;
+                }
+                Command::Info(_) => {
+                    total_records += info::info(&path)?;
+                }
+                Command::Show(args) => {
+                    total_records += show::show(&path, args)?;
+                }
+                Command::Import(args) => {
+                    total_records +=
+                        import::import(&path, args.format, import_writer.as_mut().unwrap())?;
+                }
+                Command::Startpos(_) => {
+                    total_records += get_startpos_counts(path, &mut startpos_counts)?;
+                }
+                Command::Export(_) => {
+                    total_records +=
+                        export::export(&path, exporter.as_deref_mut().unwrap(), &mut game_id)?;
+                }
+            }
+
+            Ok(())
+        })();
+        processed.map_err(|error| error.in_file(&original))?;
+        progress::file_finished(
+            &input,
+            total_records - records_before,
+            metadata(&input).map_or(0, |metadata| metadata.len()),
+            file_started,
+        );
+
+        if extracted.contains(&input) {
+            remove_file(&input)?;
+        }
+
+        match compressed.get(&input) {
+            Some(archive) => {
+                if rewrites_input {
+                    let (level, block_kib) = compress::settings(archive)?;
+                    compress::pack(&input, archive, level, block_kib)?;
+                    checksum::refresh(archive)?;
+                }
+
+                remove_file(&input)?;
+            }
+            None if rewrites_input => checksum::refresh(&input)?,
+            None => {}
+        }
+
+        // Only once any repacking is done, so the upload is the finished file
+        if let Some(target) = upload {
+            target.upload(&original)?;
+        }
+    }
+
+    if let Some(stats) = count_stats.as_mut() {
+        for label_stats in label_stats.values() {
+            stats.merge(label_stats);
+        }
+    }
+
+    if let Some(converter) = converter {
+        converter.finish()?;
+    }
+
+    if let Some(exporter) = exporter {
+        exporter.finish()?;
+    }
+
+    if let Some(mut writer) = extract_writer {
+        writer.flush()?;
+    }
+
+    if let Some(mut writer) = import_writer {
+        writer.flush()?;
+    }
+
+    if let Command::Startpos(args) = command {
+        write_startpos_counts(&args.output, &startpos_counts)?;
+    }
+
+    if let Command::Sort(args) = command {
+        sorted_records.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let buffer = sorted_records
+            .into_iter()
+            .flat_map(|(_, buffer)| buffer)
+            .collect::<Vec<_>>();
+        replace_file(&args.output, &buffer)?;
+    }
+
+    println!("               Summary               ");
+    println!("-------------------------------------");
+
+    match command {
+        Command::Count(args) => {
+            let stats = count_stats.unwrap();
+
+            print_count(args, &stats);
+
+            for (label, stats) in &label_stats {
+                println!("-------------------------------------");
+                println!("Source: {}", label);
+                print_count(args, stats);
+            }
+
+            if let Some(path) = &args.json_out {
+                sidecar::write_json(path, &sidecar::count_summary(&args.bias_plies, &stats))?;
+            }
+
+            if let Some(path) = &args.chart_out {
+                write_charts(path, &stats)?;
+            }
+
+            if let Some(path) = &args.list_reverses {
+                write_reverse_games(path, &stats.reverse_games)?;
+            }
+
+            if !args.quick {
+                if let Some(path) = &args.heatmap_out {
+                    write_heatmaps(path, &stats)?;
+                }
+
+                if let Some(path) = &args.heatmap_csv {
+                    heatmap::write_csv(path, &all_heatmaps(&stats))?;
+                    println!("Heatmap matrices written to {}", path.display());
+                }
+            }
+        }
+        Command::Trim(_) => {
+            println!("Total records: {}", total_records);
+            println!("Trimmed games: {}", trimmed_games);
+            println!("Trimmed moves: {}", trimmed_moves);
+        }
+        Command::Filter(args) => {
+            println!("Total records: {}", total_records);
+            println!("Dropped games: {}", dropped_games);
+
+            if args.min_ply > 0 {
+                println!("Short games  : {}", filter_state.short_games);
+            }
+
+            if args.drop_illegal_drops {
+                println!("Illegal drops: {}", filter_state.illegal_drop_games);
+            }
+
+            if let Some(cap) = filter_state.ply_cap {
+                println!(
+                    "Ply-capped   : {} (draws of {}+ plies)",
+                    filter_state.ply_capped_games, cap
+                );
+            }
+
+            if args.max_per_startpos.is_some() {
+                print_startpos_distribution(&filter_state);
+            }
+
+            print_positions_per_game(&filter_state);
+        }
+        Command::Sort(args) => {
+            println!("Total records: {}", total_records);
+            println!("Output: {}", args.output.display());
+        }
+        Command::Convert(ConvertArgs {
+            output,
+            clip_scores,
+            ..
+        })
+        | Command::Features(FeaturesArgs {
+            output,
+            clip_scores,
+            ..
+        }) => {
+            println!("Total games    : {}", total_records);
+            println!("Total positions: {}", converted_positions);
+
+            if clip_scores.is_some() {
+                println!(
+                    "Clipped        : {} ({:.2}%)",
+                    clipped_positions,
+                    clipped_positions as f64 / converted_positions as f64 * 100.0f64
+                );
+            }
+
+            print_parity(sente_positions, converted_positions);
+            println!("Output: {}", output.display());
+        }
+        Command::Augment(_) => {
+            println!("Total records: {}", total_records);
+            println!("Added games: {}", added_games);
+        }
+        Command::Verify(args) => {
+            println!("Total records: {}", total_records);
+            println!("Illegal games: {}", illegal_games);
+
+            for (rule, games) in args.rules.iter().zip(&rule_games) {
+                println!("Games breaking {}: {}", rule.name(), games);
+            }
+
+            if args.repair {
+                println!("Repaired games: {}", illegal_games);
+            }
+        }
+        Command::Extract(args) => {
+            println!("Total records: {}", total_records);
+            println!("Extracted games: {}", extracted_games);
+            println!("Output: {}", args.output.display());
+        }
+        Command::NearDuplicates(args) => {
+            print_prefix_clusters(args.top, &prefix_clusters);
+
+            println!("Total records: {}", total_records);
+
+            if args.keep.is_some() {
+                println!("Dropped games: {}", dropped_games);
+            }
+        }
+        Command::Remap(_) => {
+            println!("Total records: {}", total_records);
+        }
+        Command::SimulateAdjudication(_) => {
+            let stats = &adjudication_stats;
+            let adjudicated = stats.resigned + stats.drawn;
+
+            println!("Total games  : {}", stats.games);
+            println!(
+                "Adjudicated  : {: <8} ({:.2}%)",
+                adjudicated,
+                adjudicated as f64 / stats.games as f64 * 100.0f64
+            );
+            println!("  Resigned   : {}", stats.resigned);
+            println!("  Drawn      : {}", stats.drawn);
+            println!(
+                "Plies saved  : {} ({:.2} per adjudicated game)",
+                stats.plies_saved,
+                stats.plies_saved as f64 / adjudicated as f64
+            );
+            println!(
+                "Disagreements: {: <8} ({:.2}% of adjudicated)",
+                stats.disagreements,
+                stats.disagreements as f64 / adjudicated as f64 * 100.0f64
+            );
+        }
+        Command::Prune(args) => {
+            let total_positions = prune_files.iter().map(|file| file.positions).sum::<u64>();
+            let (kept, removed_files) = prune::prune(args, prune_files)?;
+
+            println!("Total positions  : {}", total_positions);
+            println!("Kept positions   : {}", kept);
+            println!("Removed positions: {}", total_positions - kept);
+            println!("Removed files    : {}", removed_files);
+        }
+        Command::Relabel(_) => {
+            println!("Total records: {}", total_records);
+            println!("Relabeled games: {}", relabeled_games);
+        }
+        Command::Archive(args) => {
+            if args.verify {
+                println!("Checked files: {}", total_records);
+                println!("Failed files : {}", failed_files);
+            } else {
+                let output = args.output.as_ref().unwrap();
+                archive::write(output, &archive_entries)?;
+
+                println!("Archived files: {}", archive_entries.len());
+                println!("Output: {}", output.display());
+            }
+        }
+        Command::Frame(_) | Command::Compress(_) | Command::Info(_) | Command::Show(_) => {
+            println!("Total records: {}", total_records);
+        }
+        Command::Split(args) => {
+            println!("Total records: {}", total_records);
+
+            for (bucket, count) in splitter.unwrap().finish()? {
+                println!("{}: {}", bucket, count);
+            }
+
+            println!("Output: {}", args.output_dir.display());
+        }
+        Command::Selftest(_) => {
+            println!("Total records: {}", total_records);
+            println!("Mismatched records: {}", mismatched_records);
+        }
+        Command::Duplicates(_) => {
+            let duplicate_groups = print_duplicates(&game_hashes);
+
+            println!("Total records: {}", total_records);
+            println!("Duplicate groups: {}", duplicate_groups);
+            println!("Duplicate games: {}", total_records - game_hashes.len());
+        }
+        Command::Startpos(args) => {
+            println!("Total games    : {}", total_records);
+            println!("Start positions: {}", startpos_counts.len());
+            println!("Output: {}", args.output.display());
+        }
+        Command::Export(args) => {
+            println!("Total games: {}", total_records);
+            println!("Output: {}", args.output.display());
+        }
+        Command::Import(args) => {
+            println!("Total games: {}", total_records);
+            println!("Output: {}", args.output.display());
+        }
+        Command::Report(args) => {
+            let stats = count_stats.unwrap();
+
+            let sources = label_stats
+                .iter()
+                .map(|(label, stats)| report::FileSummary::new(Path::new(label), stats))
+                .collect::<Vec<_>>();
+
+            report::write_html(&args.output, &stats, &file_summaries, &sources)?;
+
+            println!("Total games    : {}", stats.games());
+            println!("Total positions: {}", stats.positions);
+            println!("Report written to {}", args.output.display());
+        }
+        Command::Compare(args) => {
+            let labels = args.labels();
+            let empty = CountStats::new(&args.count);
+            let stats = [0, 1].map(|idx| label_stats.get(&labels[idx]).unwrap_or(&empty));
+
+            compare::print(&args.count, [&labels[0], &labels[1]], stats);
+        }
+        _ => {
+            println!("Total records: {}", total_records);
+            println!("Total broken records: {}", total_broken_records);
+            println!("Total trimmed bytes: {}", total_trimmed_bytes);
+            println!("Fixed files: {}", fixed_files);
+        }
+    }
+
+    progress::summary(files, total_records, started);
+
+    Ok(())
+}
+
+fn add_counts(a: &mut [u64], b: &[u64]) {
+    for (a, b) in a.iter_mut().zip(b) {
+        *a += b;
+    }
+}
+
+fn resolve_path(
+    path: &Path,
+    args: &CommonArgs,
+    stream: bool,
+    extracted: &mut HashSet<PathBuf>,
+) -> Result<Vec<PathBuf>> {
+    if archive::is_bundle(path) && !stream {
+        let members = archive::extract(path, &cache_dir(args))?;
+        extracted.extend(members.iter().cloned());
+
+        return Ok(members);
+    }
+
+    if !remote::is_remote(path) {
+        return expand_path(path, args.recursive);
+    }
+
+    if stream && args.cache_dir.is_none() {
+        Ok(vec![path.to_path_buf()])
+    } else {
+        Ok(vec![remote::fetch(path, &cache_dir(args))?])
+    }
+}
+
+fn cache_dir(args: &CommonArgs) -> PathBuf {
+    args.cache_dir
+        .clone()
+        .unwrap_or_else(|| env::temp_dir().join("spk-tools-cache"))
+}
+
+fn expand_path(path: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    if path.is_file() {
+        Ok(vec![path.to_path_buf()])
+    } else if path.is_dir() {
+        get_files(path, recursive)
+    } else {
+        eprintln!("Invalid path: {}", path.display());
+        Ok(Vec::new())
+    }
+}
+
+fn get_files(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut result = Vec::new();
+
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if path.is_file() {
+            result.push(path);
+        } else if path.is_dir() && recursive {
+            result.extend(get_files(&path, recursive)?);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Counts every game of a file, or of every member of a bundle, returning how many were read.
+fn count(path: PathBuf, args: &CountArgs, stats: &mut CountStats) -> Result<usize> {
+    if archive::is_bundle(&path) {
+        let mut records = 0;
+        archive::for_each_member(&path, |member, reader, len| {
+            records += count_reader(member, reader, len, args, stats)?;
+
+            Ok(())
+        })?;
+
+        return Ok(records);
+    }
+
+    let (mut input, len) = remote::open(&path)?;
+    count_reader(path, &mut input, len, args, stats)
+}
+
+fn count_reader(
+    path: PathBuf,
+    input: &mut dyn Read,
+    len: u64,
+    args: &CountArgs,
+    stats: &mut CountStats,
+) -> Result<usize> {
+    let mut reader = TeeReader::new(buffer::reader(input));
+    let eval_limit = args.eval_limit;
+    let mut index = 0;
+
+    while reader.position < len && args.common.within_limit(index) {
+        let offset = reader.position;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(&path, offset)?;
+        reader.take_captured();
+        progress::record(&path);
+
+        if args.common.skipped(index) {
+            index += 1;
+            continue;
+        }
+
+        match game.wdl {
+            Outcome::SenteWin => stats.black_wins += 1,
+            Outcome::SenteLoss => stats.white_wins += 1,
+            Outcome::Draw => stats.draws += 1,
+        }
+
+        stats.positions += game
+            .moves
+            .iter()
+            .filter(|(_, score)| score.abs() <= eval_limit)
+            .count()
+            + 1;
+
+        if let Some(ply) = reverse_ply(&game, eval_limit) {
+            stats.reverses += 1;
+
+            if args.list_reverses.is_some() {
+                stats
+                    .reverse_games
+                    .push((path.clone(), index, offset, ply, game.moves[ply].1));
+            }
+        }
+
+        index += 1;
+
+        for &(_, score) in game
+            .moves
+            .iter()
+            .filter(|(_, score)| score.abs() <= eval_limit)
+        {
+            let bucket = (score.clamp(-EVAL_RANGE, EVAL_RANGE - 1) + EVAL_RANGE) / EVAL_BUCKET;
+            stats.eval_histogram[bucket as usize] += 1;
+            stats.record_score(game.wdl, score);
+        }
+
+        stats.length_histogram[(game.moves.len() / LENGTH_BUCKET).min(LENGTH_BUCKETS - 1)] += 1;
+
+        for (&ply, (sum, count)) in args.bias_plies.iter().zip(stats.ply_scores.iter_mut()) {
+            if let Some((_, score)) = game
+                .moves
+                .get(ply)
+                .filter(|(_, score)| score.abs() <= eval_limit)
+            {
+                *sum += *score as i64;
+                *count += 1;
+            }
+        }
+
+        for (moves, counts) in stats.opening_moves.iter_mut().enumerate() {
+            if game.moves.len() > moves {
+                let line = game.moves[..=moves]
+                    .iter()
+                    .map(|(mv, _)| mv.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                *counts.entry(line).or_default() += 1;
+            }
+        }
+
+        if !args.quick {
+            let mut pos = game.startpos.clone();
+            // Hashes the move order so far, so equal keys with different paths are transpositions
+            let mut path = pos.key();
+            let mut keys = vec![pos.key()];
+            stats.record_position(&pos, 0);
+            stats.record_outcome_kings(&pos, game.wdl);
+
+            if args.transpositions {
+                stats.record_transposition(&pos, path);
+            }
+
+            for (ply, mv) in game.moves.iter().enumerate() {
+                if args.top_positions > 0 {
+                    stats.record_position_count(&pos, mv.1);
+                }
+
+                let stm = pos.stm();
+                // A capture changes the material balance, so its score jump is not noise
+                let quiet_next = game
+                    .moves
+                    .get(ply + 1)
+                    .map(|&(_, next)| next)
+                    .filter(|next| mv.1.abs() <= eval_limit && next.abs() <= eval_limit)
+                    .filter(|_| !is_capture(&pos, mv.0));
+
+                stats.record_drop(stm, &mv.0.to_string());
+                pos = pos.apply_move(mv.0);
+                keys.push(pos.key());
+                stats.record_position(&pos, ply + 1);
+                stats.record_outcome_kings(&pos, game.wdl);
+
+                if let Some(next) = quiet_next {
+                    stats.score_noise.record(mv.1, next);
+                }
+
+                if args.transpositions {
+                    let mut hasher = DefaultHasher::new();
+                    (path, mv.0.to_string()).hash(&mut hasher);
+                    path = hasher.finish();
+
+                    stats.record_transposition(&pos, path);
+                }
+            }
+
+            stats.endings.record(&game, &pos, &keys);
+        }
+    }
+
+    Ok(index)
+}
+
+fn fix(path: PathBuf) -> Result<(usize, usize, u64)> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let len = file.metadata()?.len();
+    let mut reader = TeeReader::new(buffer::reader(ChecksumReader::new(&file, &path)?));
+    let tmp_path = path.with_extension("spk.tmp");
+    let mut writer: Option<BufWriter<File>> = None;
+    let mut records = 0;
+    let mut broken_records = 0;
+    let mut valid_bytes = 0;
+    let mut trimmed_bytes = 0;
+
+    // Valid records are only copied out once the first broken one shows up
+    while reader.position < len {
+        match Stoatpack::deserialise(&mut reader) {
+            Ok(_) => {
+                let game_buffer = reader.take_captured();
+
+                if let Some(writer) = writer.as_mut() {
+                    writer.write_all(&game_buffer)?;
+                }
+
+                records += 1;
+                valid_bytes += game_buffer.len() as u64;
+            }
+            Err(_) => {
+                reader.take_captured();
+                broken_records += 1;
+
+                if writer.is_none() {
+                    let mut tmp = buffer::writer(File::create(&tmp_path)?);
+                    copy(&mut File::open(&path)?.take(valid_bytes), &mut tmp)?;
+                    writer = Some(tmp);
+                }
+            }
+        }
+    }
+
+    if let Some(mut writer) = writer {
+        writer.flush()?;
+        drop(writer);
+        rename(&tmp_path, &path)?;
+        trimmed_bytes = len - valid_bytes;
+
+        println!(
+            "Fixed : {}, {} records, {} broken records, {} bytes trimmed",
+            path.display(),
+            records,
+            broken_records,
+            trimmed_bytes
+        );
+    } else {
+        println!("  OK  : {}, {} records", path.display(), records);
+    }
+
+    Ok((records, broken_records, trimmed_bytes))
+}
+
+fn shuffle(path: PathBuf, args: &ShuffleArgs) -> Result<(usize, usize)> {
+    let mut file = OpenOptions::new().read(true).open(&path)?;
+    let len = file.metadata()?.len();
+    let mut reader = TeeReader::new(buffer::reader(ChecksumReader::new(&file, &path)?));
+    let mut records = Vec::new();
+    let mut expected = Vec::new();
+    let mut broken_records = 0;
+    let mut prev_pos = 0;
+
+    // Only the (offset, length) index and the verification hashes are held in memory, so
+    // --max-mem caps how many records they may hold, checked before they grow rather than after
+    let record_mem = size_of::<(u64, usize)>() + if args.verify { size_of::<u64>() } else { 0 };
+    let max_records = args.max_mem.map_or(usize::MAX, |max_mem| {
+        max_mem.saturating_mul(1 << 20) as usize / record_mem
+    });
+
+    while reader.position < len {
+        match Stoatpack::deserialise(&mut reader) {
+            Ok(_) => {
+                let game_buffer = reader.take_captured();
+
+                if !push_within(&mut records, (prev_pos, game_buffer.len()), max_records) {
+                    // Extrapolates from the share of the file read so far
+                    let required = (records.len() * record_mem) as u64 * len / reader.position;
+
+                    return Err(Error::other(format!(
+                        "Shuffling {} needs about {} MiB, exceeding --max-mem {} MiB",
+                        path.display(),
+                        required.div_ceil(1 << 20),
+                        args.max_mem.unwrap_or_default()
+                    )));
+                }
+
+                if args.verify {
+                    push_within(&mut expected, game_hash(&game_buffer), max_records);
+                }
+
+                prev_pos = reader.position;
+            }
+            Err(_) => {
+                broken_records += 1;
+            }
+        }
+    }
+
+    if broken_records == 0 {
+        permutation::shuffle(&mut records, args.seed, args.rng_version)?;
+
+        let tmp_path = path.with_extension("spk.tmp");
+        let mut writer = buffer::writer(File::create(&tmp_path)?);
+        let mut game_buffer = Vec::new();
+
+        for &(offset, size) in &records {
+            game_buffer.resize(size, 0);
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut game_buffer)?;
+            writer.write_all(&game_buffer)?;
+        }
+
+        writer.flush()?;
+        drop(writer);
+
+        if args.verify {
+            expected.sort_unstable();
+
+            if sorted_game_hashes(&File::open(&tmp_path)?)? != expected {
+                remove_file(&tmp_path)?;
+
+                return Err(Error::other(format!(
+                    "Verification failed: {}, shuffled output does not match the input records",
+                    path.display()
+                )));
+            }
+        }
+
+        rename(&tmp_path, &path)?;
+    } else {
+        println!(
+            "Shuffling is skipped because {} broken records",
+            broken_records
+        );
+    }
+
+    Ok((records.len(), broken_records))
+}
+
+/// Pushes `value` unless `vec` already holds `limit` elements, growing it by doubling like
+/// `push` but never reserving room past `limit`.
+fn push_within<T>(vec: &mut Vec<T>, value: T, limit: usize) -> bool {
+    if vec.len() >= limit {
+        return false;
+    }
+
+    if vec.len() == vec.capacity() {
+        vec.reserve_exact(vec.len().max(4).min(limit - vec.len()));
+    }
+
+    vec.push(value);
+
+    true
+}
+
+fn trim(path: PathBuf, threshold: i16, plies: usize) -> Result<(usize, usize, usize)> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, &path)?);
+    let len = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    let mut records = 0;
+    let mut trimmed_games = 0;
+    let mut trimmed_moves = 0;
+
+    while reader.stream_position()? < len {
+        let offset = reader.stream_position()?;
+        let mut game = Stoatpack::deserialise(&mut reader).at_offset(&path, offset)?;
+
+        // The threshold only counts as crossed once the score never drops back below it
+        let crossed = game
+            .moves
+            .iter()
+            .rposition(|(_, score)| score.unsigned_abs() < threshold.unsigned_abs())
+            .map_or(0, |idx| idx + 1);
+        let keep = crossed + plies;
+
+        if keep < game.moves.len() {
+            trimmed_games += 1;
+            trimmed_moves += game.moves.len() - keep;
+            game.moves.truncate(keep);
+        }
+
+        game.serialise(&mut buffer)?;
+        records += 1;
+    }
+
+    if trimmed_games == 0 {
+        println!("  OK  : {}, {} records", path.display(), records);
+    } else {
+        replace_file(&path, &buffer)?;
+
+        println!(
+            "Trim  : {}, {} records, {} games trimmed, {} moves removed",
+            path.display(),
+            records,
+            trimmed_games,
+            trimmed_moves
+        );
+    }
+
+    Ok((records, trimmed_games, trimmed_moves))
+}
+
+fn keep_game(game: &Stoatpack, args: &FilterArgs, state: &mut FilterState) -> bool {
+    let mut keep = !(args.drop_reverses && is_reverse(game, args.eval_limit))
+        && filter_positions(game, args.eval_limit) >= args.min_positions;
+
+    if game.moves.len() < args.min_ply {
+        state.short_games += 1;
+        keep = false;
+    }
+
+    if state
+        .ply_cap
+        .is_some_and(|cap| ending::is_capped(game, cap))
+    {
+        state.ply_capped_games += 1;
+        keep = false;
+    }
+
+    if args.drop_illegal_drops && verify::illegal_drop(game).is_some() {
+        state.illegal_drop_games += 1;
+        keep = false;
+    }
+
+    if let Some(list) = &state.startpos_list {
+        keep &= list.contains(&game.startpos.key()) != args.invert;
+    }
+
+    if let Some(max) = args.max_per_startpos {
+        let (seen, kept) = state
+            .startpos_counts
+            .entry(game.startpos.key())
+            .or_default();
+        *seen += 1;
+        keep &= *kept < max;
+
+        if keep {
+            *kept += 1;
+        }
+    }
+
+    keep
+}
+
+fn filter_positions(game: &Stoatpack, eval_limit: i16) -> u64 {
+    game.moves
+        .iter()
+        .filter(|(_, score)| score.abs() <= eval_limit)
+        .count() as u64
+        + 1
+}
+
+fn count_eligible_positions(
+    paths: &[PathBuf],
+    mut positions: impl FnMut(&Stoatpack) -> Option<u64>,
+) -> Result<u64> {
+    let mut total = 0;
+
+    for path in paths {
+        let file = OpenOptions::new().read(true).open(path)?;
+        let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+        let len = file.metadata()?.len();
+
+        while reader.stream_position()? < len {
+            let offset = reader.stream_position()?;
+            total += positions(&Stoatpack::deserialise(&mut reader).at_offset(path, offset)?)
+                .unwrap_or(0);
+        }
+    }
+
+    Ok(total)
+}
+
+fn filter(path: PathBuf, args: &FilterArgs, state: &mut FilterState) -> Result<(usize, usize)> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, &path)?);
+    let len = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    let mut records = 0;
+    let mut dropped = 0;
+    let mut prev_pos = 0;
+
+    while reader.stream_position()? < len {
+        let game = Stoatpack::deserialise(&mut reader).at_offset(&path, prev_pos)?;
+        progress::record(&path);
+        let curr_pos = reader.stream_position()?;
+        records += 1;
+
+        let mut keep = keep_game(&game, args, state);
+
+        if let Some(sampler) = state.sampler.as_mut() {
+            keep &= sampler.sample(filter_positions(&game, args.eval_limit));
+        }
+
+        if keep {
+            let positions = filter_positions(&game, args.eval_limit) as usize;
+            state.positions_histogram[(positions / LENGTH_BUCKET).min(LENGTH_BUCKETS - 1)] += 1;
+
+            let mut game_buffer = vec![0u8; (curr_pos - prev_pos) as usize];
+
+            reader.seek(SeekFrom::Start(prev_pos))?;
+            reader.read_exact(&mut game_buffer)?;
+            buffer.extend(game_buffer);
+        } else {
+            dropped += 1;
+        }
+
+        prev_pos = curr_pos;
+    }
+
+    if dropped == 0 {
+        println!("  OK  : {}, {} records", path.display(), records);
+    } else {
+        replace_file(&path, &buffer)?;
+
+        println!(
+            "Filter: {}, {} records, {} games dropped",
+            path.display(),
+            records,
+            dropped
+        );
+    }
+
+    Ok((records, dropped))
+}
+
+fn get_startpos_counts(path: PathBuf, counts: &mut HashMap<u64, (String, usize)>) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, &path)?);
+    let len = file.metadata()?.len();
+    let mut records = 0;
+
+    while reader.stream_position()? < len {
+        let offset = reader.stream_position()?;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(&path, offset)?;
+
+        counts
+            .entry(game.startpos.key())
+            .or_insert_with(|| (game.startpos.sfen(), 0))
+            .1 += 1;
+        records += 1;
+    }
+
+    println!("  OK  : {}, {} records", path.display(), records);
+
+    Ok(records)
+}
+
+fn extract(
+    path: PathBuf,
+    targets: &HashSet<u64>,
+    indices: &[IndexRange],
+    writer: &mut BufWriter<File>,
+) -> Result<(usize, usize)> {
+    if compress::is_compressed(&path) {
+        let (records, extracted) = compress::extract(&path, indices, writer)?;
+        println!(
+            "  OK  : {}, {} records, {} extracted",
+            path.display(),
+            records,
+            extracted
+        );
+
+        return Ok((records, extracted));
+    }
+
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, &path)?);
+    let len = file.metadata()?.len();
+    let mut records = 0;
+    let mut extracted = 0;
+    let mut prev_pos = 0;
+
+    while reader.stream_position()? < len {
+        let game = Stoatpack::deserialise(&mut reader).at_offset(&path, prev_pos)?;
+        let curr_pos = reader.stream_position()?;
+        let mut found = indices.iter().any(|range| range.contains(records));
+
+        if !found && !targets.is_empty() {
+            let mut pos = game.startpos;
+            found = targets.contains(&pos.key());
+
+            for (mv, _) in &game.moves {
+                if found {
+                    break;
+                }
+
+                pos = pos.apply_move(*mv);
+                found = targets.contains(&pos.key());
+            }
+        }
+
+        if found {
+            let mut game_buffer = vec![0u8; (curr_pos - prev_pos) as usize];
+
+            reader.seek(SeekFrom::Start(prev_pos))?;
+            reader.read_exact(&mut game_buffer)?;
+            writer.write_all(&game_buffer)?;
+            extracted += 1;
+        }
+
+        records += 1;
+        prev_pos = curr_pos;
+    }
+
+    println!(
+        "  OK  : {}, {} records, {} extracted",
+        path.display(),
+        records,
+        extracted
+    );
+
+    Ok((records, extracted))
+}
+
+fn read_startpos_list(path: &Path) -> Result<HashSet<u64>> {
+    let mut list = HashSet::new();
+
+    for line in buffer::reader(File::open(path)?).lines() {
+        let line = line?;
+        let sfen = line.split('\t').next().unwrap_or("").trim();
+
+        if sfen.is_empty() || sfen.starts_with('#') {
+            continue;
+        }
+
+        let pos = Position::from_sfen(sfen)
+            .map_err(|_| Error::other(format!("Invalid SFEN: {}", sfen)))?;
+        list.insert(pos.key());
+    }
+
+    println!(
+        "Loaded {} start positions from {}",
+        list.len(),
+        path.display()
+    );
+
+    Ok(list)
+}
+
+fn write_startpos_counts(path: &Path, counts: &HashMap<u64, (String, usize)>) -> Result<()> {
+    let mut startpos = counts.values().collect::<Vec<_>>();
+    startpos.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let mut writer = buffer::writer(File::create(path)?);
+
+    for (sfen, count) in startpos {
+        writeln!(writer, "{}\t{}", sfen, count)?;
+    }
+
+    Ok(writer.flush()?)
+}
+
+fn get_sort_keys(
+    path: PathBuf,
+    sort_by: SortBy,
+    records: &mut Vec<(SortKey, Vec<u8>)>,
+) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, &path)?);
+    let len = file.metadata()?.len();
+    let mut count = 0;
+    let mut prev_pos = 0;
+
+    while reader.stream_position()? < len {
+        let game = Stoatpack::deserialise(&mut reader).at_offset(&path, prev_pos)?;
+        let curr_pos = reader.stream_position()?;
+        let mut game_buffer = vec![0u8; (curr_pos - prev_pos) as usize];
+
+        reader.seek(SeekFrom::Start(prev_pos))?;
+        reader.read_exact(&mut game_buffer)?;
+
+        let key = match sort_by {
+            SortBy::Length => SortKey::Length(game.moves.len()),
+            SortBy::Hash => SortKey::Hash(game.startpos.key()),
+            SortBy::FirstMove => SortKey::FirstMove(
+                game.moves
+                    .first()
+                    .map_or_else(String::new, |(mv, _)| mv.to_string()),
+            ),
+        };
+
+        records.push((key, game_buffer));
+        count += 1;
+        prev_pos = curr_pos;
+    }
+
+    println!("  OK  : {}, {} records", path.display(), count);
+
+    Ok(count)
+}
+
+/// The outcome a final position decides on its own. A side with no legal moves has lost,
+/// whether by checkmate or otherwise, and while there is only one legal continuation it is
+/// played out, so a game stopped short of a forced mate still gets its result.
+fn forced_outcome(mut pos: Position) -> Option<Outcome> {
+    let mut seen = HashSet::new();
+
+    loop {
+        match pos.legal_moves().as_slice() {
+            [] => {
+                return Some(if pos.stm() == Color::SENTE {
+                    Outcome::SenteLoss
+                } else {
+                    Outcome::SenteWin
+                });
+            }
+            // A forced line that repeats never reaches a decision
+            [only] if seen.insert(pos.key()) => pos = pos.apply_move(*only),
+            _ => return None,
+        }
+    }
+}
+
+fn relabel(path: PathBuf) -> Result<(usize, usize)> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, &path)?);
+    let len = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    let mut records = 0;
+    let mut relabeled = 0;
+
+    while reader.stream_position()? < len {
+        let offset = reader.stream_position()?;
+        let mut game = Stoatpack::deserialise(&mut reader).at_offset(&path, offset)?;
+        let mut pos = game.startpos.clone();
+
+        for (mv, _) in &game.moves {
+            pos = pos.apply_move(*mv);
+        }
+
+        if let Some(wdl) = forced_outcome(pos) {
+            if game.wdl != wdl {
+                game.wdl = wdl;
+                relabeled += 1;
+            }
+        }
+
+        game.serialise(&mut buffer)?;
+        records += 1;
+    }
+
+    if relabeled == 0 {
+        println!("  OK  : {}, {} records", path.display(), records);
+    } else {
+        replace_file(&path, &buffer)?;
+
+        println!(
+            "Relabel: {}, {} records, {} games relabeled",
+            path.display(),
+            records,
+            relabeled
+        );
+    }
+
+    Ok((records, relabeled))
+}
+
+fn selftest(path: PathBuf) -> Result<(usize, usize)> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, &path)?);
+    let len = file.metadata()?.len();
+    let mut records = 0;
+    let mut mismatched = 0;
+    let mut prev_pos = 0;
+
+    while reader.stream_position()? < len {
+        let game = Stoatpack::deserialise(&mut reader).at_offset(&path, prev_pos)?;
+        let curr_pos = reader.stream_position()?;
+        let mut game_buffer = vec![0u8; (curr_pos - prev_pos) as usize];
+        let mut serialised = Vec::new();
+
+        reader.seek(SeekFrom::Start(prev_pos))?;
+        reader.read_exact(&mut game_buffer)?;
+        game.serialise(&mut serialised)?;
+
+        if serialised != game_buffer {
+            let diff = game_buffer
+                .iter()
+                .zip(&serialised)
+                .position(|(a, b)| a != b)
+                .unwrap_or(game_buffer.len().min(serialised.len()));
+
+            println!(
+                "Mismatch: {}, record {} at offset {}, first difference at offset {} ({} bytes read, {} bytes written)",
+                path.display(),
+                records,
+                prev_pos,
+                prev_pos + diff as u64,
+                game_buffer.len(),
+                serialised.len()
+            );
+            mismatched += 1;
+        }
+
+        records += 1;
+        prev_pos = curr_pos;
+    }
+
+    if mismatched == 0 {
+        println!("  OK  : {}, {} records", path.display(), records);
+    }
+
+    Ok((records, mismatched))
+}
+
+fn near_duplicates(
+    path: PathBuf,
+    args: &NearDuplicatesArgs,
+    clusters: &mut HashMap<u64, (usize, PathBuf, usize)>,
+) -> Result<(usize, usize)> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let mut reader = TeeReader::new(buffer::reader(ChecksumReader::new(&file, &path)?));
+    let len = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    let mut records = 0;
+    let mut dropped = 0;
+
+    while reader.position < len {
+        let offset = reader.position;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(&path, offset)?;
+        let game_buffer = reader.take_captured();
+        let mut keep = true;
+
+        // Games shorter than the prefix cannot share it with anything
+        if game.moves.len() >= args.prefix_length {
+            let mut hasher = DefaultHasher::new();
+            game.startpos.key().hash(&mut hasher);
+
+            for (mv, _) in &game.moves[..args.prefix_length] {
+                mv.to_string().hash(&mut hasher);
+            }
+
+            let (count, _, _) = clusters
+                .entry(hasher.finish())
+                .or_insert_with(|| (0, path.clone(), records));
+            *count += 1;
+            keep = args.keep.is_none_or(|keep| *count <= keep);
+        }
+
+        if keep {
+            buffer.extend(game_buffer);
+        } else {
+            dropped += 1;
+        }
+
+        records += 1;
+    }
+
+    if dropped == 0 {
+        println!("  OK  : {}, {} records", path.display(), records);
+    } else {
+        replace_file(&path, &buffer)?;
+
+        println!(
+            "Filter: {}, {} records, {} games dropped",
+            path.display(),
+            records,
+            dropped
+        );
+    }
+
+    Ok((records, dropped))
+}
+
+fn print_prefix_clusters(top: usize, clusters: &HashMap<u64, (usize, PathBuf, usize)>) {
+    let mut clusters = clusters
+        .values()
+        .filter(|(count, _, _)| *count > 1)
+        .collect::<Vec<_>>();
+    clusters.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| (&a.1, a.2).cmp(&(&b.1, b.2))));
+
+    let games = clusters.iter().map(|(count, _, _)| count).sum::<usize>();
+
+    println!(
+        "Prefix clusters: {} ({} games in clusters)",
+        clusters.len(),
+        games
+    );
+
+    for (count, path, index) in clusters.iter().take(top) {
+        println!("  {: >8} games, first {} #{}", count, path.display(), index);
+    }
+}
+
+fn get_game_hashes(
+    path: PathBuf,
+    game_hashes: &mut HashMap<u64, Vec<(PathBuf, usize)>>,
+) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let (buffer, _) = get_buffer(&file)?;
+
+    for (index, game_buffer) in buffer.iter().enumerate() {
+        game_hashes
+            .entry(game_hash(game_buffer))
+            .or_default()
+            .push((path.clone(), index));
+    }
+
+    Ok(buffer.len())
+}
+
+fn game_hash(game_buffer: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    game_buffer.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+fn sorted_game_hashes(file: &File) -> Result<Vec<u64>> {
+    let mut reader = TeeReader::new(buffer::reader(file));
+    let len = file.metadata()?.len();
+    let mut hashes = Vec::new();
+
+    while reader.position < len {
+        Stoatpack::deserialise(&mut reader)?;
+        hashes.push(game_hash(&reader.take_captured()));
+    }
+
+    hashes.sort_unstable();
+
+    Ok(hashes)
+}
+
+fn print_duplicates(game_hashes: &HashMap<u64, Vec<(PathBuf, usize)>>) -> usize {
+    let mut groups = game_hashes
+        .values()
+        .filter(|games| games.len() > 1)
+        .collect::<Vec<_>>();
+    groups.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+    for games in &groups {
+        println!("Duplicate: {} copies", games.len());
+
+        for (path, index) in games.iter() {
+            println!("    {} #{}", path.display(), index);
+        }
+    }
+
+    groups.len()
+}
+
+fn get_buffer(file: &File) -> Result<(Vec<Vec<u8>>, usize)> {
+    let mut reader = TeeReader::new(buffer::reader(file));
+    let len = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    let mut broken_records = 0;
+
+    while reader.position < len {
+        match Stoatpack::deserialise(&mut reader) {
+            Ok(_) => {
+                buffer.push(reader.take_captured());
+            }
+            Err(_) => {
+                broken_records += 1;
+            }
+        }
+    }
+
+    Ok((buffer, broken_records))
+}
+
+/// Keeps a copy of every byte read since the last [`TeeReader::take_captured`], so a record's
+/// raw bytes are available once it has been deserialised without reading them a second time.
+struct TeeReader<R> {
+    inner: R,
+    captured: Vec<u8>,
+    position: u64,
+}
+
+impl<R> TeeReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            captured: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn take_captured(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.captured)
+    }
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.captured.extend_from_slice(&buf[..read]);
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+/// Replaces the contents of `path` by writing a temporary file next to it and renaming that
+/// over it, so an interrupted rewrite never leaves the file half written.
+fn replace_file(path: &Path, buffer: &[u8]) -> Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut writer = buffer::writer(File::create(&tmp_path)?);
+    writer.write_all(buffer)?;
+    writer.flush()?;
+    drop(writer);
+    rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+fn is_reverse(game: &Stoatpack, eval_limit: i16) -> bool {
+    reverse_ply(game, eval_limit).is_some()
+}
+
+fn reverse_ply(game: &Stoatpack, eval_limit: i16) -> Option<usize> {
+    match game.wdl {
+        Outcome::SenteWin => game
+            .moves
+            .iter()
+            .position(|(_, score)| *score <= -eval_limit),
+        Outcome::SenteLoss => game
+            .moves
+            .iter()
+            .position(|(_, score)| *score >= eval_limit),
+        Outcome::Draw => None,
+    }
+}
+
+fn phase(ply: usize) -> usize {
+    match ply {
+        0..40 => 0,
+        40..100 => 1,
+        _ => 2,
+    }
+}
+
+fn material(pos: &Position, color: Color) -> i32 {
+    let board = PIECE_VALUES
+        .iter()
+        .map(|&(piece_type, value)| {
+            pos.piece_bb(piece_type.with_color(color)).popcount() as i32 * value
+        })
+        .sum::<i32>();
+    let hand = PIECE_VALUES[..HAND_PIECE_TYPES]
+        .iter()
+        .map(|&(piece_type, value)| pos.hand(color).count(piece_type) as i32 * value)
+        .sum::<i32>();
+
+    board + hand
+}
+
+/// Whether `mv` takes a piece, from what stands on its destination before it is played.
+/// Drops always land on an empty square.
+fn is_capture(pos: &Position, mv: Move) -> bool {
+    show::destination(&mv.to_string()).is_some_and(|dest| show::board(pos)[dest].is_some())
+}
+
+fn distance(a: Square, b: Square) -> usize {
+    let (a, b) = (a.idx(), b.idx());
+
+    (a % 9).abs_diff(b % 9).max((a / 9).abs_diff(b / 9))
+}
+
+fn relative_square(color: Color, square: Square) -> Square {
+    if color == Color::SENTE {
+        square
+    } else {
+        square.rotate()
+    }
+}
+
+/// Prints the side-to-move split of the emitted positions, in yellow once it strays from even
+fn print_parity(sente: usize, positions: usize) {
+    let share = sente as f64 / positions as f64 * 100.0;
+    let line = format!(
+        "Side to move   : sente {} ({:.2}%), gote {} ({:.2}%)",
+        sente,
+        share,
+        positions - sente,
+        100.0 - share
+    );
+
+    if (share - 50.0).abs() >= PARITY_WARNING {
+        println!("{}", line.yellow());
+    } else {
+        println!("{}", line);
+    }
+}
+
+fn print_startpos_distribution(state: &FilterState) {
+    let mut distribution = [0usize; 11];
+
+    for (seen, _) in state.startpos_counts.values() {
+        distribution[(*seen).min(distribution.len()) - 1] += 1;
+    }
+
+    println!("Start positions: {}", state.startpos_counts.len());
+    println!("Games per start position:");
+
+    for (idx, openings) in distribution.iter().enumerate() {
+        let label = if idx == distribution.len() - 1 {
+            format!("{}+", idx + 1)
+        } else {
+            format!("{}", idx + 1)
+        };
+
+        println!(
+            "  {: <4} : {: <8} ({:.2}%)",
+            label,
+            openings,
+            *openings as f64 / state.startpos_counts.len() as f64 * 100.0f64
+        );
+    }
 }
 
-impl Command {
-    fn common(&self) -> &CommonArgs {
-        match self {
-            Command::Count(args) => &args.common,
-            Command::Fix(args) => args,
-            Command::Shuffle(args) => &args.common,
+fn print_positions_per_game(state: &FilterState) {
+    let games = state.positions_histogram.iter().sum::<u64>();
+
+    println!("Positions per kept game:");
+
+    for (bucket, count) in state.positions_histogram.iter().enumerate() {
+        if *count == 0 {
+            continue;
         }
+
+        let label = if bucket == LENGTH_BUCKETS - 1 {
+            format!("{}+", bucket * LENGTH_BUCKET)
+        } else {
+            format!(
+                "{}-{}",
+                bucket * LENGTH_BUCKET,
+                (bucket + 1) * LENGTH_BUCKET - 1
+            )
+        };
+
+        println!(
+            "  {: <7} : {: <8} ({:.2}%)",
+            label,
+            count,
+            *count as f64 / games as f64 * 100.0f64
+        );
     }
 }
 
-#[derive(Parser, Debug)]
-struct CommonArgs {
-    #[arg(short, long)]
-    recursive: bool,
+fn print_count(args: &CountArgs, stats: &CountStats) {
+    let games = stats.games();
 
-    #[arg(required = true)]
-    paths: Vec<PathBuf>,
-}
+    println!("Total positions: {}", stats.positions);
+    println!("Total games    : {}", games);
+    println!(
+        "Black wins     : {: <8} ({:.2}%)",
+        stats.black_wins,
+        stats.black_wins as f64 / games as f64 * 100.0f64
+    );
+    println!(
+        "White wins     : {: <8} ({:.2}%)",
+        stats.white_wins,
+        stats.white_wins as f64 / games as f64 * 100.0f64
+    );
+    println!(
+        "Draws          : {: <8} ({:.2}%)",
+        stats.draws,
+        stats.draws as f64 / games as f64 * 100.0f64
+    );
+    println!(
+        "Reverses       : {: <8} ({:.2}%)",
+        stats.reverses,
+        stats.reverses as f64 / games as f64 * 100.0f64
+    );
 
-#[derive(Parser, Debug)]
-struct CountArgs {
-    #[clap(flatten)]
-    common: CommonArgs,
+    print_bias(args, stats);
+    print_score_statistics(&args.percentiles, stats);
+    print_disagreement(stats);
+    print_opening_moves(args.top_moves, stats);
 
-    #[arg(long, short)]
-    quick: bool,
+    if !args.quick {
+        print_material(stats);
+        print_king_distances(stats);
+        print_board_pieces(stats);
+        print_promotion_zone(stats);
+        print_drops(stats);
+        print_top_positions(args.top_positions, stats);
 
-    #[arg(long, short, default_value_t = 25001)]
-    eval_limit: i16,
+        if args.transpositions {
+            print_transpositions(stats);
+        }
+
+        print_score_noise(&stats.score_noise);
+        print_endings(args.max_ply, &stats.endings);
+        print_king_squares(args, "King squares", &stats.king_squares);
+
+        for (name, king_squares) in ["winning", "losing"]
+            .iter()
+            .zip(&stats.outcome_king_squares)
+        {
+            print_king_squares(args, &format!("King squares ({} side)", name), king_squares);
+        }
+    }
 }
 
-#[derive(Parser, Debug)]
-struct ShuffleArgs {
-    #[clap(flatten)]
-    common: CommonArgs,
+fn print_score_noise(noise: &ScoreNoise) {
+    if noise.pairs == 0 {
+        println!("Consecutive score noise: -");
+        return;
+    }
 
-    #[arg(long, short, default_value_t = 42)]
-    seed: u64,
+    println!(
+        "Consecutive score noise: mean |diff| {:.2}, autocorrelation {:.4} ({} pairs, captures excluded)",
+        noise.abs_diff / noise.pairs as f64,
+        noise.correlation(),
+        noise.pairs
+    );
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let command = &cli.command;
-    let args = command.common();
+fn print_endings(max_ply: Option<usize>, endings: &ending::Endings) {
+    let counts = endings.counts(max_ply);
+    let total = counts.iter().sum::<u64>();
 
-    let mut paths = Vec::new();
+    match endings.cap(max_ply) {
+        Some(cap) => println!("Game endings (max-ply cap {}):", cap),
+        None => println!("Game endings:"),
+    }
 
-    for path in args.paths.clone() {
-        if path.is_file() {
-            paths.push(path);
-        } else if path.is_dir() {
-            paths.extend(get_files(&path, args.recursive)?);
+    for (name, count) in ending::NAMES.iter().zip(counts) {
+        println!(
+            "  {: <17} : {: >6.2}% ({})",
+            name,
+            count as f64 / total as f64 * 100.0f64,
+            count
+        );
+    }
+}
+
+fn print_bias(args: &CountArgs, stats: &CountStats) {
+    let games = stats.games();
+
+    println!(
+        "Sente score    : {:.2}%",
+        (stats.black_wins as f64 + stats.draws as f64 / 2.0) / games as f64 * 100.0f64
+    );
+    println!("Mean score (sente perspective):");
+
+    for (ply, (sum, count)) in args.bias_plies.iter().zip(&stats.ply_scores) {
+        if *count == 0 {
+            println!("  ply {: <4} : -", ply);
         } else {
-            eprintln!("Invalid path: {}", path.display());
+            println!(
+                "  ply {: <4} : {: >+8.2} ({} positions)",
+                ply,
+                *sum as f64 / *count as f64,
+                count
+            );
         }
     }
+}
 
-    paths = paths
-        .into_iter()
-        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("spk"))
-        .collect();
+fn write_reverse_games(path: &Path, games: &[(PathBuf, usize, u64, usize, i16)]) -> Result<()> {
+    let mut writer = buffer::writer(File::create(path)?);
 
-    println!("Checking {} files...", paths.len());
+    for (file, index, offset, ply, score) in games {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            file.display(),
+            index,
+            offset,
+            score,
+            ply
+        )?;
+    }
 
-    let mut total_positions = 0;
-    let mut black_win = 0;
-    let mut white_win = 0;
-    let mut draw = 0;
-    let mut reverse = 0;
-    let mut total_records = 0;
-    let mut total_broken_records = 0;
-    let mut total_trimmed_bytes = 0;
-    let mut fixed_files = 0;
-    let mut king_squares = [0u64; 81];
+    Ok(writer.flush()?)
+}
 
-    for path in paths {
-        match command {
-            Command::Count(args) => {
-                let (positions, black_wins, white_wins, draws, reverses) =
-                    count(path, args.quick, args.eval_limit, &mut king_squares)?;
-                total_positions += positions;
-                black_win += black_wins;
-                white_win += white_wins;
-                draw += draws;
-                reverse += reverses;
-            }
-            Command::Fix(_) => {
-                let (records, broken_records, trimmed_bytes) = fix(path)?;
-                total_records += records;
-                total_broken_records += broken_records;
-                total_trimmed_bytes += trimmed_bytes;
+fn print_score_statistics(percentiles: &[f64], stats: &CountStats) {
+    let overall = (0..1 << 16)
+        .map(|idx| stats.score_counts.iter().map(|counts| counts[idx]).sum())
+        .collect::<Vec<u64>>();
 
-                if total_broken_records != 0 {
-                    fixed_files += 1;
-                }
-            }
-            Command::Shuffle(args) => {
-                let (records, broken_records) = shuffle(path, args.seed)?;
-                total_records += records;
-                total_broken_records += broken_records;
-            }
+    println!("Score statistics (sente perspective):");
+
+    for (name, counts) in [
+        ("Overall", &overall),
+        ("Black wins", &stats.score_counts[0]),
+        ("White wins", &stats.score_counts[1]),
+        ("Draws", &stats.score_counts[2]),
+    ] {
+        let total = counts.iter().sum::<u64>();
+
+        if total == 0 {
+            println!("  {: <10} : -", name);
+            continue;
         }
-    }
 
-    println!("               Summary               ");
-    println!("-------------------------------------");
+        let scores = || {
+            counts
+                .iter()
+                .enumerate()
+                .map(|(idx, &count)| (idx as f64 + i16::MIN as f64, count as f64))
+        };
+        let mean = scores().map(|(score, count)| score * count).sum::<f64>() / total as f64;
+        let variance = scores()
+            .map(|(score, count)| (score - mean).powi(2) * count)
+            .sum::<f64>()
+            / total as f64;
 
-    match command {
-        Command::Count(args) => {
-            let games = black_win + white_win + draw;
+        let values = percentiles
+            .iter()
+            .map(|p| {
+                let rank = ((p / 100.0 * total as f64).ceil() as u64).clamp(1, total);
+                let mut seen = 0;
+                let idx = counts
+                    .iter()
+                    .position(|&count| {
+                        seen += count;
+                        seen >= rank
+                    })
+                    .unwrap();
 
-            println!("Total positions: {}", total_positions);
-            println!("Total games    : {}", games);
-            println!(
-                "Black wins     : {: <8} ({:.2}%)",
-                black_win,
-                black_win as f64 / games as f64 * 100.0f64
-            );
-            println!(
-                "White wins     : {: <8} ({:.2}%)",
-                white_win,
-                white_win as f64 / games as f64 * 100.0f64
-            );
-            println!(
-                "Draws          : {: <8} ({:.2}%)",
-                draw,
-                draw as f64 / games as f64 * 100.0f64
-            );
-            println!(
-                "Reverses       : {: <8} ({:.2}%)",
-                reverse,
-                reverse as f64 / games as f64 * 100.0f64
-            );
+                format!("p{} {:+}", p, idx as i32 + i16::MIN as i32)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
 
-            if !args.quick {
-                print_king_squares(total_positions, &king_squares);
+        println!(
+            "  {: <10} : mean {:+.2}, std {:.2}, {} ({} scores)",
+            name,
+            mean,
+            variance.sqrt(),
+            values,
+            total
+        );
+    }
+}
+
+/// Prints, per eval bucket, how often the score of a decisive game's position points to the
+/// side that went on to lose. Draws have no winner to disagree with and are left out.
+fn print_disagreement(stats: &CountStats) {
+    let mut decisive = [0u64; EVAL_BUCKETS];
+    let mut disagreeing = [0u64; EVAL_BUCKETS];
+
+    for (idx, counts) in stats.score_counts[..2].iter().enumerate() {
+        for (offset, &count) in counts.iter().enumerate() {
+            let score = (offset as i32 + i16::MIN as i32) as i16;
+            let bucket = (score.clamp(-EVAL_RANGE, EVAL_RANGE - 1) + EVAL_RANGE) / EVAL_BUCKET;
+            decisive[bucket as usize] += count;
+
+            // Index 0 holds sente wins, index 1 sente losses
+            if (idx == 0 && score < 0) || (idx == 1 && score > 0) {
+                disagreeing[bucket as usize] += count;
             }
         }
-        _ => {
-            println!("Total records: {}", total_records);
-            println!("Total broken records: {}", total_broken_records);
-            println!("Total trimmed bytes: {}", total_trimmed_bytes);
-            println!("Fixed files: {}", fixed_files);
+    }
+
+    println!("Score/result disagreement (decisive games):");
+
+    for ((label, total), wrong) in stats.eval_labels().iter().zip(decisive).zip(disagreeing) {
+        if total == 0 {
+            continue;
         }
+
+        println!(
+            "  {: >6} : {: >6.2}% ({} of {})",
+            label,
+            wrong as f64 / total as f64 * 100.0,
+            wrong,
+            total
+        );
     }
 
-    Ok(())
+    let (total, wrong) = (
+        decisive.iter().sum::<u64>(),
+        disagreeing.iter().sum::<u64>(),
+    );
+    println!(
+        "  {: >6} : {: >6.2}% ({} of {})",
+        "All",
+        wrong as f64 / total as f64 * 100.0,
+        wrong,
+        total
+    );
 }
 
-fn get_files(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
-    let mut result = Vec::new();
+fn print_opening_moves(top: usize, stats: &CountStats) {
+    for (moves, counts) in stats.opening_moves.iter().enumerate() {
+        let total = counts.values().sum::<usize>();
+        let mut lines = counts.iter().collect::<Vec<_>>();
+        lines.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
 
-    for entry in read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
+        println!(
+            "Most common {} moves:",
+            if moves == 0 { "first" } else { "first two" }
+        );
 
-        if path.is_file() {
-            result.push(path);
-        } else if path.is_dir() && recursive {
-            result.extend(get_files(&path, recursive)?);
+        for (line, count) in lines.into_iter().take(top) {
+            println!(
+                "  {: <12} : {: <8} ({:.2}%)",
+                line,
+                count,
+                *count as f64 / total as f64 * 100.0f64
+            );
         }
     }
-
-    Ok(result)
 }
 
-fn count(
-    path: PathBuf,
-    quick: bool,
-    eval_limit: i16,
-    king_squares: &mut [u64; 81],
-) -> Result<(usize, usize, usize, usize, usize)> {
-    let file = OpenOptions::new().read(true).open(&path)?;
-    let mut reader = BufReader::new(&file);
-    let len = file.metadata()?.len();
-    let mut total_positions = 0;
-    let mut black_wins = 0;
-    let mut white_wins = 0;
-    let mut draws = 0;
-    let mut reverses = 0;
+fn print_king_distances(stats: &CountStats) {
+    let king_total = stats.king_distances.iter().sum::<u64>();
+    let major_total = stats.major_distances.iter().sum::<u64>();
 
-    while reader.stream_position()? < len {
-        let game = Stoatpack::deserialise(&mut reader)?;
-        let mut pos = game.startpos;
+    println!("King distances:");
+    println!("  {: >8} | {: >10} | {: >10}", "Distance", "Kings", "Major");
 
-        match game.wdl {
-            Outcome::SenteWin => black_wins += 1,
-            Outcome::SenteLoss => white_wins += 1,
-            Outcome::Draw => draws += 1,
-        }
+    for distance in 0..stats.major_distances.len() {
+        let label = if distance == stats.major_distances.len() - 1 {
+            "None".to_string()
+        } else {
+            distance.to_string()
+        };
+        let kings = stats
+            .king_distances
+            .get(distance)
+            .map_or("-".to_string(), |&count| {
+                format!("{:.2}%", count as f64 / king_total as f64 * 100.0f64)
+            });
 
-        total_positions += game
-            .moves
-            .iter()
-            .filter(|(_, score)| score.abs() <= eval_limit)
-            .count()
-            + 1;
+        println!(
+            "  {: >8} | {: >10} | {: >9.2}%",
+            label,
+            kings,
+            stats.major_distances[distance] as f64 / major_total as f64 * 100.0f64
+        );
+    }
+}
 
-        if (game.wdl == Outcome::SenteWin
-            && game
-                .moves
-                .iter()
-                .filter(|(_, score)| *score <= -eval_limit)
-                .count()
-                > 0)
-            || game.wdl == Outcome::SenteLoss
-                && game
-                    .moves
-                    .iter()
-                    .filter(|(_, score)| *score >= eval_limit)
-                    .count()
-                    > 0
-        {
-            reverses += 1;
-        }
+fn print_board_pieces(stats: &CountStats) {
+    let total = stats.board_pieces.iter().sum::<u64>();
 
-        if !quick {
-            let king_square = relative_square(
-                pos.stm(),
-                pos.piece_bb(PieceType::KING.with_color(pos.stm()))
-                    .lsb()
-                    .unwrap(),
-            );
-            king_squares[king_square.idx()] += 1;
+    // Every piece not on the board is in a hand, so the hand count is implied
+    println!("Pieces on board / in hand:");
 
-            for mv in game.moves {
-                pos = pos.apply_move(mv.0);
-                let king_square = relative_square(
-                    pos.stm(),
-                    pos.piece_bb(PieceType::KING.with_color(pos.stm()))
-                        .lsb()
-                        .unwrap(),
-                );
-                king_squares[king_square.idx()] += 1;
-            }
+    for (pieces, &count) in stats.board_pieces.iter().enumerate() {
+        if count == 0 {
+            continue;
         }
-    }
 
-    Ok((total_positions, black_wins, white_wins, draws, reverses))
+        println!(
+            "  {: >2} / {: >2} : {: >6.2}% ({})",
+            pieces,
+            stats.board_pieces.len() - 1 - pieces,
+            count as f64 / total as f64 * 100.0f64,
+            count
+        );
+    }
 }
 
-fn fix(path: PathBuf) -> Result<(usize, usize, u64)> {
-    let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
-    let len = file.metadata()?.len();
-    let (buffer, broken_records) = get_buffer(&file)?;
-    let records = buffer.len();
-    let mut trimmed_bytes = 0;
+fn print_promotion_zone(stats: &CountStats) {
+    println!("Positions with pieces in the promotion zone:");
+    println!("  {: >10} | {: >10} | {: >10}", "", "Sente", "Gote");
 
-    if broken_records == 0 {
-        println!("  OK  : {}, {} records", path.display(), records);
-    } else {
-        let buffer = buffer.into_iter().flatten().collect();
-        write_buffer(&mut file, &buffer)?;
-        trimmed_bytes = len - file.metadata()?.len();
+    for (name, (positions, phase)) in PHASES.iter().zip(
+        stats
+            .promotion_zone_positions
+            .iter()
+            .zip(&stats.phase_king_squares),
+    ) {
+        let total = phase.iter().sum::<u64>();
 
         println!(
-            "Fixed : {}, {} records, {} broken records, {} bytes trimmed",
-            path.display(),
-            records,
-            broken_records,
-            trimmed_bytes
+            "  {: >10} | {: >9.2}% | {: >9.2}%",
+            name,
+            positions[0] as f64 / total as f64 * 100.0f64,
+            positions[1] as f64 / total as f64 * 100.0f64
         );
     }
+}
 
-    Ok((records, broken_records, trimmed_bytes))
+fn print_drops(stats: &CountStats) {
+    let total = stats.drop_squares.iter().flatten().sum::<u64>();
+
+    println!("Drops: {}", total);
+
+    for ((_, name), squares) in DROP_PIECES.iter().zip(&stats.drop_squares) {
+        let count = squares.iter().sum::<u64>();
+
+        println!(
+            "  {: <6} : {: >6.2}% ({})",
+            name,
+            count as f64 / total as f64 * 100.0f64,
+            count
+        );
+    }
 }
 
-fn shuffle(path: PathBuf, seed: u64) -> Result<(usize, usize)> {
-    let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
-    let (mut buffer, broken_records) = get_buffer(&file)?;
-    let records = buffer.len();
+fn print_top_positions(top: usize, stats: &CountStats) {
+    if top == 0 {
+        return;
+    }
+
+    let total = stats
+        .position_counts
+        .values()
+        .map(|position| position.count)
+        .sum::<usize>();
+    let mut positions = stats.position_counts.values().collect::<Vec<_>>();
+    positions.sort_by(|a, b| b.count.cmp(&a.count).then(a.sfen.cmp(&b.sfen)));
 
-    if broken_records == 0 {
-        let mut rng = SmallRng::seed_from_u64(seed);
-        buffer.shuffle(&mut rng);
-        write_buffer(&mut file, &buffer.into_iter().flatten().collect())?;
-    } else {
+    println!(
+        "Most frequent positions ({} distinct of {}):",
+        positions.len(),
+        total
+    );
+
+    for position in positions.iter().take(top) {
         println!(
-            "Shuffling is skipped because {} broken records",
-            broken_records
+            "  {: >8} ({: >6.2}%) | {: >+8.2} | {}",
+            position.count,
+            position.count as f64 / total as f64 * 100.0f64,
+            position.score_sum as f64 / position.count as f64,
+            position.sfen
         );
     }
+}
+
+fn print_transpositions(stats: &CountStats) {
+    let distinct = stats.transpositions.len();
+    let total = stats
+        .transpositions
+        .values()
+        .map(|(_, _, count)| count)
+        .sum::<usize>();
+    let (transposed, duplicated) = stats
+        .transpositions
+        .values()
+        .filter(|(_, transposed, _)| *transposed)
+        .fold((0, 0), |(positions, duplicated), (_, _, count)| {
+            (positions + 1, duplicated + count - 1)
+        });
 
-    Ok((records, broken_records))
+    println!("Transpositions:");
+    println!(
+        "  Positions reached by several move orders: {} ({:.2}% of {} distinct)",
+        transposed,
+        transposed as f64 / distinct as f64 * 100.0f64,
+        distinct
+    );
+    println!(
+        "  Repeated occurrences of those positions : {} ({:.2}% of {} positions)",
+        duplicated,
+        duplicated as f64 / total as f64 * 100.0f64,
+        total
+    );
 }
 
-fn get_buffer(file: &File) -> Result<(Vec<Vec<u8>>, usize)> {
-    let mut reader = BufReader::new(file);
-    let len = file.metadata()?.len();
-    let mut buffer = Vec::new();
-    let mut broken_records = 0;
-    let mut prev_pos = 0;
+fn print_material(stats: &CountStats) {
+    let overall = (0..stats.material[0].len())
+        .map(|bucket| stats.material.iter().map(|phase| phase[bucket]).sum())
+        .collect::<Vec<u64>>();
+    let columns = [
+        &overall[..],
+        &stats.material[0],
+        &stats.material[1],
+        &stats.material[2],
+    ];
+    let totals = columns
+        .iter()
+        .map(|column| column.iter().sum::<u64>())
+        .collect::<Vec<_>>();
 
-    while reader.stream_position()? < len {
-        match Stoatpack::deserialise(&mut reader) {
-            Ok(_) => {
-                let curr_pos = reader.stream_position()?;
-                let size = (curr_pos - prev_pos) as usize;
-                let mut game_buffer = vec![0u8; size];
+    println!("Material balance (sente perspective):");
+    println!(
+        "  {: >7} | {: >10} | {: >10} | {: >10} | {: >10}",
+        "", "Overall", PHASES[0], PHASES[1], PHASES[2]
+    );
 
-                reader.seek(SeekFrom::Start(prev_pos))?;
-                reader.read_exact(&mut game_buffer)?;
-                buffer.push(game_buffer);
-                reader.seek(SeekFrom::Start(curr_pos))?;
+    for bucket in 0..overall.len() {
+        let balance = bucket as i32 - MATERIAL_RANGE;
+        let label = match balance {
+            b if b == -MATERIAL_RANGE => format!("<= {}", b),
+            b if b == MATERIAL_RANGE => format!(">= {}", b),
+            b => format!("{}", b),
+        };
 
-                prev_pos = curr_pos;
-            }
-            Err(_) => {
-                broken_records += 1;
-            }
+        print!("  {: >7}", label);
+
+        for (column, total) in columns.iter().zip(&totals) {
+            print!(
+                " | {: >9.2}%",
+                column[bucket] as f64 / *total as f64 * 100.0f64
+            );
         }
-    }
 
-    Ok((buffer, broken_records))
+        println!();
+    }
 }
 
-fn write_buffer(file: &mut File, buffer: &Vec<u8>) -> Result<()> {
-    file.seek(SeekFrom::Start(0))?;
-    file.set_len(0)?;
-    file.write_all(&buffer)?;
+fn write_charts(path: &Path, stats: &CountStats) -> Result<()> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("chart");
+    let eval_path = path.with_file_name(format!("{}-eval.png", stem));
+    let length_path = path.with_file_name(format!("{}-length.png", stem));
+
+    chart::write_histogram(
+        &eval_path,
+        "Eval distribution",
+        &stats.eval_labels(),
+        &stats.eval_histogram,
+    )?;
+    println!("Chart written to {}", eval_path.display());
+
+    chart::write_histogram(
+        &length_path,
+        "Game length distribution",
+        &stats.length_labels(),
+        &stats.length_histogram,
+    )?;
+    println!("Chart written to {}", length_path.display());
 
     Ok(())
 }
 
-fn relative_square(color: Color, square: Square) -> Square {
-    if color == Color::SENTE {
-        square
-    } else {
-        square.rotate()
+fn all_heatmaps(stats: &CountStats) -> Vec<(String, &[u64; 81])> {
+    let mut heatmaps = vec![("King squares".to_string(), &stats.king_squares)];
+
+    for (name, king_squares) in PHASES.iter().zip(&stats.phase_king_squares) {
+        heatmaps.push((format!("King squares ({})", name), king_squares));
+    }
+
+    for (name, king_squares) in ["winning", "losing"]
+        .iter()
+        .zip(&stats.outcome_king_squares)
+    {
+        heatmaps.push((format!("King squares ({} side)", name), king_squares));
     }
+
+    for (name, squares) in PHASES.iter().zip(&stats.promotion_zone_squares) {
+        heatmaps.push((format!("Promotion zone occupancy ({})", name), squares));
+    }
+
+    for ((_, name), squares) in DROP_PIECES.iter().zip(&stats.drop_squares) {
+        heatmaps.push((format!("{} drops", name), squares));
+    }
+
+    heatmaps
+}
+
+fn write_heatmaps(path: &Path, stats: &CountStats) -> Result<()> {
+    heatmap::write_svg(path, "King squares", &stats.king_squares)?;
+    println!("Heatmap written to {}", path.display());
+
+    for (name, king_squares) in ["winning", "losing"]
+        .iter()
+        .zip(&stats.outcome_king_squares)
+    {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("heatmap");
+        let outcome_path = path.with_file_name(format!("{}-{}.svg", stem, name));
+
+        heatmap::write_svg(
+            &outcome_path,
+            &format!("King squares ({} side)", name),
+            king_squares,
+        )?;
+        println!("Heatmap written to {}", outcome_path.display());
+    }
+
+    for (name, (king_squares, promotion_zone_squares)) in PHASES.iter().zip(
+        stats
+            .phase_king_squares
+            .iter()
+            .zip(&stats.promotion_zone_squares),
+    ) {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("heatmap");
+        let phase_path = path.with_file_name(format!("{}-{}.svg", stem, name.to_lowercase()));
+
+        heatmap::write_svg(
+            &phase_path,
+            &format!("King squares ({})", name),
+            king_squares,
+        )?;
+        println!("Heatmap written to {}", phase_path.display());
+
+        let promotion_path =
+            path.with_file_name(format!("{}-promotion-{}.svg", stem, name.to_lowercase()));
+
+        heatmap::write_svg(
+            &promotion_path,
+            &format!("Promotion zone occupancy ({})", name),
+            promotion_zone_squares,
+        )?;
+        println!("Heatmap written to {}", promotion_path.display());
+    }
+
+    for ((_, name), squares) in DROP_PIECES.iter().zip(&stats.drop_squares) {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("heatmap");
+        let drop_path = path.with_file_name(format!("{}-drop-{}.svg", stem, name.to_lowercase()));
+
+        heatmap::write_svg(&drop_path, &format!("{} drops", name), squares)?;
+        println!("Heatmap written to {}", drop_path.display());
+    }
+
+    Ok(())
 }
 
-fn print_king_squares(total_positions: usize, king_squares: &[u64; 81]) {
-    println!("King squares:");
+fn print_king_squares(args: &CountArgs, title: &str, king_squares: &[u64; 81]) {
+    let ratios = normalized_ratios(king_squares, args.heatmap_normalize);
+    let thresholds = if args.heatmap_quantiles {
+        quantile_thresholds(&ratios)
+    } else {
+        args.heatmap_thresholds
+    };
+
+    println!("{}:", title);
 
     let border = "-".repeat(127);
     let space = format!("|{}", format!("{}|", " ".repeat(13)).repeat(9));
@@ -385,10 +3930,7 @@ fn print_king_squares(total_positions: usize, king_squares: &[u64; 81]) {
         let text = if num {
             format!("{}", king_squares[square])
         } else {
-            format!(
-                "{}",
-                colorize_ratio(king_squares[square] as f64 / total_positions as f64 * 100.0f64)
-            )
+            format!("{}", colorize_ratio(ratios[square], thresholds))
         };
 
         print!(
@@ -427,10 +3969,30 @@ fn print_king_squares(total_positions: usize, king_squares: &[u64; 81]) {
     }
 }
 
-fn colorize_ratio(ratio: f64) -> ColoredString {
-    if ratio > 10.0 {
+fn normalized_ratios(counts: &[u64; 81], normalize: Normalize) -> [f64; 81] {
+    std::array::from_fn(|square| {
+        let total = match normalize {
+            Normalize::Total => counts.iter().sum::<u64>(),
+            Normalize::Row => counts[square / 9 * 9..][..9].iter().sum(),
+            Normalize::Column => counts.iter().skip(square % 9).step_by(9).sum(),
+        };
+
+        counts[square] as f64 / total as f64 * 100.0f64
+    })
+}
+
+/// Splits the squares into thirds, so flat distributions still get all three colours
+fn quantile_thresholds(ratios: &[f64; 81]) -> (f64, f64) {
+    let mut sorted = *ratios;
+    sorted.sort_by(f64::total_cmp);
+
+    (sorted[sorted.len() / 3], sorted[sorted.len() * 2 / 3])
+}
+
+fn colorize_ratio(ratio: f64, (low, high): (f64, f64)) -> ColoredString {
+    if ratio > high {
         format!("{:.2}%", ratio).red()
-    } else if ratio > 5.0 {
+    } else if ratio > low {
         format!("{:.2}%", ratio).yellow()
     } else {
         format!("{:.2}%", ratio).blue()
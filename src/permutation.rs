@@ -0,0 +1,121 @@
+use {
+    crate::error::{Error, Result},
+    rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom},
+};
+
+pub const LATEST_VERSION: u32 = 1;
+
+/// Shuffles `items` with the permutation defined by `version` and `seed`.
+///
+/// - Version 0 uses `rand`'s `SmallRng`, whose output may change between `rand` releases.
+/// - Version 1 is a Fisher-Yates shuffle running from the last index down to 1. Each swap index
+///   `j` in `0..=i` is drawn from a SplitMix64 stream seeded with `seed`, rejecting draws
+///   `>= 2^64 - 2^64 % (i + 1)` to avoid modulo bias. It depends only on `seed` and the number
+///   of items.
+pub fn shuffle<T>(items: &mut [T], seed: u64, version: u32) -> Result<()> {
+    match version {
+        0 => items.shuffle(&mut SmallRng::seed_from_u64(seed)),
+        1 => {
+            let mut rng = SplitMix64(seed);
+
+            for i in (1..items.len()).rev() {
+                items.swap(i, rng.below(i as u64 + 1) as usize);
+            }
+        }
+        _ => {
+            return Err(Error::other(format!(
+                "Unknown RNG version {}, the latest is {}",
+                version, LATEST_VERSION
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        loop {
+            if let Some(value) = reduce(self.next(), bound) {
+                return value;
+            }
+        }
+    }
+}
+
+/// Maps a draw into `0..bound`, rejecting draws `>= 2^64 - 2^64 % bound` so a multiple of
+/// `bound` values is left to map
+fn reduce(value: u64, bound: u64) -> Option<u64> {
+    // 2^64 - bound is congruent to 2^64 modulo bound
+    let rejected = 0u64.wrapping_sub(bound) % bound;
+
+    (value <= u64::MAX - rejected).then_some(value % bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splitmix_matches_reference() {
+        let mut rng = SplitMix64(0);
+
+        assert_eq!(rng.next(), 0xe220a8397b1dcdaf);
+        assert_eq!(rng.next(), 0x6e789e6aa1b965f4);
+        assert_eq!(rng.next(), 0x06c45d188009454f);
+    }
+
+    #[test]
+    fn reduce_rejects_exactly_the_biased_tail() {
+        // 2^64 % 3 == 1, so only u64::MAX is rejected
+        assert_eq!(reduce(u64::MAX - 1, 3), Some((u64::MAX - 1) % 3));
+        assert_eq!(reduce(u64::MAX, 3), None);
+
+        // 2^64 % (2^63 + 1) == 2^63 - 1, leaving only 0..=2^63 accepted
+        let bound = (1 << 63) + 1;
+        assert_eq!(reduce(1 << 63, bound), Some(1 << 63));
+        assert_eq!(reduce(bound, bound), None);
+
+        // Powers of two divide 2^64, so nothing is rejected
+        assert_eq!(reduce(u64::MAX, 1 << 32), Some((1 << 32) - 1));
+        assert_eq!(reduce(u64::MAX, 1), Some(0));
+    }
+
+    #[test]
+    fn version_1_is_pinned() {
+        let mut items = (0..10).collect::<Vec<_>>();
+        shuffle(&mut items, 42, 1).unwrap();
+        assert_eq!(items, [0, 9, 5, 8, 6, 4, 7, 2, 1, 3]);
+
+        let mut items = (0..10).collect::<Vec<_>>();
+        shuffle(&mut items, 0, 1).unwrap();
+        assert_eq!(items, [6, 3, 2, 9, 8, 1, 4, 7, 0, 5]);
+    }
+
+    #[test]
+    fn shuffles_are_permutations() {
+        for version in 0..=LATEST_VERSION {
+            let mut items = (0..1000).collect::<Vec<_>>();
+            shuffle(&mut items, 7, version).unwrap();
+            items.sort_unstable();
+
+            assert_eq!(items, (0..1000).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        assert!(shuffle(&mut [1, 2, 3], 0, LATEST_VERSION + 1).is_err());
+    }
+}
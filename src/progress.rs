@@ -0,0 +1,78 @@
+use {
+    clap::ValueEnum,
+    serde_json::{Value, json},
+    std::{
+        path::Path,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        time::Instant,
+    },
+};
+
+/// Records between two `records` events of the same file
+const INTERVAL: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static RECORDS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn configure(format: Option<Format>) {
+    JSON.store(matches!(format, Some(Format::Json)), Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Writes one event as a line of JSON to stderr, leaving stdout to the usual report.
+fn emit(event: Value) {
+    if enabled() {
+        eprintln!("{}", event);
+    }
+}
+
+pub fn file_started(path: &Path, index: usize, files: usize) {
+    RECORDS.store(0, Ordering::Relaxed);
+    emit(json!({
+        "event": "file_started",
+        "path": path.display().to_string(),
+        "index": index,
+        "files": files,
+    }));
+}
+
+/// Counts one record of the current file. Only the streaming scans (count, filter, verify,
+/// convert and export) report records as they go; every command reports them per file.
+pub fn record(path: &Path) {
+    let records = RECORDS.fetch_add(1, Ordering::Relaxed) + 1;
+
+    if records % INTERVAL == 0 && enabled() {
+        emit(json!({
+            "event": "records",
+            "path": path.display().to_string(),
+            "records": records,
+        }));
+    }
+}
+
+pub fn file_finished(path: &Path, records: usize, bytes: u64, started: Instant) {
+    emit(json!({
+        "event": "file_finished",
+        "path": path.display().to_string(),
+        "records": records,
+        "bytes": bytes,
+        "seconds": started.elapsed().as_secs_f64(),
+    }));
+}
+
+pub fn summary(files: usize, records: usize, started: Instant) {
+    emit(json!({
+        "event": "summary",
+        "files": files,
+        "records": records,
+        "seconds": started.elapsed().as_secs_f64(),
+    }));
+}
@@ -0,0 +1,203 @@
+use {
+    crate::{
+        PruneArgs, buffer,
+        checksum::{self, ChecksumReader},
+        error::{Context, Error, Result},
+        replace_file,
+    },
+    clap::ValueEnum,
+    std::{
+        cmp::Reverse,
+        fs::{File, OpenOptions, create_dir_all, remove_file, rename},
+        io::{Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+        time::SystemTime,
+    },
+    stoatformat::stoatpack::Stoatpack,
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Order {
+    Mtime,
+    Name,
+}
+
+pub struct PruneFile {
+    path: PathBuf,
+    modified: SystemTime,
+    pub positions: u64,
+}
+
+pub fn scan(path: PathBuf) -> Result<PruneFile> {
+    let file = OpenOptions::new().read(true).open(&path)?;
+    let modified = file.metadata()?.modified()?;
+    let positions = game_positions(&file, &path)?
+        .iter()
+        .map(|(_, positions)| positions)
+        .sum();
+
+    Ok(PruneFile {
+        path,
+        modified,
+        positions,
+    })
+}
+
+/// Keeps the newest `--keep-positions` positions. Files are ranked by `--order`, and within the
+/// file that straddles the budget the earliest games are the ones removed.
+pub fn prune(args: &PruneArgs, mut files: Vec<PruneFile>) -> Result<(u64, usize)> {
+    match args.order {
+        Order::Mtime => files.sort_by_key(|file| Reverse(file.modified)),
+        Order::Name => files.sort_by(|a, b| b.path.cmp(&a.path)),
+    }
+
+    if let Some(archive) = &args.archive {
+        if !args.dry_run {
+            create_dir_all(archive)?;
+        }
+    }
+
+    let mut kept = 0;
+    let mut removed_files = 0;
+
+    for file in files {
+        let remaining = args.keep_positions - kept.min(args.keep_positions);
+
+        if file.positions <= remaining {
+            kept += file.positions;
+            println!(
+                "  OK  : {}, {} positions",
+                file.path.display(),
+                file.positions
+            );
+        } else if remaining > 0 {
+            kept += prune_games(args, &file.path, remaining)?;
+        } else {
+            remove(args, &file.path)?;
+            removed_files += 1;
+
+            println!(
+                "Remove: {}, {} positions",
+                file.path.display(),
+                file.positions
+            );
+        }
+    }
+
+    Ok((kept, removed_files))
+}
+
+fn prune_games(args: &PruneArgs, path: &Path, budget: u64) -> Result<u64> {
+    let mut file = OpenOptions::new().read(true).open(path)?;
+    let games = game_positions(&file, path)?;
+    let mut kept = 0;
+    let mut split = games.len();
+
+    for (idx, (_, positions)) in games.iter().enumerate().rev() {
+        if kept + positions > budget {
+            break;
+        }
+
+        kept += positions;
+        split = idx;
+    }
+
+    let offset = match games.get(split) {
+        Some((offset, _)) => *offset,
+        None => file.metadata()?.len(),
+    };
+
+    println!(
+        "Prune : {}, {} games removed, {} positions kept",
+        path.display(),
+        split,
+        kept
+    );
+
+    if !args.dry_run {
+        let mut buffer = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut buffer)?;
+
+        if let Some(archive) = &args.archive {
+            append_to_archive(&archive_path(archive, path)?, &buffer[..offset as usize])?;
+        }
+
+        replace_file(path, &buffer[offset as usize..])?;
+        checksum::refresh(path)?;
+    }
+
+    Ok(kept)
+}
+
+fn remove(args: &PruneArgs, path: &Path) -> Result<()> {
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let sidecar = checksum::sidecar_path(path);
+    let archived = match &args.archive {
+        Some(archive) => archive_path(archive, path)?,
+        None => {
+            remove_file(path)?;
+            return remove_sidecar(&sidecar);
+        }
+    };
+
+    if archived.exists() {
+        // An earlier prune archived games under this name, which must survive
+        let mut buffer = Vec::new();
+        File::open(path)?.read_to_end(&mut buffer)?;
+        append_to_archive(&archived, &buffer)?;
+        remove_file(path)?;
+        return remove_sidecar(&sidecar);
+    }
+
+    rename(path, &archived)?;
+
+    if sidecar.is_file() {
+        rename(&sidecar, checksum::sidecar_path(&archived))?;
+    }
+
+    Ok(())
+}
+
+/// Appends pruned games to their file in the archive, so games archived by earlier runs
+/// are kept
+fn append_to_archive(archived: &Path, games: &[u8]) -> Result<()> {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(archived)?
+        .write_all(games)?;
+
+    checksum::refresh(archived)
+}
+
+fn remove_sidecar(sidecar: &Path) -> Result<()> {
+    if sidecar.is_file() {
+        remove_file(sidecar)?;
+    }
+
+    Ok(())
+}
+
+fn archive_path(archive: &Path, path: &Path) -> Result<PathBuf> {
+    path.file_name()
+        .map(|name| archive.join(name))
+        .ok_or_else(|| Error::other(format!("Invalid path: {}", path.display())))
+}
+
+fn game_positions(file: &File, path: &Path) -> Result<Vec<(u64, u64)>> {
+    let mut reader = buffer::reader(ChecksumReader::new(file, path)?);
+    let len = file.metadata()?.len();
+    let mut games = Vec::new();
+
+    while reader.stream_position()? < len {
+        let offset = reader.stream_position()?;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+        games.push((offset, game.moves.len() as u64 + 1));
+    }
+
+    Ok(games)
+}
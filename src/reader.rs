@@ -0,0 +1,64 @@
+use {
+    crate::{
+        buffer,
+        checksum::ChecksumReader,
+        error::{Context, Result},
+    },
+    std::{
+        fs::File,
+        io::{BufReader, Seek},
+        path::{Path, PathBuf},
+    },
+    stoatformat::stoatpack::Stoatpack,
+};
+
+/// Reads the records of a `.spk` file one game at a time, checking the file against its
+/// checksum sidecar or manifest as it goes. A record that fails to decode is reported with
+/// its offset and ends the iteration.
+pub struct RecordReader {
+    reader: BufReader<ChecksumReader<File>>,
+    path: PathBuf,
+    len: u64,
+    failed: bool,
+}
+
+impl RecordReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let reader = ChecksumReader::open(path)?;
+        let len = reader.len();
+
+        Ok(Self {
+            reader: buffer::reader(reader),
+            path: path.to_path_buf(),
+            len,
+            failed: false,
+        })
+    }
+
+    fn read(&mut self) -> Result<Option<Stoatpack>> {
+        let offset = self.reader.stream_position()?;
+
+        if offset >= self.len {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            Stoatpack::deserialise(&mut self.reader).at_offset(&self.path, offset)?,
+        ))
+    }
+}
+
+impl Iterator for RecordReader {
+    type Item = Result<Stoatpack>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let record = self.read();
+        self.failed = record.is_err();
+
+        record.transpose()
+    }
+}
@@ -0,0 +1,103 @@
+use {
+    crate::{
+        buffer,
+        checksum::ChecksumReader,
+        error::{Context, Error, Result},
+        replace_file,
+    },
+    std::{
+        fs::{OpenOptions, read_to_string},
+        io::Seek,
+        path::Path,
+    },
+    stoatformat::stoatpack::Stoatpack,
+};
+
+/// A piecewise-linear score mapping loaded from `old_eval,new_eval` lines. Scores outside the
+/// table follow the nearest segment's slope.
+pub struct EvalTable {
+    points: Vec<(f64, f64)>,
+}
+
+impl EvalTable {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut points = Vec::new();
+
+        for (idx, line) in read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let parsed = line
+                .split_once(',')
+                .and_then(|(old, new)| Some((old.trim().parse().ok()?, new.trim().parse().ok()?)));
+
+            match parsed {
+                Some(point) => points.push(point),
+                // Allow a header row
+                None if idx == 0 => continue,
+                None => {
+                    return Err(Error::other(format!(
+                        "Invalid mapping at {}:{}: {}",
+                        path.display(),
+                        idx + 1,
+                        line
+                    )));
+                }
+            }
+        }
+
+        points.sort_by(|a: &(f64, f64), b| a.0.total_cmp(&b.0));
+        points.dedup_by(|a, b| a.0 == b.0);
+
+        if points.len() < 2 {
+            return Err(Error::other(format!(
+                "Mapping table {} needs at least two points",
+                path.display()
+            )));
+        }
+
+        Ok(Self { points })
+    }
+
+    pub fn map(&self, score: i16) -> i16 {
+        let score = score as f64;
+        let segment = self
+            .points
+            .windows(2)
+            .position(|pair| score <= pair[1].0)
+            .unwrap_or(self.points.len() - 2);
+        let ((x0, y0), (x1, y1)) = (self.points[segment], self.points[segment + 1]);
+        let mapped = y0 + (score - x0) * (y1 - y0) / (x1 - x0);
+
+        mapped.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+    }
+}
+
+pub fn remap(path: &Path, table: &EvalTable) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+    let len = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    let mut records = 0;
+
+    while reader.stream_position()? < len {
+        let offset = reader.stream_position()?;
+        let mut game = Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+
+        for (_, score) in &mut game.moves {
+            *score = table.map(*score);
+        }
+
+        game.serialise(&mut buffer)?;
+        records += 1;
+    }
+
+    replace_file(path, &buffer)?;
+
+    println!("Remap : {}, {} records", path.display(), records);
+
+    Ok(records)
+}
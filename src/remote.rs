@@ -0,0 +1,156 @@
+use {
+    crate::{
+        buffer,
+        checksum::ChecksumReader,
+        error::{Error, Result},
+    },
+    std::{
+        fs::{File, create_dir_all, rename},
+        hash::{DefaultHasher, Hash, Hasher},
+        io::{self, Read, Write, copy},
+        path::{Path, PathBuf},
+        process::{self, Child, ChildStdout, Stdio},
+    },
+};
+
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str().is_some_and(|url| {
+        url.starts_with("s3://") || url.starts_with("http://") || url.starts_with("https://")
+    })
+}
+
+/// Opens a local file or streams a remote object, returning the reader and its length in bytes.
+pub fn open(path: &Path) -> Result<(Box<dyn Read>, u64)> {
+    let Some(url) = path.to_str().filter(|_| is_remote(path)) else {
+        let reader = ChecksumReader::open(path)?;
+        let len = reader.len();
+
+        return Ok((Box::new(reader), len));
+    };
+
+    match url.strip_prefix("s3://") {
+        Some(object) => {
+            let (bucket, key) = object
+                .split_once('/')
+                .ok_or_else(|| Error::other(format!("Invalid S3 URL: {}", url)))?;
+            let output = process::Command::new("aws")
+                .args(["s3api", "head-object", "--bucket", bucket, "--key", key])
+                .args(["--query", "ContentLength", "--output", "text"])
+                .output()?;
+
+            if !output.status.success() {
+                return Err(Error::other(format!(
+                    "Failed to open {}: {}",
+                    url,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+
+            let len = String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .parse()
+                .map_err(Error::other)?;
+            let mut child = process::Command::new("aws")
+                .args(["s3", "cp", "--only-show-errors", url, "-"])
+                .stdout(Stdio::piped())
+                .spawn()?;
+            let stdout = child.stdout.take().unwrap();
+
+            Ok((
+                Box::new(ChildReader {
+                    child,
+                    stdout,
+                    url: url.to_string(),
+                    finished: false,
+                }),
+                len,
+            ))
+        }
+        None => {
+            let response = ureq::get(url).call().map_err(Error::other)?;
+            let len = response
+                .headers()
+                .get("content-length")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| Error::other(format!("No Content-Length for {}", url)))?;
+
+            Ok((Box::new(response.into_body().into_reader()), len))
+        }
+    }
+}
+
+/// Streams the output of a download command, waiting on it at the end so a failed download
+/// is an error rather than a clean short read.
+struct ChildReader {
+    child: Child,
+    stdout: ChildStdout,
+    url: String,
+    finished: bool,
+}
+
+impl Read for ChildReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+
+        if read == 0 && !buf.is_empty() && !self.finished {
+            self.finished = true;
+            let status = self.child.wait()?;
+
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "Failed to download {}, aws exited with {}",
+                    self.url, status
+                )));
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+impl Drop for ChildReader {
+    fn drop(&mut self) {
+        // Readers that stop early still reap the child
+        if !self.finished {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Downloads a remote input into `cache_dir`, reusing an earlier download of the same URL.
+pub fn fetch(path: &Path, cache_dir: &Path) -> Result<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let cached = cache_dir.join(format!("{:016x}-{}", hasher.finish(), name));
+
+    if cached.is_file() {
+        println!("Cached: {} -> {}", path.display(), cached.display());
+        return Ok(cached);
+    }
+
+    create_dir_all(cache_dir)?;
+
+    let partial = cached.with_extension("spk.part");
+    let (mut reader, len) = open(path)?;
+    let mut writer = buffer::writer(File::create(&partial)?);
+    let copied = copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+
+    if copied != len {
+        return Err(Error::other(format!(
+            "Download truncated: {}, {} of {} bytes",
+            path.display(),
+            copied,
+            len
+        )));
+    }
+
+    rename(&partial, &cached)?;
+    println!("Fetch : {} -> {}", path.display(), cached.display());
+
+    Ok(cached)
+}
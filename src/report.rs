@@ -0,0 +1,250 @@
+use {
+    crate::{CountStats, MATERIAL_RANGE, PHASES, error::Result, heatmap},
+    std::{
+        fmt::Write as _,
+        fs::File,
+        io::Write,
+        path::{Path, PathBuf},
+    },
+};
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; } \
+    table { border-collapse: collapse; margin-bottom: 1em; } \
+    th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: right; } \
+    th:first-child, td:first-child { text-align: left; } \
+    .heatmaps { display: flex; flex-wrap: wrap; gap: 1em; }";
+
+pub struct FileSummary {
+    path: PathBuf,
+    games: usize,
+    positions: usize,
+    black_wins: usize,
+    white_wins: usize,
+    draws: usize,
+    reverses: usize,
+}
+
+impl FileSummary {
+    pub fn new(path: &Path, stats: &CountStats) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            games: stats.games(),
+            positions: stats.positions,
+            black_wins: stats.black_wins,
+            white_wins: stats.white_wins,
+            draws: stats.draws,
+            reverses: stats.reverses,
+        }
+    }
+
+    pub fn since(self, before: &Self) -> Self {
+        Self {
+            path: self.path,
+            games: self.games - before.games,
+            positions: self.positions - before.positions,
+            black_wins: self.black_wins - before.black_wins,
+            white_wins: self.white_wins - before.white_wins,
+            draws: self.draws - before.draws,
+            reverses: self.reverses - before.reverses,
+        }
+    }
+}
+
+pub fn write_html(
+    path: &Path,
+    stats: &CountStats,
+    files: &[FileSummary],
+    sources: &[FileSummary],
+) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(render_html(stats, files, sources).as_bytes())?;
+
+    Ok(())
+}
+
+fn render_html(stats: &CountStats, files: &[FileSummary], sources: &[FileSummary]) -> String {
+    let games = stats.games();
+    let mut html = String::new();
+
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>spk-tools report</title>\n<style>{}</style>\n</head>\n<body>",
+        STYLE
+    )
+    .unwrap();
+    writeln!(html, "<h1>spk-tools report</h1>").unwrap();
+
+    writeln!(html, "<h2>Summary</h2>\n<table>").unwrap();
+    writeln!(html, "<tr><td>Files</td><td>{}</td></tr>", files.len()).unwrap();
+    writeln!(
+        html,
+        "<tr><td>Total positions</td><td>{}</td></tr>",
+        stats.positions
+    )
+    .unwrap();
+    writeln!(html, "<tr><td>Total games</td><td>{}</td></tr>", games).unwrap();
+
+    for (name, value) in [
+        ("Black wins", stats.black_wins),
+        ("White wins", stats.white_wins),
+        ("Draws", stats.draws),
+        ("Reverses", stats.reverses),
+    ] {
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{} ({})</td></tr>",
+            name,
+            value,
+            percent(value as u64, games as u64)
+        )
+        .unwrap();
+    }
+
+    writeln!(html, "</table>").unwrap();
+
+    writeln!(html, "<h2>Eval distribution</h2>").unwrap();
+    html.push_str(&bar_chart(&stats.eval_labels(), &stats.eval_histogram));
+
+    writeln!(html, "<h2>Game length distribution</h2>").unwrap();
+    html.push_str(&bar_chart(&stats.length_labels(), &stats.length_histogram));
+
+    writeln!(html, "<h2>Material balance</h2>\n<table>").unwrap();
+    writeln!(
+        html,
+        "<tr><th>Balance</th>{}</tr>",
+        PHASES
+            .iter()
+            .map(|phase| format!("<th>{}</th>", phase))
+            .collect::<String>()
+    )
+    .unwrap();
+
+    for bucket in 0..stats.material[0].len() {
+        write!(html, "<tr><td>{}</td>", bucket as i32 - MATERIAL_RANGE).unwrap();
+
+        for phase in &stats.material {
+            write!(
+                html,
+                "<td>{}</td>",
+                percent(phase[bucket], phase.iter().sum())
+            )
+            .unwrap();
+        }
+
+        writeln!(html, "</tr>").unwrap();
+    }
+
+    writeln!(html, "</table>").unwrap();
+
+    writeln!(html, "<h2>King squares</h2>\n<div class=\"heatmaps\">").unwrap();
+    html.push_str(&heatmap::render_svg("Overall", &stats.king_squares));
+
+    for (name, king_squares) in PHASES.iter().zip(&stats.phase_king_squares) {
+        html.push_str(&heatmap::render_svg(name, king_squares));
+    }
+
+    for (name, king_squares) in ["Winning side", "Losing side"]
+        .iter()
+        .zip(&stats.outcome_king_squares)
+    {
+        html.push_str(&heatmap::render_svg(name, king_squares));
+    }
+
+    writeln!(html, "</div>").unwrap();
+
+    if !sources.is_empty() {
+        summary_table(&mut html, "Sources", "Source", sources);
+    }
+
+    summary_table(&mut html, "Files", "File", files);
+
+    writeln!(html, "</body>\n</html>").unwrap();
+
+    html
+}
+
+fn summary_table(html: &mut String, title: &str, name: &str, files: &[FileSummary]) {
+    writeln!(html, "<h2>{}</h2>\n<table>", title).unwrap();
+    writeln!(
+        html,
+        "<tr><th>{}</th><th>Games</th><th>Positions</th><th>Black wins</th><th>White wins</th><th>Draws</th><th>Reverses</th></tr>",
+        name
+    )
+    .unwrap();
+
+    for file in files {
+        let games = file.games as u64;
+
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            heatmap::escape(&file.path.display().to_string()),
+            file.games,
+            file.positions,
+            percent(file.black_wins as u64, games),
+            percent(file.white_wins as u64, games),
+            percent(file.draws as u64, games),
+            percent(file.reverses as u64, games)
+        )
+        .unwrap();
+    }
+
+    writeln!(html, "</table>").unwrap();
+}
+
+fn bar_chart(labels: &[String], values: &[u64]) -> String {
+    let bar = 20;
+    let chart = 200;
+    let width = bar * values.len() + 80;
+    let height = chart + 60;
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let mut svg = String::new();
+
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="sans-serif" font-size="10">"#
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<text x="0" y="12">{}</text>"#,
+        values.iter().copied().max().unwrap_or(0)
+    )
+    .unwrap();
+
+    for (i, (label, &value)) in labels.iter().zip(values).enumerate() {
+        let bar_height = (value as f64 / max * chart as f64).round() as usize;
+        let x = 60 + i * bar;
+
+        writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="steelblue"><title>{}: {}</title></rect>"#,
+            x,
+            20 + chart - bar_height,
+            bar - 2,
+            bar_height,
+            label,
+            value
+        )
+        .unwrap();
+
+        if i % 5 == 0 {
+            writeln!(
+                svg,
+                r#"<text x="{}" y="{}">{}</text>"#,
+                x,
+                chart + 36,
+                label
+            )
+            .unwrap();
+        }
+    }
+
+    svg.push_str("</svg>\n");
+
+    svg
+}
+
+fn percent(value: u64, total: u64) -> String {
+    format!("{:.2}%", value as f64 / total as f64 * 100.0f64)
+}
@@ -0,0 +1,283 @@
+mod metrics;
+mod ring;
+
+use {
+    crate::{
+        ServeArgs, buffer,
+        checksum::ChecksumReader,
+        convert::{self, RECORD_SIZE, Row},
+        error::{Context, Error, Result},
+    },
+    spk_tools::shuffle::ShuffleBuffer,
+    std::{
+        fs::{OpenOptions, remove_file},
+        io::{self, Seek, Write},
+        net::TcpListener,
+        path::{Path, PathBuf},
+    },
+    stoatformat::stoatpack::Stoatpack,
+};
+
+type Record = [u8; RECORD_SIZE];
+
+/// Where finished batches of 80-byte records, in the layout of `convert --to packed`,
+/// are handed to the trainer
+trait BatchSink {
+    fn send(&mut self, records: u32, batch: &[u8]) -> io::Result<()>;
+}
+
+/// Sends each batch over a socket as a little-endian `u32` record count and the records
+struct Socket<W: Write>(W);
+
+impl<W: Write> BatchSink for Socket<W> {
+    fn send(&mut self, records: u32, batch: &[u8]) -> io::Result<()> {
+        self.0.write_all(&records.to_le_bytes())?;
+        self.0.write_all(batch)?;
+        self.0.flush()
+    }
+}
+
+/// Cuts records into batches of `batch_size`, only the very last one being shorter
+struct Batcher<S: BatchSink> {
+    sink: S,
+    batch: Vec<u8>,
+    batch_size: usize,
+    batches: u64,
+}
+
+impl<S: BatchSink> Batcher<S> {
+    fn new(sink: S, batch_size: usize) -> Self {
+        Self {
+            sink,
+            batch: Vec::with_capacity(batch_size * RECORD_SIZE),
+            batch_size,
+            batches: 0,
+        }
+    }
+
+    fn push(&mut self, record: Record) -> io::Result<()> {
+        self.batch.extend_from_slice(&record);
+
+        if self.batch.len() == self.batch_size * RECORD_SIZE {
+            self.send()?;
+        }
+
+        Ok(())
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        let records = (self.batch.len() / RECORD_SIZE) as u32;
+        self.sink.send(records, &self.batch)?;
+        metrics::batch(4 + self.batch.len());
+        self.batch.clear();
+        self.batches += 1;
+
+        Ok(())
+    }
+}
+
+/// Streams shuffled batches to one client at a time, reading the inputs over and over, or
+/// `args.epochs` times before closing the connection and exiting. `--socket` listens on a
+/// Unix domain socket instead of TCP, and with `--shm` the batches go into a shared-memory
+/// ring buffer, for a single trainer on the same machine. `--metrics` exposes progress to
+/// Prometheus over HTTP.
+pub fn serve(paths: &[PathBuf], args: &ServeArgs) -> Result<()> {
+    if let Some(addr) = &args.metrics {
+        metrics::spawn(addr)?;
+    }
+
+    if let Some(path) = &args.shm {
+        let ring = ring::RingBuffer::create(path, args.slots, args.batch_size)?;
+        println!("Serving {} files into {}", paths.len(), path.display());
+
+        let mut batcher = Batcher::new(ring, args.batch_size);
+        stream_epochs(paths, args, args.seed, &mut batcher)?;
+        batcher.sink.close()?;
+        println!("Wrote {} batches", batcher.batches);
+
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = &args.socket {
+        return serve_unix(paths, args, path);
+    }
+
+    #[cfg(not(unix))]
+    if args.socket.is_some() {
+        return Err(Error::other("--socket needs Unix domain sockets"));
+    }
+
+    let listener = TcpListener::bind(&args.listen)?;
+    println!("Serving {} files on {}", paths.len(), args.listen);
+
+    serve_clients(
+        paths,
+        args,
+        listener.incoming().map(|stream| {
+            let stream = stream?;
+            let peer = stream.peer_addr()?.to_string();
+
+            Ok((stream, peer))
+        }),
+    )
+}
+
+/// Removes the socket file once the server stops, so the next run can bind the path again
+#[cfg(unix)]
+struct SocketFile<'a>(&'a Path);
+
+#[cfg(unix)]
+impl Drop for SocketFile<'_> {
+    fn drop(&mut self) {
+        let _ = remove_file(self.0);
+    }
+}
+
+#[cfg(unix)]
+fn serve_unix(paths: &[PathBuf], args: &ServeArgs, path: &Path) -> Result<()> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    // A socket file nobody answers on is left over from a server that did not shut down
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(Error::other(format!(
+                "{} is already served by another process",
+                path.display()
+            )));
+        }
+
+        remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let _socket_file = SocketFile(path);
+    println!("Serving {} files on {}", paths.len(), path.display());
+
+    serve_clients(
+        paths,
+        args,
+        listener
+            .incoming()
+            .enumerate()
+            .map(|(client, stream)| Ok((stream?, format!("#{}", client)))),
+    )
+}
+
+/// Serves each client in turn until one has been sent every epoch, moving on to the next
+/// connection whenever a client goes away so trainers can reconnect after a restart.
+fn serve_clients<S: Write>(
+    paths: &[PathBuf],
+    args: &ServeArgs,
+    clients: impl Iterator<Item = io::Result<(S, String)>>,
+) -> Result<()> {
+    for (client, connection) in clients.enumerate() {
+        let (stream, peer) = connection?;
+        println!("Client connected: {}", peer);
+
+        let mut batcher = Batcher::new(Socket(buffer::writer(stream)), args.batch_size);
+
+        // Each client gets its own order, still reproducible from the seed
+        match stream_epochs(
+            paths,
+            args,
+            args.seed.wrapping_add(client as u64),
+            &mut batcher,
+        ) {
+            Ok(()) => {
+                println!("Client {} done after {} batches", peer, batcher.batches);
+                return Ok(());
+            }
+            Err(error) if is_disconnect(&error) => {
+                println!(
+                    "Client {} disconnected after {} batches",
+                    peer, batcher.batches
+                );
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+fn stream_epochs<S: BatchSink>(
+    paths: &[PathBuf],
+    args: &ServeArgs,
+    seed: u64,
+    batcher: &mut Batcher<S>,
+) -> Result<()> {
+    let mut buffer = ShuffleBuffer::new(args.shuffle_buffer as usize, seed);
+    let mut epoch = 0;
+
+    while args.epochs.is_none_or(|epochs| epoch < epochs) {
+        for path in paths {
+            stream_file(
+                path,
+                args.eval_limit,
+                &mut |record| match buffer.push(record) {
+                    Some(record) => batcher.push(record),
+                    None => Ok(()),
+                },
+            )?;
+        }
+
+        epoch += 1;
+    }
+
+    while let Some(record) = buffer.pop() {
+        batcher.push(record)?;
+    }
+
+    Ok(batcher.send()?)
+}
+
+fn stream_file(
+    path: &Path,
+    eval_limit: i16,
+    emit: &mut dyn FnMut(Record) -> io::Result<()>,
+) -> Result<()> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+    let len = file.metadata()?.len();
+
+    while reader.stream_position()? < len {
+        let offset = reader.stream_position()?;
+        let game = Stoatpack::deserialise(&mut reader)
+            .at_offset(path, offset)
+            .inspect_err(|_| metrics::broken_record())?;
+        metrics::record();
+        let result = convert::sente_result(game.wdl);
+        let mut pos = game.startpos;
+
+        for (ply, (mv, score)) in game.moves.into_iter().enumerate() {
+            if score.abs() <= eval_limit {
+                emit(convert::encode_record(&Row {
+                    game_id: 0,
+                    ply,
+                    pos: &pos,
+                    score,
+                    result,
+                }))?;
+            }
+
+            pos = pos.apply_move(mv);
+        }
+    }
+
+    Ok(())
+}
+
+fn is_disconnect(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Io { source, .. } if matches!(
+            source.kind(),
+            io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+        )
+    )
+}
@@ -0,0 +1,93 @@
+use {
+    crate::error::Result,
+    std::{
+        io::{self, Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::atomic::{AtomicU64, Ordering},
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+/// How long a client may take to send its request or take the response, so one that stalls
+/// cannot hold up the scrapes after it
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static RECORDS: AtomicU64 = AtomicU64::new(0);
+static BROKEN_RECORDS: AtomicU64 = AtomicU64::new(0);
+static BATCHES: AtomicU64 = AtomicU64::new(0);
+static BYTES_SERVED: AtomicU64 = AtomicU64::new(0);
+
+pub fn record() {
+    RECORDS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn broken_record() {
+    BROKEN_RECORDS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn batch(bytes: usize) {
+    BATCHES.fetch_add(1, Ordering::Relaxed);
+    BYTES_SERVED.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Answers every HTTP request on `addr` with the counters in the Prometheus text format,
+/// from a background thread that lives as long as the server. The batch rate is measured
+/// since the previous scrape.
+pub fn spawn(addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("Metrics on http://{}/metrics", addr);
+
+    thread::spawn(move || {
+        let mut last_scrape = (Instant::now(), 0);
+
+        for stream in listener.incoming().flatten() {
+            let batches = BATCHES.load(Ordering::Relaxed);
+            let elapsed = last_scrape.0.elapsed().as_secs_f64();
+            let rate = (batches - last_scrape.1) as f64 / elapsed.max(f64::EPSILON);
+            last_scrape = (Instant::now(), batches);
+
+            // A scraper that hangs up early only loses its own response
+            let _ = respond(stream, batches, rate);
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, batches: u64, rate: f64) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    // The request itself does not matter, every path gets the metrics
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request)?;
+
+    let body = format!(
+        "# TYPE spk_records_total counter\n\
+         spk_records_total {}\n\
+         # TYPE spk_broken_records_total counter\n\
+         spk_broken_records_total {}\n\
+         # TYPE spk_batches_total counter\n\
+         spk_batches_total {}\n\
+         # TYPE spk_bytes_served_total counter\n\
+         spk_bytes_served_total {}\n\
+         # TYPE spk_batches_per_second gauge\n\
+         spk_batches_per_second {:.3}\n",
+        RECORDS.load(Ordering::Relaxed),
+        BROKEN_RECORDS.load(Ordering::Relaxed),
+        batches,
+        BYTES_SERVED.load(Ordering::Relaxed),
+        rate
+    );
+
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
@@ -0,0 +1,139 @@
+use {
+    super::BatchSink,
+    crate::{
+        convert::RECORD_SIZE,
+        error::{Error, Result},
+    },
+    memmap2::MmapMut,
+    std::{
+        fs::OpenOptions,
+        io,
+        path::Path,
+        sync::atomic::{AtomicU32, AtomicU64, Ordering},
+        thread,
+        time::Duration,
+    },
+};
+
+const MAGIC: &[u8; 8] = b"SPKRING\0";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 64;
+
+const WRITE_INDEX: usize = 24;
+const READ_INDEX: usize = 32;
+const CLOSED: usize = 40;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A ring of batch slots in a memory-mapped file, meant for a memory-backed filesystem such as
+/// `/dev/shm` so a trainer on the same machine can map it too and read batches without a copy.
+/// Integers are little-endian, and the indices and closed flag are naturally aligned so both
+/// sides can access them atomically. The 64-byte header holds:
+///
+/// - `[u8; 8]` magic `SPKRING\0`, `u32` version, `u32` record size (80)
+/// - `u32` records per slot, `u32` slot count
+/// - `u64` write index at byte 24: batches published so far, advanced by spk-tools
+/// - `u64` read index at byte 32: batches consumed so far, advanced by the trainer
+/// - `u32` closed flag at byte 40, set once the last batch is published
+///
+/// Batch `n` lives in slot `n % slots`, at `64 + slot * (4 + records_per_slot * 80)`, as a
+/// `u32` record count followed by the records. The writer waits while all slots are unread.
+pub struct RingBuffer {
+    map: MmapMut,
+    slots: usize,
+    slot_size: usize,
+    written: u64,
+}
+
+impl RingBuffer {
+    pub fn create(path: &Path, slots: usize, batch_size: usize) -> Result<Self> {
+        if slots == 0 || batch_size == 0 {
+            return Err(Error::other(
+                "Ring buffer needs at least one slot of one record",
+            ));
+        }
+
+        let too_large = || Error::other("Ring buffer too large, lower --slots or --batch-size");
+        let slot_size = batch_size
+            .checked_mul(RECORD_SIZE)
+            .and_then(|size| size.checked_add(4))
+            .ok_or_else(too_large)?;
+        let len = slots
+            .checked_mul(slot_size)
+            .and_then(|size| size.checked_add(HEADER_SIZE))
+            .ok_or_else(too_large)?;
+        // The header holds both as u32
+        let header_batch_size = u32::try_from(batch_size).map_err(|_| too_large())?;
+        let header_slots = u32::try_from(slots).map_err(|_| too_large())?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        file.set_len(len as u64)?;
+
+        // SAFETY: the file was just created at its full size, and the trainer only ever
+        // touches the read index, which is accessed atomically
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&(RECORD_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&header_batch_size.to_le_bytes());
+        header.extend_from_slice(&header_slots.to_le_bytes());
+        map[..header.len()].copy_from_slice(&header);
+
+        Ok(Self {
+            map,
+            slots,
+            slot_size,
+            written: 0,
+        })
+    }
+
+    /// Tells the trainer no batches follow the ones already published
+    pub fn close(&mut self) -> io::Result<()> {
+        self.closed().store(1u32.to_le(), Ordering::Release);
+        self.map.flush()
+    }
+
+    fn index(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: the mapping is page-aligned and lives as long as `self`, and the index
+        // offsets are multiples of 8 inside the header
+        unsafe { AtomicU64::from_ptr(self.map.as_ptr().add(offset) as *mut u64) }
+    }
+
+    fn closed(&self) -> &AtomicU32 {
+        // SAFETY: as for `index`, with the flag at a multiple of 4
+        unsafe { AtomicU32::from_ptr(self.map.as_ptr().add(CLOSED) as *mut u32) }
+    }
+}
+
+impl BatchSink for RingBuffer {
+    fn send(&mut self, records: u32, batch: &[u8]) -> io::Result<()> {
+        // Acquire pairs with the trainer's release once it is done reading a slot
+        while self
+            .written
+            .saturating_sub(u64::from_le(self.index(READ_INDEX).load(Ordering::Acquire)))
+            >= self.slots as u64
+        {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let offset = HEADER_SIZE + (self.written % self.slots as u64) as usize * self.slot_size;
+        self.map[offset..offset + 4].copy_from_slice(&records.to_le_bytes());
+        self.map[offset + 4..offset + 4 + batch.len()].copy_from_slice(batch);
+
+        // Publishing the index last, with release ordering, keeps the trainer from seeing a
+        // half-written slot
+        self.written += 1;
+        self.index(WRITE_INDEX)
+            .store(self.written.to_le(), Ordering::Release);
+
+        Ok(())
+    }
+}
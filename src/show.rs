@@ -0,0 +1,237 @@
+use {
+    crate::{
+        ShowArgs, buffer,
+        checksum::ChecksumReader,
+        error::{Context, Result},
+        export::outcome_name,
+    },
+    colored::Colorize,
+    std::{fmt::Write as _, fs::OpenOptions, io::Seek, path::Path},
+    stoatformat::{
+        shogi::{
+            core::{Color, Move, PieceType},
+            position::Position,
+        },
+        stoatpack::Stoatpack,
+    },
+};
+
+/// Piece types with their kanji and USI letters; the first seven can be held in hand
+pub const PIECES: [(PieceType, &str, &str); 14] = [
+    (PieceType::PAWN, "歩", "P"),
+    (PieceType::LANCE, "香", "L"),
+    (PieceType::KNIGHT, "桂", "N"),
+    (PieceType::SILVER, "銀", "S"),
+    (PieceType::GOLD, "金", "G"),
+    (PieceType::BISHOP, "角", "B"),
+    (PieceType::ROOK, "飛", "R"),
+    (PieceType::PROMOTED_PAWN, "と", "+P"),
+    (PieceType::PROMOTED_LANCE, "杏", "+L"),
+    (PieceType::PROMOTED_KNIGHT, "圭", "+N"),
+    (PieceType::PROMOTED_SILVER, "全", "+S"),
+    (PieceType::PROMOTED_BISHOP, "馬", "+B"),
+    (PieceType::PROMOTED_ROOK, "龍", "+R"),
+    (PieceType::KING, "玉", "K"),
+];
+const HAND_PIECES: usize = 7;
+
+pub const FILES: [&str; 9] = ["９", "８", "７", "６", "５", "４", "３", "２", "１"];
+pub const NUMERALS: [&str; 9] = ["一", "二", "三", "四", "五", "六", "七", "八", "九"];
+
+/// Prints game `args.game` of a file, one board per position or only the one at `args.ply`.
+pub fn show(path: &Path, args: &ShowArgs) -> Result<usize> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+    let len = file.metadata()?.len();
+    let mut records = 0;
+
+    while reader.stream_position()? < len && records <= args.game {
+        let offset = reader.stream_position()?;
+        let game = Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+
+        if records == args.game {
+            show_game(path, records, &game, args);
+        }
+
+        records += 1;
+    }
+
+    if records <= args.game {
+        println!(
+            "{}: no game {}, {} records",
+            path.display(),
+            args.game,
+            records
+        );
+    }
+
+    Ok(records)
+}
+
+fn show_game(path: &Path, index: usize, game: &Stoatpack, args: &ShowArgs) {
+    let mut pos = game.startpos.clone();
+
+    println!(
+        "{}, game {}, {} plies, {}",
+        path.display(),
+        index,
+        game.moves.len(),
+        outcome_name(game.wdl)
+    );
+
+    if args.ply.is_none_or(|ply| ply == 0) {
+        println!("Start: {}", pos.sfen());
+        println!("{}", render(&pos, None, args.ascii));
+    }
+
+    for (ply, &(mv, score)) in game.moves.iter().enumerate() {
+        pos = pos.apply_move(mv);
+
+        if args.ply.is_none_or(|target| target == ply + 1) {
+            println!("Ply {}: {} (score {:+})", ply + 1, mv, score);
+            println!("{}", render(&pos, Some(mv), args.ascii));
+        }
+    }
+}
+
+/// Draws the board from sente's side, with gote's hand above it and sente's below. Gote's
+/// pieces are marked with `v` in kanji mode and lowercase in ASCII mode, and the square the
+/// last move landed on is shown in reverse video.
+pub fn render(pos: &Position, last_move: Option<Move>, ascii: bool) -> String {
+    let board = board(pos);
+    let mut text = String::new();
+    let target = last_move.and_then(|mv| destination(&mv.to_string()));
+
+    writeln!(text, "{}", hand(pos, Color::GOTE, ascii)).unwrap();
+    // Lines the file numbers up over the cells, past the left border
+    text.push(' ');
+
+    for (idx, file) in FILES.iter().enumerate() {
+        if ascii {
+            write!(text, "{:>3}", 9 - idx).unwrap();
+        } else {
+            write!(text, " {}", file).unwrap();
+        }
+    }
+
+    writeln!(text, "\n+{}+", "-".repeat(27)).unwrap();
+
+    for (row, numeral) in NUMERALS.iter().enumerate() {
+        text.push('|');
+
+        for col in 0..9 {
+            let square = (8 - row) * 9 + col;
+            let cell = match board[square] {
+                Some((color, idx)) if ascii => {
+                    let letters = PIECES[idx].2;
+
+                    if color == Color::SENTE {
+                        format!("{:>3}", letters)
+                    } else {
+                        format!("{:>3}", letters.to_lowercase())
+                    }
+                }
+                Some((color, idx)) => {
+                    let mark = if color == Color::SENTE { ' ' } else { 'v' };
+
+                    format!("{}{}", mark, PIECES[idx].1)
+                }
+                None if ascii => "  .".to_string(),
+                None => " ・".to_string(),
+            };
+
+            if target == Some(square) {
+                write!(text, "{}", cell.reversed()).unwrap();
+            } else {
+                text.push_str(&cell);
+            }
+        }
+
+        if ascii {
+            writeln!(text, "| {}", (b'a' + row as u8) as char).unwrap();
+        } else {
+            writeln!(text, "|{}", numeral).unwrap();
+        }
+    }
+
+    writeln!(text, "+{}+", "-".repeat(27)).unwrap();
+    write!(text, "{}", hand(pos, Color::SENTE, ascii)).unwrap();
+
+    text
+}
+
+/// The colour and [`PIECES`] index of whatever stands on each square
+pub fn board(pos: &Position) -> [Option<(Color, usize)>; 81] {
+    let mut board = [None; 81];
+
+    for (idx, &(piece_type, _, _)) in PIECES.iter().enumerate() {
+        for color in [Color::SENTE, Color::GOTE] {
+            for square in pos.piece_bb(piece_type.with_color(color)) {
+                board[square.idx()] = Some((color, idx));
+            }
+        }
+    }
+
+    board
+}
+
+fn hand(pos: &Position, color: Color, ascii: bool) -> String {
+    let hand = pos.hand(color);
+    let pieces = PIECES[..HAND_PIECES]
+        .iter()
+        .rev()
+        .filter(|&&(piece_type, _, _)| hand.count(piece_type) > 0)
+        .map(|&(piece_type, kanji, letter)| {
+            let count = hand.count(piece_type);
+
+            match (ascii, count) {
+                (true, 1) => letter.to_string(),
+                (true, _) => format!("{}{}", letter, count),
+                (false, 1) => kanji.to_string(),
+                (false, _) => format!("{}{}", kanji, kanji_number(count)),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    match (ascii, color == Color::SENTE) {
+        (true, true) => format!("Sente hand: {}", or_none(pieces, "-")),
+        (true, false) => format!("Gote hand : {}", or_none(pieces, "-")),
+        (false, true) => format!("先手の持駒：{}", or_none(pieces, "なし")),
+        (false, false) => format!("後手の持駒：{}", or_none(pieces, "なし")),
+    }
+}
+
+fn or_none(pieces: Vec<String>, none: &str) -> String {
+    if pieces.is_empty() {
+        none.to_string()
+    } else {
+        pieces.join(" ")
+    }
+}
+
+/// Hands hold at most 18 pawns, so tens never go past 十
+pub fn kanji_number(count: u32) -> String {
+    match count {
+        1..=9 => NUMERALS[count as usize - 1].to_string(),
+        10 => "十".to_string(),
+        _ => format!("十{}", NUMERALS[count as usize - 11]),
+    }
+}
+
+/// The square a move lands on, which USI writes third and fourth for both moves and drops
+pub fn destination(usi: &str) -> Option<usize> {
+    square(usi.get(2..)?)
+}
+
+/// Parses a USI square such as `7g` at the start of `usi`
+pub fn square(usi: &str) -> Option<usize> {
+    let mut chars = usi.chars();
+    let file = chars
+        .next()?
+        .to_digit(10)
+        .filter(|file| (1..=9).contains(file))?;
+    let rank = chars.next().filter(|c| ('a'..='i').contains(c))?;
+
+    // USI ranks run from gote's side, files from right to left
+    Some((8 - (rank as usize - 'a' as usize)) * 9 + (9 - file as usize))
+}
@@ -0,0 +1,41 @@
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+/// Holds up to `capacity` items and hands out a random one for each item added once full,
+/// so runs of positions from the same game or file leave it spread far apart.
+pub struct ShuffleBuffer<T> {
+    items: Vec<T>,
+    capacity: usize,
+    rng: SmallRng,
+}
+
+impl<T> ShuffleBuffer<T> {
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        Self {
+            items: Vec::new(),
+            capacity: capacity.max(1),
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn push(&mut self, item: T) -> Option<T> {
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+            return None;
+        }
+
+        let idx = self.rng.random_range(0..self.items.len());
+
+        Some(std::mem::replace(&mut self.items[idx], item))
+    }
+
+    /// Hands out a random remaining item, for draining the buffer once the input ends
+    pub fn pop(&mut self) -> Option<T> {
+        if self.items.is_empty() {
+            return None;
+        }
+
+        let idx = self.rng.random_range(0..self.items.len());
+
+        Some(self.items.swap_remove(idx))
+    }
+}
@@ -0,0 +1,107 @@
+use {
+    crate::{
+        CountStats, DROP_PIECES, PHASES,
+        error::{Error, Result},
+    },
+    serde_json::{Map, Value, json},
+    std::{
+        fs::{read_to_string, write},
+        path::{Path, PathBuf},
+    },
+};
+
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".stats.json");
+
+    PathBuf::from(name)
+}
+
+/// Replaces one command's section of the sidecar, keeping whatever the other commands wrote.
+pub fn update(path: &Path, section: &str, value: Value) -> Result<()> {
+    let sidecar = sidecar_path(path);
+    let mut root = read_to_string(&sidecar)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+        .filter(Value::is_object)
+        .unwrap_or_else(|| Value::Object(Map::new()));
+
+    root[section] = value;
+    write_json(&sidecar, &root)
+}
+
+pub fn write_json(path: &Path, value: &Value) -> Result<()> {
+    write(
+        path,
+        serde_json::to_string_pretty(value).map_err(Error::other)?,
+    )?;
+
+    Ok(())
+}
+
+pub fn count_summary(bias_plies: &[usize], stats: &CountStats) -> Value {
+    let bias = bias_plies
+        .iter()
+        .zip(&stats.ply_scores)
+        .map(|(ply, &(sum, count))| {
+            let mean_score = if count == 0 {
+                0.0
+            } else {
+                sum as f64 / count as f64
+            };
+
+            json!({
+                "ply": ply,
+                "positions": count,
+                "mean_score": mean_score,
+            })
+        })
+        .collect::<Vec<_>>();
+    let drops = DROP_PIECES
+        .iter()
+        .zip(&stats.drop_squares)
+        .map(|((_, name), squares)| (name.to_string(), json!(&squares[..])))
+        .collect::<Map<_, _>>();
+
+    json!({
+        "games": stats.games(),
+        "positions": stats.positions,
+        "black_wins": stats.black_wins,
+        "white_wins": stats.white_wins,
+        "draws": stats.draws,
+        "reverses": stats.reverses,
+        "bias": bias,
+        "eval_labels": stats.eval_labels(),
+        "eval_histogram": &stats.eval_histogram[..],
+        "length_labels": stats.length_labels(),
+        "length_histogram": &stats.length_histogram[..],
+        "king_squares": &stats.king_squares[..],
+        "phase_king_squares": phase_counts(&stats.phase_king_squares),
+        "winner_king_squares": &stats.outcome_king_squares[0][..],
+        "loser_king_squares": &stats.outcome_king_squares[1][..],
+        "material": phase_counts(&stats.material),
+        "king_distances": &stats.king_distances[..],
+        "major_distances": &stats.major_distances[..],
+        "board_pieces": &stats.board_pieces[..],
+        "promotion_zone_squares": phase_counts(&stats.promotion_zone_squares),
+        "promotion_zone_positions": phase_counts(&stats.promotion_zone_positions),
+        "drop_squares": drops,
+    })
+}
+
+pub fn fix_summary(records: usize, broken_records: usize, trimmed_bytes: u64) -> Value {
+    json!({
+        "records": records,
+        "broken_records": broken_records,
+        "trimmed_bytes": trimmed_bytes,
+    })
+}
+
+fn phase_counts<const N: usize>(counts: &[[u64; N]; PHASES.len()]) -> Value {
+    PHASES
+        .iter()
+        .zip(counts)
+        .map(|(phase, counts)| (phase.to_lowercase(), json!(&counts[..])))
+        .collect::<Map<_, _>>()
+        .into()
+}
@@ -0,0 +1,253 @@
+use {
+    crate::{
+        SplitArgs, buffer,
+        checksum::ChecksumReader,
+        error::{Context, Error, Result},
+        export::outcome_name,
+    },
+    std::{
+        collections::BTreeMap,
+        fs::{File, OpenOptions, create_dir_all},
+        io::{BufWriter, Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+        str::FromStr,
+    },
+    stoatformat::{Outcome, stoatpack::Stoatpack},
+};
+
+#[derive(Clone, Debug)]
+pub enum SplitBy {
+    Outcome,
+    Length(Vec<(usize, Option<usize>)>),
+    Hash { modulus: u64, threshold: u64 },
+}
+
+/// Parses `game_hash % M < K`. Games whose hash satisfies it go to `validation`, the rest to
+/// `train`.
+pub fn parse_hash_split(s: &str) -> Result<SplitBy> {
+    let invalid = || Error::other(format!("Invalid hash split: {}", s));
+    let expr = s.split_whitespace().collect::<String>();
+    let (modulus, threshold) = expr
+        .strip_prefix("game_hash%")
+        .and_then(|rest| rest.split_once('<'))
+        .ok_or_else(invalid)?;
+    let modulus = modulus.parse::<u64>().map_err(|_| invalid())?;
+    let threshold = threshold.parse().map_err(|_| invalid())?;
+
+    if modulus == 0 {
+        return Err(invalid());
+    }
+
+    Ok(SplitBy::Hash { modulus, threshold })
+}
+
+/// 64-bit FNV-1a over the raw record bytes, which unlike `DefaultHasher` is stable across
+/// machines and Rust releases.
+fn game_hash(game_buffer: &[u8]) -> u64 {
+    game_buffer.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl FromStr for SplitBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "outcome" {
+            return Ok(Self::Outcome);
+        }
+
+        match s.strip_prefix("length:") {
+            Some(buckets) => buckets
+                .split(',')
+                .map(parse_length_bucket)
+                .collect::<Result<Vec<_>>>()
+                .map(Self::Length),
+            None => Err(Error::other(format!("Unknown split key: {}", s))),
+        }
+    }
+}
+
+impl SplitBy {
+    fn bucket(&self, game: &Stoatpack, game_buffer: &[u8]) -> String {
+        match self {
+            Self::Outcome => match game.wdl {
+                Outcome::SenteLoss => "gote_win".to_string(),
+                wdl => outcome_name(wdl).to_string(),
+            },
+            Self::Length(buckets) => {
+                let plies = game.moves.len();
+
+                buckets
+                    .iter()
+                    .find(|(min, max)| plies >= *min && max.is_none_or(|max| plies <= max))
+                    .map_or("length_other".to_string(), |(min, max)| match max {
+                        Some(max) => format!("length_{}-{}", min, max),
+                        None => format!("length_{}+", min),
+                    })
+            }
+            Self::Hash { modulus, threshold } => {
+                if game_hash(game_buffer) % modulus < *threshold {
+                    "validation".to_string()
+                } else {
+                    "train".to_string()
+                }
+            }
+        }
+    }
+}
+
+fn parse_length_bucket(bucket: &str) -> Result<(usize, Option<usize>)> {
+    let invalid = || Error::other(format!("Invalid length bucket: {}", bucket));
+
+    if let Some(min) = bucket.strip_suffix('+') {
+        return Ok((min.parse().map_err(|_| invalid())?, None));
+    }
+
+    let (min, max) = bucket.split_once('-').ok_or_else(invalid)?;
+    let min = min.parse().map_err(|_| invalid())?;
+    let max = max.parse().map_err(|_| invalid())?;
+
+    if min > max {
+        return Err(invalid());
+    }
+
+    Ok((min, Some(max)))
+}
+
+pub struct Splitter {
+    by: SplitBy,
+    output_dir: PathBuf,
+    writers: BTreeMap<String, (BufWriter<File>, usize)>,
+}
+
+impl Splitter {
+    pub fn new(args: &SplitArgs) -> Result<Self> {
+        create_dir_all(&args.output_dir)?;
+
+        Ok(Self {
+            by: args.by.clone().or(args.by_hash.clone()).unwrap(),
+            output_dir: args.output_dir.clone(),
+            writers: BTreeMap::new(),
+        })
+    }
+
+    pub fn split(&mut self, path: &Path) -> Result<usize> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+        let len = file.metadata()?.len();
+        let mut records = 0;
+        let mut prev_pos = 0;
+
+        while reader.stream_position()? < len {
+            let game = Stoatpack::deserialise(&mut reader).at_offset(path, prev_pos)?;
+            let curr_pos = reader.stream_position()?;
+            let mut game_buffer = vec![0u8; (curr_pos - prev_pos) as usize];
+
+            reader.seek(SeekFrom::Start(prev_pos))?;
+            reader.read_exact(&mut game_buffer)?;
+
+            let bucket = self.by.bucket(&game, &game_buffer);
+
+            if !self.writers.contains_key(&bucket) {
+                let output = self.output_dir.join(format!("{}.spk", bucket));
+                self.writers
+                    .insert(bucket.clone(), (buffer::writer(File::create(output)?), 0));
+            }
+
+            let (writer, count) = self.writers.get_mut(&bucket).unwrap();
+            writer.write_all(&game_buffer)?;
+            *count += 1;
+
+            records += 1;
+            prev_pos = curr_pos;
+        }
+
+        println!("Split : {}, {} records", path.display(), records);
+
+        Ok(records)
+    }
+
+    pub fn finish(self) -> Result<Vec<(String, usize)>> {
+        let mut counts = Vec::new();
+
+        for (bucket, (mut writer, count)) in self.writers {
+            writer.flush()?;
+            counts.push((bucket, count));
+        }
+
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, stoatformat::shogi::position::Position};
+
+    const STARTPOS: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+    fn hash_split(modulus: u64, threshold: u64) -> SplitBy {
+        SplitBy::Hash { modulus, threshold }
+    }
+
+    #[test]
+    fn parses_hash_split() {
+        for s in [
+            "game_hash % 10 < 1",
+            "game_hash%10<1",
+            " game_hash %10 <  1 ",
+        ] {
+            assert!(matches!(
+                parse_hash_split(s),
+                Ok(SplitBy::Hash {
+                    modulus: 10,
+                    threshold: 1
+                })
+            ));
+        }
+
+        for s in [
+            "game_hash % 0 < 1",
+            "game_hash % 10",
+            "game_hash % -10 < 1",
+            "game_hash % 10 < x",
+            "hash % 10 < 1",
+            "game_hash < 1",
+        ] {
+            assert!(parse_hash_split(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn game_hash_is_fnv1a() {
+        assert_eq!(game_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(game_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(game_hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn hash_split_assigns_by_threshold() {
+        let game = Stoatpack {
+            startpos: Position::from_sfen(STARTPOS).unwrap(),
+            wdl: Outcome::Draw,
+            moves: Vec::new(),
+        };
+        let buffers = (0..10_000u32)
+            .map(|i| i.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let validation = |split: &SplitBy| {
+            buffers
+                .iter()
+                .filter(|buffer| split.bucket(&game, buffer) == "validation")
+                .count()
+        };
+
+        assert_eq!(validation(&hash_split(10, 0)), 0);
+        assert_eq!(validation(&hash_split(10, 10)), buffers.len());
+        assert!((2_700..3_300).contains(&validation(&hash_split(10, 3))));
+
+        // The FNV-1a hash of "foobar" is 68 modulo 100, so thresholds up to 68 leave it in train
+        assert_eq!(hash_split(100, 68).bucket(&game, b"foobar"), "train");
+        assert_eq!(hash_split(100, 69).bucket(&game, b"foobar"), "validation");
+    }
+}
@@ -0,0 +1,229 @@
+use {
+    crate::{
+        StatsDbAction, StatsDbArgs,
+        error::{Error, Result},
+    },
+    rusqlite::{Connection, params},
+    serde_json::Value,
+    std::{
+        fmt::Write as _,
+        fs::{File, read_to_string},
+        io::Write,
+        path::Path,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS snapshots (
+    id INTEGER PRIMARY KEY,
+    label TEXT NOT NULL,
+    recorded_at INTEGER NOT NULL,
+    source TEXT NOT NULL,
+    games INTEGER NOT NULL,
+    positions INTEGER NOT NULL,
+    black_wins INTEGER NOT NULL,
+    white_wins INTEGER NOT NULL,
+    draws INTEGER NOT NULL,
+    reverses INTEGER NOT NULL,
+    eval_labels TEXT NOT NULL,
+    eval_histogram TEXT NOT NULL
+);";
+
+struct Snapshot {
+    id: i64,
+    label: String,
+    recorded_at: i64,
+    games: i64,
+    positions: i64,
+    draws: i64,
+    reverses: i64,
+    eval_labels: Vec<String>,
+    eval_histogram: Vec<i64>,
+}
+
+impl Snapshot {
+    fn median_eval(&self) -> &str {
+        let total = self.eval_histogram.iter().sum::<i64>();
+        let mut seen = 0;
+
+        for (label, count) in self.eval_labels.iter().zip(&self.eval_histogram) {
+            seen += count;
+
+            if 2 * seen >= total {
+                return label;
+            }
+        }
+
+        "-"
+    }
+}
+
+pub fn run(args: &StatsDbArgs) -> Result<()> {
+    let connection = Connection::open(&args.db).map_err(Error::other)?;
+    connection.execute_batch(SCHEMA).map_err(Error::other)?;
+
+    match &args.action {
+        StatsDbAction::Append { runs, label } => {
+            for run in runs {
+                let label = label.clone().unwrap_or_else(|| {
+                    run.file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                });
+
+                append(&connection, run, &label)?;
+                println!("Append: {} as {}", run.display(), label);
+            }
+        }
+        StatsDbAction::Query { label, csv } => {
+            let snapshots = query(&connection, label.as_deref())?;
+
+            print_snapshots(&snapshots);
+
+            if let Some(path) = csv {
+                write_csv(path, &snapshots)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn append(connection: &Connection, run: &Path, label: &str) -> Result<()> {
+    let text = read_to_string(run)?;
+    let root = serde_json::from_str::<Value>(&text).map_err(Error::other)?;
+    // Accept both a count --json-out summary and a per-file sidecar
+    let count = root.get("count").unwrap_or(&root);
+    let field = |name: &str| {
+        count
+            .get(name)
+            .and_then(Value::as_i64)
+            .ok_or_else(|| Error::other(format!("{}: missing \"{}\"", run.display(), name)))
+    };
+    let recorded_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+
+    connection
+        .execute(
+            "INSERT INTO snapshots (label, recorded_at, source, games, positions, black_wins, white_wins, draws, reverses, eval_labels, eval_histogram)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                label,
+                recorded_at,
+                run.display().to_string(),
+                field("games")?,
+                field("positions")?,
+                field("black_wins")?,
+                field("white_wins")?,
+                field("draws")?,
+                field("reverses")?,
+                serde_json::to_string(&count["eval_labels"]).map_err(Error::other)?,
+                serde_json::to_string(&count["eval_histogram"]).map_err(Error::other)?,
+            ],
+        )
+        .map_err(Error::other)?;
+
+    Ok(())
+}
+
+fn query(connection: &Connection, label: Option<&str>) -> Result<Vec<Snapshot>> {
+    let mut statement = connection
+        .prepare(
+            "SELECT id, label, recorded_at, games, positions, draws, reverses, eval_labels, eval_histogram
+             FROM snapshots WHERE ?1 = '' OR label = ?1 ORDER BY recorded_at, id",
+        )
+        .map_err(Error::other)?;
+    let rows = statement
+        .query_map(params![label.unwrap_or_default()], |row| {
+            let eval_labels: String = row.get(7)?;
+            let eval_histogram: String = row.get(8)?;
+
+            Ok((
+                Snapshot {
+                    id: row.get(0)?,
+                    label: row.get(1)?,
+                    recorded_at: row.get(2)?,
+                    games: row.get(3)?,
+                    positions: row.get(4)?,
+                    draws: row.get(5)?,
+                    reverses: row.get(6)?,
+                    eval_labels: Vec::new(),
+                    eval_histogram: Vec::new(),
+                },
+                eval_labels,
+                eval_histogram,
+            ))
+        })
+        .map_err(Error::other)?;
+    let mut snapshots = Vec::new();
+
+    for row in rows {
+        let (mut snapshot, labels, histogram) = row.map_err(Error::other)?;
+        snapshot.eval_labels = serde_json::from_str(&labels).unwrap_or_default();
+        snapshot.eval_histogram = serde_json::from_str(&histogram).unwrap_or_default();
+        snapshots.push(snapshot);
+    }
+
+    Ok(snapshots)
+}
+
+fn print_snapshots(snapshots: &[Snapshot]) {
+    println!(
+        "{:>4}  {:<20} {:>12} {:>14} {:>8} {:>9} {:>8}",
+        "ID", "Label", "Games", "Positions", "Draws", "Reverses", "Median"
+    );
+
+    for snapshot in snapshots {
+        println!(
+            "{:>4}  {:<20} {:>12} {:>14} {:>7.2}% {:>8.2}% {:>8}",
+            snapshot.id,
+            snapshot.label,
+            snapshot.games,
+            snapshot.positions,
+            rate(snapshot.draws, snapshot.games),
+            rate(snapshot.reverses, snapshot.games),
+            snapshot.median_eval()
+        );
+    }
+}
+
+fn write_csv(path: &Path, snapshots: &[Snapshot]) -> Result<()> {
+    let mut csv = String::from("id,label,recorded_at,games,positions,draw_rate,reverse_rate");
+
+    if let Some(first) = snapshots.first() {
+        for label in &first.eval_labels {
+            write!(csv, ",eval_{}", label).unwrap();
+        }
+    }
+
+    csv.push('\n');
+
+    for snapshot in snapshots {
+        write!(
+            csv,
+            "{},{},{},{},{},{:.4},{:.4}",
+            snapshot.id,
+            snapshot.label,
+            snapshot.recorded_at,
+            snapshot.games,
+            snapshot.positions,
+            rate(snapshot.draws, snapshot.games),
+            rate(snapshot.reverses, snapshot.games)
+        )
+        .unwrap();
+
+        for count in &snapshot.eval_histogram {
+            write!(csv, ",{}", count).unwrap();
+        }
+
+        csv.push('\n');
+    }
+
+    Ok(File::create(path)?.write_all(csv.as_bytes())?)
+}
+
+fn rate(value: i64, total: i64) -> f64 {
+    value as f64 / total as f64 * 100.0f64
+}
@@ -0,0 +1,72 @@
+use {
+    crate::error::{Error, Result},
+    std::{fs::File, path::Path, process, str::FromStr},
+};
+
+#[derive(Clone, Debug)]
+pub enum UploadTarget {
+    /// Copied with the `aws` CLI so credentials and regions come from its usual configuration
+    S3(String),
+    Http(String),
+}
+
+impl FromStr for UploadTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let prefix = s.trim_end_matches('/').to_string();
+
+        if s.starts_with("s3://") {
+            Ok(Self::S3(prefix))
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Self::Http(prefix))
+        } else {
+            Err(Error::other(format!(
+                "Invalid upload target: {}, expected s3://bucket/prefix or an http(s) URL",
+                s
+            )))
+        }
+    }
+}
+
+impl UploadTarget {
+    pub fn upload(&self, path: &Path) -> Result<()> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| Error::other(format!("Not a file: {}", path.display())))?
+            .to_string_lossy();
+
+        match self {
+            Self::S3(prefix) => {
+                let url = format!("{}/{}", prefix, name);
+                let status = process::Command::new("aws")
+                    .args(["s3", "cp", "--only-show-errors"])
+                    .arg(path)
+                    .arg(&url)
+                    .status()?;
+
+                if !status.success() {
+                    return Err(Error::other(format!(
+                        "Upload failed: {} -> {}, aws exited with {}",
+                        path.display(),
+                        url,
+                        status
+                    )));
+                }
+
+                println!("Upload: {} -> {}", path.display(), url);
+            }
+            Self::Http(prefix) => {
+                let url = format!("{}/{}", prefix, name);
+
+                ureq::put(&url)
+                    .send(File::open(path)?)
+                    .map_err(Error::other)?;
+
+                println!("Upload: {} -> {}", path.display(), url);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+use {
+    crate::{
+        VerifyArgs, buffer,
+        checksum::ChecksumReader,
+        error::{Context, Error, Result},
+        features::opponent,
+        progress, replace_file,
+    },
+    clap::ValueEnum,
+    std::{collections::HashMap, fs::OpenOptions, io::Seek, path::Path},
+    stoatformat::{
+        shogi::{
+            core::{Color, Move, PieceType},
+            position::Position,
+        },
+        stoatpack::Stoatpack,
+    },
+};
+
+/// Shogi rules that engines sometimes get wrong during datagen, checked on top of move legality.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Rule {
+    /// A pawn dropped onto a file that already holds one of the same side's pawns
+    Nifu,
+    /// A pawn drop that gives checkmate
+    Uchifuzume,
+    /// A fourfold repetition in which one side gave check on every move
+    PerpetualCheck,
+}
+
+impl Rule {
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::Nifu => "nifu",
+            Rule::Uchifuzume => "uchifuzume",
+            Rule::PerpetualCheck => "perpetual check",
+        }
+    }
+}
+
+/// (file, rank) offsets, with rank pointing towards the opponent
+type Offsets = &'static [(i32, i32)];
+
+const ORTHOGONALS: Offsets = &[(0, 1), (-1, 0), (1, 0), (0, -1)];
+const DIAGONALS: Offsets = &[(-1, 1), (1, 1), (-1, -1), (1, -1)];
+const GOLD_STEPS: Offsets = &[(-1, 1), (0, 1), (1, 1), (-1, 0), (1, 0), (0, -1)];
+const KING_STEPS: Offsets = &[
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+    (-1, 0),
+    (1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// Single steps and sliding directions per piece type
+const PIECE_MOVES: [(PieceType, Offsets, Offsets); 14] = [
+    (PieceType::PAWN, &[(0, 1)], &[]),
+    (PieceType::LANCE, &[], &[(0, 1)]),
+    (PieceType::KNIGHT, &[(-1, 2), (1, 2)], &[]),
+    (
+        PieceType::SILVER,
+        &[(-1, 1), (0, 1), (1, 1), (-1, -1), (1, -1)],
+        &[],
+    ),
+    (PieceType::GOLD, GOLD_STEPS, &[]),
+    (PieceType::BISHOP, &[], DIAGONALS),
+    (PieceType::ROOK, &[], ORTHOGONALS),
+    (PieceType::PROMOTED_PAWN, GOLD_STEPS, &[]),
+    (PieceType::PROMOTED_LANCE, GOLD_STEPS, &[]),
+    (PieceType::PROMOTED_KNIGHT, GOLD_STEPS, &[]),
+    (PieceType::PROMOTED_SILVER, GOLD_STEPS, &[]),
+    (PieceType::PROMOTED_BISHOP, ORTHOGONALS, DIAGONALS),
+    (PieceType::PROMOTED_ROOK, DIAGONALS, ORTHOGONALS),
+    (PieceType::KING, KING_STEPS, &[]),
+];
+
+/// Returns the record count, the number of games with an illegal move and, for each of
+/// `args.rules`, the number of games breaking that rule.
+pub fn verify(path: &Path, args: &VerifyArgs) -> Result<(usize, usize, Vec<usize>)> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    let mut reader = buffer::reader(ChecksumReader::new(&file, path)?);
+    let len = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    let mut records = 0;
+    let mut illegal_games = 0;
+    let mut rule_games = vec![0; args.rules.len()];
+
+    while reader.stream_position()? < len && args.common.within_limit(records) {
+        let offset = reader.stream_position()?;
+        let mut game = Stoatpack::deserialise(&mut reader).at_offset(path, offset)?;
+        progress::record(path);
+
+        if args.common.skipped(records) {
+            records += 1;
+            continue;
+        }
+
+        for (rule, ply) in rule_violations(&game, &args.rules) {
+            println!(
+                "Rule   : {}, game {}, ply {}, move {}, {}",
+                path.display(),
+                records,
+                ply,
+                game.moves[ply].0,
+                rule.name()
+            );
+
+            rule_games[args.rules.iter().position(|&r| r == rule).unwrap()] += 1;
+        }
+
+        if let Some(ply) = first_illegal_move(&game) {
+            let illegal = Error::InvalidMove {
+                path: path.to_path_buf(),
+                game: records,
+                ply,
+                mv: game.moves[ply].0.to_string(),
+            };
+            println!("Illegal: {}", illegal);
+
+            game.moves.truncate(ply);
+            illegal_games += 1;
+        }
+
+        if args.repair {
+            game.serialise(&mut buffer)?;
+        }
+
+        records += 1;
+    }
+
+    if illegal_games == 0 && rule_games.iter().all(|&games| games == 0) {
+        println!("  OK  : {}, {} records", path.display(), records);
+    } else if args.repair && illegal_games > 0 {
+        replace_file(path, &buffer)?;
+
+        println!(
+            "Repair: {}, {} records, {} games truncated",
+            path.display(),
+            records,
+            illegal_games
+        );
+    }
+
+    Ok((records, illegal_games, rule_games))
+}
+
+fn first_illegal_move(game: &Stoatpack) -> Option<usize> {
+    let mut pos = game.startpos.clone();
+
+    for (ply, (mv, _)) in game.moves.iter().enumerate() {
+        if !pos.is_legal(*mv) {
+            return Some(ply);
+        }
+
+        pos = pos.apply_move(*mv);
+    }
+
+    None
+}
+
+/// Finds the first ply breaking each of `rules`, following the game up to its first illegal move.
+fn rule_violations(game: &Stoatpack, rules: &[Rule]) -> Vec<(Rule, usize)> {
+    let mut violations = Vec::new();
+    let mut pos = game.startpos.clone();
+    let mut seen = HashMap::new();
+    // Whether the side to move is in check, for every position so far
+    let mut checks = vec![rules.contains(&Rule::PerpetualCheck) && in_check(&pos)];
+
+    seen.insert(pos.key(), vec![0]);
+
+    for (ply, (mv, _)) in game.moves.iter().enumerate() {
+        let next = pos.apply_move(*mv);
+        let mut broken = |rule| {
+            if rules.contains(&rule) && violations.iter().all(|&(other, _)| other != rule) {
+                violations.push((rule, ply));
+            }
+        };
+
+        if is_pawn_drop(*mv) {
+            if has_doubled_pawns(&next, pos.stm()) {
+                broken(Rule::Nifu);
+            }
+
+            if in_check(&next) && next.legal_moves().is_empty() {
+                broken(Rule::Uchifuzume);
+            }
+        }
+
+        if rules.contains(&Rule::PerpetualCheck) {
+            checks.push(in_check(&next));
+
+            let index = ply + 1;
+            let occurrences = seen.entry(next.key()).or_insert_with(Vec::new);
+            occurrences.push(index);
+
+            // Both sides' moves alternate between repetitions, so positions reached by the
+            // same side share the parity of `index`
+            if occurrences.len() == 4 {
+                let first = occurrences[0];
+                let perpetual = [index % 2, (index + 1) % 2].into_iter().any(|parity| {
+                    (first + 1..=index)
+                        .filter(|i| i % 2 == parity)
+                        .all(|i| checks[i])
+                });
+
+                if perpetual {
+                    broken(Rule::PerpetualCheck);
+                }
+            }
+        }
+
+        if !pos.is_legal(*mv) {
+            break;
+        }
+
+        pos = next;
+    }
+
+    violations
+}
+
+/// Finds the first drop onto a file already holding one of the mover's pawns, or onto a rank
+/// from which the dropped piece could never move. Only drops are examined, so this is much
+/// cheaper than a full legality check.
+pub fn illegal_drop(game: &Stoatpack) -> Option<usize> {
+    let mut pos = game.startpos.clone();
+
+    for (ply, (mv, _)) in game.moves.iter().enumerate() {
+        let next = pos.apply_move(*mv);
+
+        if let Some((piece, rank)) = parse_drop(*mv) {
+            // Ranks counted from the mover's far side, 'a' being sente's
+            let depth = if pos.stm() == Color::SENTE {
+                rank as u8 - b'a'
+            } else {
+                b'i' - rank as u8
+            };
+            let dead = match piece {
+                'P' | 'L' => depth == 0,
+                'N' => depth < 2,
+                _ => false,
+            };
+
+            if dead || (piece == 'P' && has_doubled_pawns(&next, pos.stm())) {
+                return Some(ply);
+            }
+        }
+
+        pos = next;
+    }
+
+    None
+}
+
+/// The piece letter and target rank of a drop, read from its USI notation such as `P*5e`
+fn parse_drop(mv: Move) -> Option<(char, char)> {
+    let usi = mv.to_string();
+    let mut chars = usi.chars();
+    let (piece, '*', _, rank) = (chars.next()?, chars.next()?, chars.next()?, chars.next()?) else {
+        return None;
+    };
+
+    Some((piece, rank))
+}
+
+fn is_pawn_drop(mv: Move) -> bool {
+    parse_drop(mv).is_some_and(|(piece, _)| piece == 'P')
+}
+
+fn has_doubled_pawns(pos: &Position, color: Color) -> bool {
+    let mut files = [false; 9];
+
+    pos.piece_bb(PieceType::PAWN.with_color(color))
+        .any(|square| std::mem::replace(&mut files[square.idx() % 9], true))
+}
+
+fn in_check(pos: &Position) -> bool {
+    pos.piece_bb(PieceType::KING.with_color(pos.stm()))
+        .lsb()
+        .is_some_and(|king| is_attacked(pos, king.idx(), opponent(pos.stm())))
+}
+
+fn is_attacked(pos: &Position, target: usize, by: Color) -> bool {
+    let mut occupied = [false; 81];
+
+    for color in [Color::SENTE, Color::GOTE] {
+        for &(piece_type, _, _) in &PIECE_MOVES {
+            for square in pos.piece_bb(piece_type.with_color(color)) {
+                occupied[square.idx()] = true;
+            }
+        }
+    }
+
+    let forward = if by == Color::SENTE { 1 } else { -1 };
+    let on_board = |file: i32, rank: i32| (0..9).contains(&file) && (0..9).contains(&rank);
+
+    for &(piece_type, steps, slides) in &PIECE_MOVES {
+        for square in pos.piece_bb(piece_type.with_color(by)) {
+            let (file, rank) = ((square.idx() % 9) as i32, (square.idx() / 9) as i32);
+
+            for &(df, dr) in steps {
+                let (file, rank) = (file + df, rank + dr * forward);
+
+                if on_board(file, rank) && (rank * 9 + file) as usize == target {
+                    return true;
+                }
+            }
+
+            for &(df, dr) in slides {
+                let (mut file, mut rank) = (file + df, rank + dr * forward);
+
+                while on_board(file, rank) {
+                    let idx = (rank * 9 + file) as usize;
+
+                    if idx == target {
+                        return true;
+                    }
+
+                    if occupied[idx] {
+                        break;
+                    }
+
+                    file += df;
+                    rank += dr * forward;
+                }
+            }
+        }
+    }
+
+    false
+}